@@ -0,0 +1,57 @@
+// embeds `templates/` and `public/` so the binary serves the ui from any directory
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// every file under `dir`, sorted so the generated code is stable
+fn walk(dir: &Path, out: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(n) => n,
+        Err(_) => {return;},
+    };
+
+    let mut paths: Vec<PathBuf> = entries.filter_map(|n| n.ok()).map(|n| n.path()).collect();
+    paths.sort();
+
+    for path in paths {
+        if path.is_dir() {
+            walk(&path, out);
+        }
+        else {
+            out.push(path);
+        }
+    }
+}
+
+/// `pub const <name>: &[(&str, &[u8])]` holding every file under `dir`, keyed by its relative path
+fn table(name: &str, dir: &Path) -> String {
+    println!("cargo:rerun-if-changed={}", dir.display());
+
+    let mut files: Vec<PathBuf> = vec![];
+    walk(dir, &mut files);
+
+    let mut code = format!("pub const {}: &[(&str, &[u8])] = &[\n", name);
+    for path in files {
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        let key = path.strip_prefix(dir).unwrap().components()
+            .map(|n| n.as_os_str().to_string_lossy().to_string())
+            .collect::<Vec<String>>()
+            .join("/");
+        code += &format!("    ({:?}, include_bytes!({:?})),\n", key, path.display().to_string());
+    }
+    code += "];\n";
+
+    return code;
+}
+
+fn main() {
+    let root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("embedded.rs");
+
+    let mut file = fs::File::create(&out).expect("could not create embedded.rs");
+    file.write_all(table("TEMPLATES", &root.join("templates")).as_bytes()).unwrap();
+    file.write_all(table("PUBLIC", &root.join("public")).as_bytes()).unwrap();
+}
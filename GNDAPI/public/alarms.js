@@ -0,0 +1,167 @@
+// alarm banner shared by the pages, include it with <script src="/static/alarms.js"></script>
+//
+// polls /api/alarms and shows every latched alarm at the top of the page. unacknowledged
+// alarms beep, fast for red and slow for yellow, once sound has been turned on
+(function () {
+    const POLL_MS = 1000;
+    // ms between beeps for each severity
+    const BEEP_EVERY = {red: 1000, yellow: 4000};
+    const SOUND_KEY = "gndapi-alarm-sound";
+
+    const style = document.createElement("style");
+    style.textContent = `
+        #alarms { font-family: sans-serif; font-size: 14px; }
+        #alarms .alarm { display: flex; align-items: center; gap: 12px; padding: 6px 16px; color: white; }
+        #alarms .alarm.red { background: #b00; }
+        #alarms .alarm.yellow { background: #a67c00; }
+        #alarms .alarm.acked { opacity: 0.6; }
+        #alarms .alarm.unacked.red { animation: alarm-flash 1s steps(1) infinite; }
+        #alarms .alarm .name { font-weight: bold; text-transform: uppercase; }
+        #alarms .alarm .detail { flex: 1; }
+        #alarms .bar { display: flex; gap: 8px; justify-content: flex-end; padding: 4px 16px; background: #1b1b1b; }
+        #alarms button { background: #222; color: #ddd; border: 1px solid #444; padding: 2px 8px; }
+        #alarms .error { color: #e57373; }
+        @keyframes alarm-flash { 50% { background: #600; } }
+    `;
+    document.head.appendChild(style);
+
+    const banner = document.createElement("div");
+    banner.id = "alarms";
+    document.body.insertBefore(banner, document.body.firstChild);
+
+    let latched = [];
+    let audio = null;
+    let sound = localStorage.getItem(SOUND_KEY) == "on";
+    let lastBeep = 0;
+    let message = "";
+
+    // browsers only start audio from a click, so a sound setting kept from before waits for one
+    function unlock() {
+        if (!audio) {
+            audio = new AudioContext();
+        }
+        audio.resume();
+    }
+    document.addEventListener("click", unlock);
+
+    function beep(severity) {
+        if (!audio || audio.state != "running") {
+            return;
+        }
+        const count = severity == "red" ? 3 : 1;
+        const pitch = severity == "red" ? 880 : 660;
+        for (let i = 0; i < count; i++) {
+            const osc = audio.createOscillator();
+            const gain = audio.createGain();
+            const start = audio.currentTime + i * 0.2;
+            osc.frequency.value = pitch;
+            gain.gain.setValueAtTime(0.2, start);
+            gain.gain.setValueAtTime(0, start + 0.12);
+            osc.connect(gain).connect(audio.destination);
+            osc.start(start);
+            osc.stop(start + 0.15);
+        }
+    }
+
+    function worst(alarms) {
+        if (alarms.some(n => n.severity == "red")) {
+            return "red";
+        }
+        return alarms.length > 0 ? "yellow" : null;
+    }
+
+    function sounding() {
+        if (!sound) {
+            return;
+        }
+        const severity = worst(latched.filter(n => !n.acked));
+        if (severity && Date.now() - lastBeep >= BEEP_EVERY[severity]) {
+            lastBeep = Date.now();
+            beep(severity);
+        }
+    }
+
+    async function ack(url) {
+        const response = await fetch(url, {method: "POST"});
+        if (response.status == 401 || response.status == 403) {
+            message = "acknowledging alarms needs an operator token";
+        }
+        else if (!response.ok) {
+            message = (await response.json()).error || "could not acknowledge";
+        }
+        else {
+            message = "";
+        }
+        poll();
+    }
+
+    function button(text, onclick) {
+        const b = document.createElement("button");
+        b.textContent = text;
+        b.addEventListener("click", onclick);
+        return b;
+    }
+
+    function render() {
+        banner.innerHTML = "";
+        if (latched.length == 0) {
+            return;
+        }
+
+        for (const alarm of latched.slice().reverse()) {
+            const row = document.createElement("div");
+            row.className = "alarm " + alarm.severity + (alarm.acked ? " acked" : " unacked");
+
+            const name = document.createElement("span");
+            name.className = "name";
+            name.textContent = alarm.severity + " " + alarm.name;
+
+            const detail = document.createElement("span");
+            detail.className = "detail";
+            let text = alarm.channel + " " + alarm.value.toFixed(2) + " past " + alarm.limit + " since " + new Date(alarm.raised * 1000).toLocaleTimeString();
+            if (!alarm.active) {
+                text += ", back in limits";
+            }
+            if (alarm.acked) {
+                text += ", acknowledged by " + alarm.acked.by;
+            }
+            detail.textContent = text;
+
+            row.append(name, detail);
+            if (!alarm.acked) {
+                row.appendChild(button("acknowledge", () => ack("/api/alarms/" + alarm.id + "/ack")));
+            }
+            banner.appendChild(row);
+        }
+
+        const bar = document.createElement("div");
+        bar.className = "bar";
+        const error = document.createElement("span");
+        error.className = "error";
+        error.textContent = message;
+        bar.appendChild(error);
+        bar.appendChild(button(sound ? "mute" : "sound on", function () {
+            sound = !sound;
+            unlock();
+            localStorage.setItem(SOUND_KEY, sound ? "on" : "off");
+            render();
+        }));
+        if (latched.some(n => !n.acked)) {
+            bar.appendChild(button("acknowledge all", () => ack("/api/alarms/ack")));
+        }
+        banner.appendChild(bar);
+    }
+
+    async function poll() {
+        const response = await fetch("/api/alarms");
+        if (!response.ok) {
+            return;
+        }
+        latched = await response.json();
+        render();
+        sounding();
+    }
+
+    poll();
+    setInterval(poll, POLL_MS);
+})();
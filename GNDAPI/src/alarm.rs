@@ -0,0 +1,490 @@
+use crate::api::CHANNELS;
+use crate::event::EventKind;
+use crate::protocol::Frame;
+
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeSet;
+
+/// channels computed by the ground station that limits can watch besides the ones in `CHANNELS`
+pub const DERIVED: [(&str, &str); 4] = [
+    ("descent", "descent rate, m/s, the negated vertical velocity"),
+    ("quat_norm", "norm of the attitude quaternion, 1 while the IMU is healthy"),
+    ("alt_divergence", "difference between fused and barometric altitude, m"),
+    ("link_age", "seconds since the last frame, checked even while nothing arrives"),
+];
+
+/// how bad an alarm is, ordered from least to most
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Yellow,
+    Red,
+}
+
+/// range a value has to stay in, either side can be left open
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Band {
+    pub low: Option<f64>,
+    pub high: Option<f64>,
+}
+
+impl Band {
+    pub fn high(high: f64) -> Band {
+        Band {low: None, high: Some(high)}
+    }
+
+    pub fn between(low: f64, high: f64) -> Band {
+        Band {low: Some(low), high: Some(high)}
+    }
+
+    /// the bound `value` is past, none while it is in range
+    pub fn breached(&self, value: f64) -> Option<f64> {
+        match (self.low, self.high) {
+            (Some(low), _) if value < low => Some(low),
+            (_, Some(high)) if value > high => Some(high),
+            _ => None,
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.low.is_none() && self.high.is_none()
+    }
+}
+
+/// yellow and red limits on one channel, e.g.
+/// `{ name = "link lost", channel = "link_age", yellow = { high = 1 }, red = { high = 3 } }`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limit {
+    /// shown in the alarm banner, unique
+    pub name: String,
+    /// a channel from `/api/channels` or one of `DERIVED`
+    pub channel: String,
+    #[serde(default)]
+    pub yellow: Band,
+    #[serde(default)]
+    pub red: Band,
+    /// only checked while the fused altitude is under this, m
+    #[serde(default)]
+    pub below_alt: Option<f64>,
+    /// only checked once this flight event has been detected, see `/api/events`
+    #[serde(default)]
+    pub after: Option<EventKind>,
+}
+
+impl Limit {
+    /// the worst band `value` is out of, with the bound it crossed
+    pub fn check(&self, value: f64) -> Option<(Severity, f64)> {
+        if let Some(n) = self.red.breached(value) {
+            return Some((Severity::Red, n));
+        }
+        self.yellow.breached(value).map(|n| (Severity::Yellow, n))
+    }
+}
+
+/// the `[alarms]` table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlarmConfig {
+    pub limits: Vec<Limit>,
+}
+
+impl Default for AlarmConfig {
+    fn default() -> AlarmConfig {
+        AlarmConfig {
+            limits: vec![
+                Limit {
+                    name: String::from("descent rate under main"),
+                    channel: String::from("descent"),
+                    yellow: Band::high(9.0),
+                    red: Band::high(15.0),
+                    below_alt: None,
+                    after: Some(EventKind::Main),
+                },
+                Limit {
+                    name: String::from("link lost"),
+                    channel: String::from("link_age"),
+                    yellow: Band::high(1.0),
+                    red: Band::high(3.0),
+                    below_alt: None,
+                    after: None,
+                },
+                Limit {
+                    name: String::from("quaternion norm"),
+                    channel: String::from("quat_norm"),
+                    yellow: Band::between(0.98, 1.02),
+                    red: Band::between(0.9, 1.1),
+                    below_alt: None,
+                    after: None,
+                },
+                Limit {
+                    name: String::from("altitude divergence"),
+                    channel: String::from("alt_divergence"),
+                    yellow: Band::high(15.0),
+                    red: Band::high(40.0),
+                    below_alt: None,
+                    after: None,
+                },
+            ],
+        }
+    }
+}
+
+impl AlarmConfig {
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors: Vec<String> = vec![];
+        let mut names: BTreeSet<&str> = BTreeSet::new();
+
+        for limit in self.limits.iter() {
+            let name = &limit.name;
+            if name.trim().is_empty() {
+                errors.push(format!("limits: the limit on {} has no name", limit.channel));
+            }
+            else if !names.insert(name) {
+                errors.push(format!("limits: {} is used twice", name));
+            }
+
+            let known = CHANNELS.iter().any(|n| n.name == limit.channel) || DERIVED.iter().any(|n| n.0 == limit.channel);
+            if !known {
+                errors.push(format!("limits: {} watches unknown channel \"{}\"", name, limit.channel));
+            }
+
+            if limit.yellow.is_open() && limit.red.is_open() {
+                errors.push(format!("limits: {} has neither yellow nor red bounds", name));
+            }
+            for (color, band) in [("yellow", limit.yellow), ("red", limit.red)] {
+                let bounds = [band.low, band.high];
+                if bounds.iter().flatten().any(|n| !n.is_finite()) {
+                    errors.push(format!("limits: {} {} bounds must be numbers", name, color));
+                }
+                if let (Some(low), Some(high)) = (band.low, band.high) {
+                    if !(low < high) {
+                        errors.push(format!("limits: {} {} low ({}) must be under high ({})", name, color, low, high));
+                    }
+                }
+            }
+            if limit.below_alt.map(|n| !n.is_finite()).unwrap_or(false) {
+                errors.push(format!("limits: {} below_alt must be a number", name));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(errors)
+        }
+    }
+}
+
+/// who acknowledged an alarm and when
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ack {
+    pub by: String,
+    /// UTC seconds
+    pub at: f64,
+}
+
+/// an alarm raised by a limit, latched until it is acknowledged and back in limits
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alarm {
+    pub id: u64,
+    /// the limit that raised it
+    pub name: String,
+    pub channel: String,
+    /// worst severity since it was raised, escalating clears the acknowledgement
+    pub severity: Severity,
+    /// the channel is still out of limits
+    pub active: bool,
+    /// last value out of limits, and the bound it crossed
+    pub value: f64,
+    pub limit: f64,
+    /// UTC seconds
+    pub raised: f64,
+    pub cleared: Option<f64>,
+    pub acked: Option<Ack>,
+}
+
+impl Alarm {
+    /// still shown in the banner
+    pub fn latched(&self) -> bool {
+        self.active || self.acked.is_none()
+    }
+}
+
+/// value of a limit's channel in a frame, derived channels included
+fn value(frame: &Frame, channel: &str) -> Option<f64> {
+    let value = match channel {
+        "descent" => -frame.vel as f64,
+        "quat_norm" => [frame.w, frame.x, frame.y, frame.z].iter().map(|n| (*n as f64).powi(2)).sum::<f64>().sqrt(),
+        "alt_divergence" => (frame.alt as f64 - frame.balt as f64).abs(),
+        n => frame.field(n)? as f64,
+    };
+
+    Some(value).filter(|n| n.is_finite())
+}
+
+/// checks every limit against each frame and the link age, keeping every alarm raised this session
+#[derive(Default)]
+pub struct Alarms {
+    pub limits: Vec<Limit>,
+    /// oldest first
+    pub history: Vec<Alarm>,
+    /// fused altitude of the last frame, for `Limit::below_alt`
+    alt: Option<f64>,
+    /// flight events detected so far, for `Limit::after`
+    events: Vec<EventKind>,
+}
+
+impl Alarms {
+    pub fn new(config: &AlarmConfig) -> Alarms {
+        Alarms {
+            limits: config.limits.clone(),
+            ..Alarms::default()
+        }
+    }
+
+    /// arms the limits waiting for a flight event
+    pub fn event(&mut self, kind: EventKind) {
+        if !self.events.contains(&kind) {
+            self.events.push(kind);
+        }
+    }
+
+    /// checks the limits against a frame, if there is one, and the age of the link
+    ///
+    /// limits whose channel has no value are left as they are
+    ///
+    /// ## Returns
+    /// the alarms that were raised, escalated or cleared
+    pub fn check(&mut self, frame: Option<&Frame>, link_age: Option<f64>, now: f64) -> Vec<Alarm> {
+        if let Some(frame) = frame {
+            self.alt = Some(frame.alt as f64).filter(|n| n.is_finite());
+        }
+
+        let mut changed: Vec<Alarm> = vec![];
+        for i in 0..self.limits.len() {
+            let limit = &self.limits[i];
+            let value = match limit.channel.as_str() {
+                "link_age" => link_age,
+                n => frame.and_then(|f| value(f, n)),
+            };
+            let value = match value {
+                Some(n) => n,
+                None => {continue;},
+            };
+
+            let below = match (limit.below_alt, self.alt) {
+                (Some(below), Some(alt)) => alt < below,
+                (Some(_), None) => false,
+                (None, _) => true,
+            };
+            let armed = below && limit.after.map(|n| self.events.contains(&n)).unwrap_or(true);
+            let breach = if armed {limit.check(value)} else {None};
+
+            if let Some(n) = self.update_limit(i, breach, value, now) {
+                changed.push(n);
+            }
+        }
+
+        return changed;
+    }
+
+    /// applies one check of a limit to its latched alarm, returns the alarm if it changed
+    fn update_limit(&mut self, index: usize, breach: Option<(Severity, f64)>, value: f64, now: f64) -> Option<Alarm> {
+        let limit = &self.limits[index];
+        let latched = self.history.iter_mut().rev().find(|n| n.name == limit.name && n.latched());
+
+        match (latched, breach) {
+            (Some(alarm), Some((severity, bound))) => {
+                let changed = !alarm.active || severity > alarm.severity;
+                alarm.active = true;
+                alarm.cleared = None;
+                alarm.value = value;
+                if severity >= alarm.severity {
+                    alarm.limit = bound;
+                }
+                if severity > alarm.severity {
+                    alarm.severity = severity;
+                    alarm.acked = None;
+                }
+                if changed {Some(alarm.clone())} else {None}
+            },
+            (Some(alarm), None) if alarm.active => {
+                alarm.active = false;
+                alarm.cleared = Some(now);
+                Some(alarm.clone())
+            },
+            (None, Some((severity, bound))) => {
+                let alarm = Alarm {
+                    id: self.history.last().map(|n| n.id + 1).unwrap_or(1),
+                    name: limit.name.clone(),
+                    channel: limit.channel.clone(),
+                    severity,
+                    active: true,
+                    value,
+                    limit: bound,
+                    raised: now,
+                    cleared: None,
+                    acked: None,
+                };
+                self.history.push(alarm.clone());
+                Some(alarm)
+            },
+            _ => None,
+        }
+    }
+
+    /// alarms still shown in the banner, oldest first
+    pub fn latched(&self) -> Vec<Alarm> {
+        self.history.iter().filter(|n| n.latched()).cloned().collect()
+    }
+
+    pub fn ack(&mut self, id: u64, by: &str, now: f64) -> Result<Alarm, String> {
+        let alarm = match self.history.iter_mut().find(|n| n.id == id) {
+            Some(n) => n,
+            None => {return Err(format!("no alarm with id {}", id))},
+        };
+
+        if alarm.acked.is_none() {
+            alarm.acked = Some(Ack {by: by.to_string(), at: now});
+        }
+        Ok(alarm.clone())
+    }
+
+    /// acknowledges every alarm that is waiting for it, returns them
+    pub fn ack_all(&mut self, by: &str, now: f64) -> Vec<Alarm> {
+        let mut acked: Vec<Alarm> = vec![];
+        for alarm in self.history.iter_mut().filter(|n| n.acked.is_none()) {
+            alarm.acked = Some(Ack {by: by.to_string(), at: now});
+            acked.push(alarm.clone());
+        }
+        return acked;
+    }
+
+    /// adds or replaces an alarm by id, e.g. from a session log
+    pub fn update(&mut self, alarm: Alarm) {
+        match self.history.iter_mut().find(|n| n.id == alarm.id) {
+            Some(n) => *n = alarm,
+            None => self.history.push(alarm),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alarm::{AlarmConfig, Alarms, Band, Limit, Severity};
+    use crate::event::EventKind;
+    use crate::protocol::Frame;
+
+    fn frame(alt: f32, vel: f32) -> Frame {
+        Frame {time: 0f32, alt, balt: alt, vel, w: 1f32, x: 0f32, y: 0f32, z: 0f32, gps: None}
+    }
+
+    fn alarms() -> Alarms {
+        Alarms::new(&AlarmConfig::default())
+    }
+
+    #[test]
+    fn defaults_valid() {
+        assert!(AlarmConfig::default().validate().is_ok());
+
+        let config = AlarmConfig {limits: vec![
+            Limit {name: String::from("a"), channel: String::from("speed"), yellow: Band::default(), red: Band::between(2.0, 1.0), below_alt: None, after: None},
+            Limit {name: String::from("a"), channel: String::from("alt"), yellow: Band::high(1.0), red: Band::default(), below_alt: None, after: None},
+        ]};
+        let errors = config.validate().unwrap_err();
+        assert!(errors.iter().any(|n| n.contains("unknown channel \"speed\"")));
+        assert!(errors.iter().any(|n| n.contains("red low (2) must be under high (1)")));
+        assert!(errors.iter().any(|n| n.contains("a is used twice")));
+    }
+
+    #[test]
+    fn latch_until_acked() {
+        let mut alarms = alarms();
+
+        // descending fast under the drogue, the main has not opened yet
+        assert!(alarms.check(Some(&frame(250.0, -30.0)), Some(0.0), 0.0).is_empty());
+        alarms.event(EventKind::Drogue);
+        assert!(alarms.check(Some(&frame(250.0, -30.0)), Some(0.0), 0.5).is_empty());
+
+        alarms.event(EventKind::Main);
+
+        let raised = alarms.check(Some(&frame(250.0, -10.0)), Some(0.0), 1.0);
+        assert_eq!(raised.len(), 1);
+        assert_eq!(raised[0].name, "descent rate under main");
+        assert_eq!(raised[0].severity, Severity::Yellow);
+        assert_eq!(raised[0].limit, 9.0);
+
+        // the same severity again is not a change
+        assert!(alarms.check(Some(&frame(240.0, -11.0)), Some(0.0), 2.0).is_empty());
+
+        let escalated = alarms.check(Some(&frame(200.0, -20.0)), Some(0.0), 3.0);
+        assert_eq!(escalated[0].severity, Severity::Red);
+        assert_eq!(escalated[0].id, raised[0].id);
+
+        // back in limits, latched until acknowledged
+        let cleared = alarms.check(Some(&frame(150.0, -5.0)), Some(0.0), 4.0);
+        assert!(!cleared[0].active);
+        assert_eq!(cleared[0].severity, Severity::Red);
+        assert_eq!(alarms.latched().len(), 1);
+
+        let acked = alarms.ack(raised[0].id, "rso", 5.0).unwrap();
+        assert_eq!(acked.acked.unwrap().by, "rso");
+        assert!(alarms.latched().is_empty());
+        assert!(alarms.ack(99, "rso", 5.0).is_err());
+
+        // tripping again raises a new alarm
+        let again = alarms.check(Some(&frame(100.0, -16.0)), Some(0.0), 6.0);
+        assert_eq!(again[0].id, raised[0].id + 1);
+    }
+
+    #[test]
+    fn acked_while_active() {
+        let mut alarms = alarms();
+
+        // a quaternion that is not normalised
+        let mut bad = frame(0.0, 0.0);
+        bad.x = 0.3;
+        let raised = alarms.check(Some(&bad), Some(0.0), 0.0);
+        assert_eq!(raised[0].name, "quaternion norm");
+        assert_eq!(raised[0].severity, Severity::Yellow);
+
+        assert_eq!(alarms.ack_all("rso", 1.0).len(), 1);
+        assert!(alarms.ack_all("rso", 1.0).is_empty());
+        // still active, so it stays in the banner
+        assert_eq!(alarms.latched().len(), 1);
+
+        bad.x = 0.6;
+        let escalated = alarms.check(Some(&bad), Some(0.0), 2.0);
+        assert_eq!(escalated[0].severity, Severity::Red);
+        assert!(escalated[0].acked.is_none());
+
+        alarms.ack_all("rso", 3.0);
+        alarms.check(Some(&frame(0.0, 0.0)), Some(0.0), 4.0);
+        assert!(alarms.latched().is_empty());
+    }
+
+    #[test]
+    fn link_age() {
+        let mut alarms = alarms();
+
+        // no frame yet, nothing to lose
+        assert!(alarms.check(None, None, 0.0).is_empty());
+
+        assert!(alarms.check(None, Some(0.5), 0.5).is_empty());
+        assert_eq!(alarms.check(None, Some(1.5), 1.5)[0].severity, Severity::Yellow);
+        let lost = alarms.check(None, Some(3.5), 3.5);
+        assert_eq!(lost[0].name, "link lost");
+        assert_eq!(lost[0].severity, Severity::Red);
+
+        // a frame brings the link back, the other limits are checked against it too
+        let mut diverged = frame(100.0, 0.0);
+        diverged.balt = 50.0;
+        let changed = alarms.check(Some(&diverged), Some(0.0), 4.0);
+        assert_eq!(changed.len(), 2);
+        assert!(changed.iter().any(|n| n.name == "link lost" && !n.active));
+        assert!(changed.iter().any(|n| n.name == "altitude divergence" && n.severity == Severity::Red));
+    }
+}
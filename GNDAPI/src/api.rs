@@ -1,12 +1,40 @@
 use rocket::{
     self,
-    serde::{json::Json},
+    serde::{json::{Json, Value}},
     Shutdown,
     State,
-    Config, fs::NamedFile,
+    Config,
+    data::ToByteUnit,
+    Request,
+    http::Status,
+    response::status::Custom,
+    http::{ContentType, Header, CookieJar},
+    response::stream::{Event, EventStream},
+    tokio::{select, sync::broadcast::error::RecvError},
 };
-use rocket_dyn_templates::Template;
-use std::{sync::{Arc, Mutex}, path::{PathBuf, Path}};
+use serde::{Serialize, Deserialize};
+use std::{sync::{Arc, Mutex}, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}, collections::BTreeMap, net::IpAddr};
+
+use crate::alarm::{Alarm, Alarms, Limit};
+use crate::assets;
+use crate::event::{Detector, FlightEvent};
+use crate::fusion::{self, Coasted, Estimate, Fusion};
+use crate::protocol::{Frame, Gps};
+use crate::auth::{Auth, Role, User, Viewer, Operator};
+use crate::command::{Command, CommandInfo, CommandLog, CommandStatus, COMMANDS};
+use crate::interlock::{Interlock, InterlockStatus};
+use crate::map::{MapStatus, Tiles};
+use crate::replay::{self, Cursor, Opened, Replays, Seek, SessionFile};
+use crate::link::{LinkStats, LinkState, RadioState};
+use crate::health::{self, Health, Level};
+use crate::metrics::{self, Metrics, RequestTimer};
+use crate::stream::{self, Feed, Message, Subscription};
+use crate::station::StationConfig;
+use crate::tuning::{RadioChange, RadioSettings, Tuning};
+use crate::vehicle::{self, VehicleView, MAX_MODEL_SIZE};
+use crate::store::{Store, Record};
+use crate::export::{self, Format};
+use crate::query::{self, Clock, Range, Downsample, Interp};
 
 pub struct Data {
     // api hosted values
@@ -15,48 +43,349 @@ pub struct Data {
     pub x: Vec<(f32, f32)>,
     pub y: Vec<(f32, f32)>,
     pub z: Vec<(f32, f32)>,
-    //pub lat: Vec<(f32, f32)>,
-    //pub long: Vec<(f32, f32)>,
-    //pub fix: Vec<(f32, f32)>,
-    //pub quality: Vec<(f32, f32)>,
+    // NaN for frames without a gps fix, see `Schema::has_gps`
+    pub lat: Vec<(f32, f32)>,
+    pub long: Vec<(f32, f32)>,
+    pub fix: Vec<(f32, f32)>,
+    pub quality: Vec<(f32, f32)>,
     pub vel: Vec<(f32, f32)>,
     pub balt: Vec<(f32, f32)>,
 
     //pub cont_main: Vec<(f32, f32)>,
     //pub cont_droug: Vec<(f32, f32)>,
 
+    // ground receive time (UTC seconds) of each frame, index aligned with the channels above
+    pub rx_time: Vec<f64>,
+    // (min, max) of each channel over the session, kept as frames come in for `/api/state`
+    pub extent: BTreeMap<&'static str, (f32, f32)>,
+    // identifies this run of the ground station, ms since epoch at startup
+    pub session: u64,
+    // UTC seconds at startup
+    pub started: f64,
+    pub link: LinkStats,
+    pub radio: RadioState,
+    // live feed of accepted frames, see `handle_stream`
+    pub feed: Feed,
+    // session log on disk, none when running from memory only
+    pub store: Option<Store>,
+
     // backend server control
     pub is_alive: bool,
     pub do_quit: bool,
-    pub cmds: Vec<(String, f32)>,
+    // every command requested this session, see `handle_cmd_val`
+    pub commands: CommandLog,
+    // arm / execute gate for hazardous commands
+    pub interlock: Interlock,
+    // ground radio settings, changed through `/api/radio`
+    pub tuning: Tuning,
+    // yellow / red limits checked on every frame, see `/api/alarms`
+    pub alarms: Alarms,
+    // finds liftoff, apogee, deployments etc. in the frames, see `/api/events`
+    pub detector: Detector,
+    // ground side altitude / velocity filter, see `/api/fusion`
+    pub fusion: Fusion,
+    // validated station config, see `Station.toml`
+    pub config: StationConfig,
 }
 
 impl Data {
     pub fn new() -> Data {
+        let now = utc_now();
         Data {
             altitude:   vec![],
             w:        vec![],
             x:        vec![],
             y:        vec![],
             z:        vec![],
-            //lat:        vec![],
-            //long:       vec![],
-            //fix:        vec![],
-            //quality:    vec![],
+            lat:        vec![],
+            long:       vec![],
+            fix:        vec![],
+            quality:    vec![],
             vel:        vec![],
             balt:       vec![],
             //cont_main:  vec![],
             //cont_droug: vec![],
+            rx_time:    vec![],
+            extent:     BTreeMap::new(),
+            session:    (now * 1000f64) as u64,
+            started:    now,
+            link:       LinkStats::default(),
+            radio:      RadioState::Starting,
+            feed:       stream::feed(),
+            store:      None,
             is_alive:   true,
             do_quit:    false,
-            cmds:       vec![]
+            commands:   CommandLog::default(),
+            interlock:  Interlock::default(),
+            tuning:     Tuning::default(),
+            alarms:     Alarms::default(),
+            detector:   Detector::default(),
+            fusion:     Fusion::default(),
+            config:     StationConfig::default(),
+        }
+    }
+
+    /// empty data set up from the station config
+    pub fn with_config(config: StationConfig) -> Data {
+        let mut data = Data::new();
+        data.link.locked_age = config.link.locked_age;
+        data.link.lost_age = config.link.lost_age;
+        data.interlock = Interlock::new(config.interlock.clone());
+        data.tuning.settings = config.radio.settings.clone();
+        data.alarms = Alarms::new(&config.alarms);
+        data.detector = Detector::new(config.events.clone());
+        data.fusion = Fusion::new(config.fusion.clone());
+        data.config = config;
+        return data;
+    }
+
+    /// stores a telemetry frame received just now
+    pub fn record(&mut self, frame: &Frame) {
+        self.record_at(frame, utc_now());
+    }
+
+    /// stores a telemetry frame in each channel, `now` is its ground receive time
+    pub fn record_at(&mut self, frame: &Frame, now: f64) {
+        self.altitude.push((frame.time, frame.alt));
+        self.balt.push((frame.time, frame.balt));
+        self.vel.push((frame.time, frame.vel));
+
+        self.w.push((frame.time, frame.w));
+        self.x.push((frame.time, frame.x));
+        self.y.push((frame.time, frame.y));
+        self.z.push((frame.time, frame.z));
+
+        // pushed either way so every channel stays index aligned with rx_time
+        let gps = frame.gps.unwrap_or(Gps {lat: f32::NAN, long: f32::NAN, fix: f32::NAN, quality: f32::NAN});
+        self.lat.push((frame.time, gps.lat));
+        self.long.push((frame.time, gps.long));
+        self.fix.push((frame.time, gps.fix));
+        self.quality.push((frame.time, gps.quality));
+
+        self.rx_time.push(now);
+
+        for def in CHANNELS.iter() {
+            let value = match self.channel(def.name).and_then(|n| n.last()) {
+                Some(n) => n.1,
+                None => {continue;},
+            };
+            let extent = self.extent.entry(def.name).or_insert((f32::INFINITY, f32::NEG_INFINITY));
+            *extent = (extent.0.min(value), extent.1.max(value));
+        }
+
+        self.link.frames += 1;
+        self.link.last_rx = Some(now);
+
+        // an error only means nobody is subscribed
+        let _ = self.feed.send(Message::Frame(*frame, now));
+
+        self.log(&Record::Frame {rx_time: now, frame: *frame});
+
+        // events first, some limits are only checked after one, e.g. the main deploying
+        for event in self.detector.push(frame, now) {
+            println!("event {:?} at {} s | {:?}, {}", event.kind, event.time, event.confidence, event.detail);
+            self.alarms.event(event.kind);
+            let _ = self.feed.send(Message::Event(event.clone()));
+            self.log(&Record::Event {event});
+        }
+
+        let changed = self.alarms.check(Some(frame), Some(0f64), now);
+        self.log_alarms(changed);
+
+        self.fusion.push(frame, now);
+    }
+
+    /// checks the alarm limits that change without a frame, i.e. the age of the link
+    pub fn check_alarms(&mut self, now: f64) {
+        let changed = self.alarms.check(None, self.link.age(now), now);
+        self.log_alarms(changed);
+    }
+
+    pub fn ack_alarm(&mut self, id: u64, client: &str) -> Result<Alarm, String> {
+        let alarm = self.alarms.ack(id, client, utc_now())?;
+        self.log(&Record::Alarm {alarm: alarm.clone()});
+        Ok(alarm)
+    }
+
+    /// acknowledges every alarm waiting for it
+    pub fn ack_alarms(&mut self, client: &str) -> Vec<Alarm> {
+        let acked = self.alarms.ack_all(client, utc_now());
+        self.log_alarms(acked.clone());
+        return acked;
+    }
+
+    fn log_alarms(&mut self, alarms: Vec<Alarm>) {
+        for alarm in alarms {
+            println!("alarm {} {:?} | {} {} past {}", alarm.name, alarm.severity, alarm.channel, alarm.value, alarm.limit);
+            self.log(&Record::Alarm {alarm});
+        }
+    }
+
+    /// validates and logs a command request to the session
+    /// 
+    /// hazardous commands are rejected unless they match the armed command
+    pub fn command(&mut self, name: &str, value: f32, client: &str) -> Command {
+        let now = utc_now();
+        let mut cmd = self.commands.request(name, value, client, now).clone();
+
+        if cmd.status != CommandStatus::Rejected {
+            let link = self.link.state(now);
+            if let Err(n) = self.interlock.execute(name, value, link, now) {
+                cmd = self.commands.reject(cmd.id, n).unwrap().clone();
+            }
+        }
+
+        self.log(&Record::Command {command: cmd.clone()});
+        return cmd;
+    }
+
+    /// first step of a hazardous command, logged with status `armed`
+    pub fn arm(&mut self, name: &str, value: f32, client: &str) -> Command {
+        let now = utc_now();
+        let mut cmd = self.commands.request(name, value, client, now).clone();
+
+        if cmd.status != CommandStatus::Rejected {
+            cmd = match self.interlock.arm(name, value, client, now) {
+                Ok(_) => {
+                    cmd.status = CommandStatus::Armed;
+                    self.commands.update(cmd.clone());
+                    cmd
+                },
+                Err(n) => self.commands.reject(cmd.id, n).unwrap().clone(),
+            };
+        }
+
+        self.log(&Record::Command {command: cmd.clone()});
+        return cmd;
+    }
+
+    /// records a change to a logged command, e.g. a transmit attempt
+    pub fn update_command(&mut self, cmd: Command) {
+        self.log(&Record::Command {command: cmd.clone()});
+        self.commands.update(cmd);
+    }
+
+    /// queues new radio settings for the radio thread
+    pub fn request_tuning(&mut self, settings: RadioSettings, client: &str) -> RadioChange {
+        let change = self.tuning.request(settings, client, utc_now());
+        self.log(&Record::Radio {change: change.clone()});
+        return change;
+    }
+
+    /// records what happened to a radio settings change
+    pub fn update_tuning(&mut self, change: RadioChange) {
+        self.log(&Record::Radio {change: change.clone()});
+        self.tuning.update(change);
+    }
+
+    fn log(&mut self, record: &Record) {
+        if let Some(store) = self.store.as_mut() {
+            store.append(record);
         }
     }
+
+    /// looks up a channel by its api name, see `CHANNELS`
+    pub fn channel(&self, field: &str) -> Option<&Vec<(f32, f32)>> {
+        match field {
+            "alt" => Some(&self.altitude),
+            "w" => Some(&self.w),
+            "x" => Some(&self.x),
+            "y" => Some(&self.y),
+            "z" => Some(&self.z),
+            "vel" => Some(&self.vel),
+            "balt" => Some(&self.balt),
+            "lat" => Some(&self.lat),
+            "long" => Some(&self.long),
+            "fix" => Some(&self.fix),
+            "quality" => Some(&self.quality),
+            _ => None,
+        }
+    }
+}
+
+/// static description of a channel
+pub struct ChannelDef {
+    /// api name
+    pub name: &'static str,
+    pub unit: &'static str,
+    /// type of the value as sent by the vehicle
+    pub kind: &'static str,
+    /// packet the channel is decoded from
+    pub source: &'static str,
+    pub description: &'static str,
+}
+
+/// every channel in `Data`
+pub const CHANNELS: [ChannelDef; 11] = [
+    ChannelDef {name: "alt", unit: "m", kind: "f32", source: "telemetry", description: "fused altitude"},
+    ChannelDef {name: "balt", unit: "m", kind: "f32", source: "telemetry", description: "barometric altitude"},
+    ChannelDef {name: "vel", unit: "m/s", kind: "f32", source: "telemetry", description: "vertical velocity"},
+    ChannelDef {name: "w", unit: "", kind: "f32", source: "telemetry", description: "attitude quaternion w"},
+    ChannelDef {name: "x", unit: "", kind: "f32", source: "telemetry", description: "attitude quaternion x"},
+    ChannelDef {name: "y", unit: "", kind: "f32", source: "telemetry", description: "attitude quaternion y"},
+    ChannelDef {name: "z", unit: "", kind: "f32", source: "telemetry", description: "attitude quaternion z"},
+    ChannelDef {name: "lat", unit: "deg", kind: "f32", source: "gps", description: "gps latitude"},
+    ChannelDef {name: "long", unit: "deg", kind: "f32", source: "gps", description: "gps longitude"},
+    ChannelDef {name: "fix", unit: "", kind: "f32", source: "gps", description: "gps fix type, 0 for none"},
+    ChannelDef {name: "quality", unit: "", kind: "f32", source: "gps", description: "gps fix quality"},
+];
+
+/// json body of every api error
+#[derive(Serialize)]
+pub struct ErrorBody {
+    pub error: String,
+}
+
+pub type ApiError = Custom<Json<ErrorBody>>;
+
+pub fn api_error(status: Status, error: String) -> ApiError {
+    Custom(status, Json(ErrorBody {error}))
+}
+
+/// looks up a channel, or a 404 naming it
+fn lookup<'a>(data: &'a Data, field: &str) -> Result<&'a Vec<(f32, f32)>, ApiError> {
+    match data.channel(field) {
+        Some(n) => Ok(n),
+        None => Err(api_error(Status::NotFound, format!("unknown channel \"{}\", see /api/channels", field))),
+    }
+}
+
+fn parse_clock(clock: Option<&str>) -> Result<Clock, ApiError> {
+    match clock {
+        Some(n) => match Clock::parse(n) {
+            Some(n) => Ok(n),
+            None => Err(api_error(Status::BadRequest, format!("unknown clock \"{}\", expected vehicle or utc", n))),
+        },
+        None => Ok(Clock::Vehicle),
+    }
+}
+
+fn parse_cursor(data: &Data, cursor: Option<&str>) -> Result<Option<usize>, ApiError> {
+    match cursor {
+        Some(n) => match query::decode_cursor(data.session, n) {
+            Ok(n) => Ok(Some(n)),
+            Err(n) => Err(api_error(Status::BadRequest, n)),
+        },
+        None => Ok(None),
+    }
+}
+
+/// splits a comma separated channel list
+fn parse_channels(list: &str) -> Vec<String> {
+    list.split(',').map(|n| n.trim().to_string()).filter(|n| !n.is_empty()).collect()
+}
+
+/// current UTC time in seconds since the unix epoch
+pub fn utc_now() -> f64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(n) => n.as_secs_f64(),
+        Err(_) => 0f64,
+    }
 }
 
 pub type TData = Arc<Mutex<Data>>;
 
-fn compile_vec(data: &mut Vec<(f32, f32)>, points: usize, is_neg: bool) -> Vec<(f32, f32)> {
+fn compile_vec(data: &[(f32, f32)], points: usize, is_neg: bool) -> Vec<(f32, f32)> {
     if points > data.len() && !is_neg {
         return vec![];
     }
@@ -81,138 +410,1665 @@ fn compile_vec(data: &mut Vec<(f32, f32)>, points: usize, is_neg: bool) -> Vec<(
     return res;
 }
 
-#[rocket::get("/api/<field>/<points>")]
-fn handle_api(state: &State<TData>, field: &str, points: i32) -> Json<Vec<(f32, f32)>> {
+/// applies the optional `max_points` / `method` downsampling query to a response
+fn downsample(samples: Vec<(f32, f32)>, max_points: Option<usize>, method: Option<&str>) -> Result<Vec<(f32, f32)>, ApiError> {
+    let method = match method {
+        Some(n) => match Downsample::parse(n) {
+            Some(n) => n,
+            None => {return Err(api_error(Status::BadRequest, format!("unknown method \"{}\", expected lttb or minmax", n)))},
+        },
+        None => Downsample::Lttb,
+    };
+
+    return match max_points {
+        Some(n) => Ok(method.apply(&samples, n)),
+        None => Ok(samples),
+    };
+}
+
+#[rocket::get("/api/<field>/<points>?<max_points>&<method>", rank = 2)]
+fn handle_api(state: &State<TData>, _viewer: Viewer, field: &str, points: i32, max_points: Option<usize>, method: Option<&str>) -> Result<Json<Vec<(f32, f32)>>, ApiError> {
     let data = Arc::clone(&state);
-    let mut data = data.lock().expect("could not lock mutex");
+    let data = data.lock().expect("could not lock mutex");
     
     let is_neg: bool = points < 0;
     let points: i32 = if is_neg {points*-1} else {points};
     let points: usize = points as usize;
 
-    return match field {
-        "alt" => {
-        Json(compile_vec(&mut data.altitude, points, is_neg))
-        },
-    
-        "w" => {
-        Json(compile_vec(&mut data.w, points, is_neg))
-        },
-                
-        "x" => {
-        Json(compile_vec(&mut data.x, points, is_neg))
-        },
-                
-        "y" => {
-        Json(compile_vec(&mut data.y, points, is_neg))
-        },
+    let channel = lookup(&data, field)?;
+    let res = compile_vec(channel, points, is_neg);
 
-        "z" => {
-        Json(compile_vec(&mut data.z, points, is_neg))
-        },
+    return Ok(Json(downsample(res, max_points, method)?));
+}
 
-        /*        
-        "lat" => {
-        Json(compile_vec(&mut data.lat, points, is_neg))
-        },
-                
-        "long" => {
-        Json(compile_vec(&mut data.long, points, is_neg))
-        },
-                
-        "fix" => {
-        Json(compile_vec(&mut data.fix, points, is_neg))
-        },
-                
-        "quality" => {
-        Json(compile_vec(&mut data.quality, points, is_neg))
+/// a slice of a channel plus the cursor to resume from on the next poll
+#[derive(Serialize)]
+pub struct Series {
+    pub samples: Vec<(f32, f32)>,
+    pub cursor: String,
+}
+
+/// time range query over a channel
+/// 
+/// `from` / `to` are inclusive, `since` is exclusive, all in seconds on the given `clock`
+/// (`vehicle` time by default, or ground receive `utc`)
+/// 
+/// `cursor` is the token from a previous response, only samples after it are returned
+/// 
+/// `max_points` caps the number of returned samples using `method` (`lttb` by default, or `minmax`)
+#[rocket::get("/api/<field>?<from>&<to>&<since>&<clock>&<cursor>&<max_points>&<method>")]
+fn handle_range(state: &State<TData>, _viewer: Viewer, field: &str, from: Option<f64>, to: Option<f64>, since: Option<f64>, clock: Option<&str>, cursor: Option<&str>, max_points: Option<usize>, method: Option<&str>) -> Result<Json<Series>, ApiError> {
+    let data = Arc::clone(&state);
+    let data = data.lock().expect("could not lock mutex");
+
+    let clock = parse_clock(clock)?;
+
+    let cursor = parse_cursor(&data, cursor)?;
+
+    let channel = lookup(&data, field)?;
+
+    let range = Range {from, to, since, cursor};
+    let (start, end, next) = match clock {
+        Clock::Vehicle => query::resolve(channel.len(), |i| channel[i].0 as f64, &range),
+        Clock::Utc => query::resolve(channel.len().min(data.rx_time.len()), |i| data.rx_time[i], &range),
+    };
+
+    Ok(Json(Series {
+        samples: downsample(channel[start..end].to_vec(), max_points, method)?,
+        cursor: query::encode_cursor(data.session, next),
+    }))
+}
+
+/// several channels aligned on a shared timestamp
+#[derive(Serialize)]
+pub struct Rows {
+    pub channels: Vec<String>,
+    /// `[time, channel values...]`, a value is null if that channel has nothing at that time
+    pub rows: Vec<Vec<Option<f32>>>,
+    pub cursor: String,
+}
+
+/// aligned multi channel query
+/// 
+/// `channels` is a comma separated list, rows are keyed on the timestamps of the first one.
+/// by default only values from the same frame are joined, `interp=hold|linear` fills the gaps
+/// 
+/// `rate` resamples the rows to a fixed rate in hz, interpolating linearly unless `interp` says otherwise
+/// 
+/// `last` keeps only the final n rows, the time range and cursor parameters work as in `handle_range`
+#[rocket::get("/api/rows?<channels>&<from>&<to>&<since>&<clock>&<cursor>&<last>&<rate>&<interp>")]
+fn handle_rows(state: &State<TData>, _viewer: Viewer, channels: &str, from: Option<f64>, to: Option<f64>, since: Option<f64>, clock: Option<&str>, cursor: Option<&str>, last: Option<usize>, rate: Option<f64>, interp: Option<&str>) -> Result<Json<Rows>, ApiError> {
+    let data = Arc::clone(&state);
+    let data = data.lock().expect("could not lock mutex");
+
+    let params = RowsQuery {channels, from, to, since, clock, cursor, last, rate, interp};
+    Ok(Json(rows(&data, &params)?))
+}
+
+/// parameters of `handle_rows`, shared with the replay rows
+pub struct RowsQuery<'a> {
+    pub channels: &'a str,
+    pub from: Option<f64>,
+    pub to: Option<f64>,
+    pub since: Option<f64>,
+    pub clock: Option<&'a str>,
+    pub cursor: Option<&'a str>,
+    pub last: Option<usize>,
+    pub rate: Option<f64>,
+    pub interp: Option<&'a str>,
+}
+
+fn rows(data: &Data, params: &RowsQuery) -> Result<Rows, ApiError> {
+    let RowsQuery {channels, from, to, since, clock, cursor, last, rate, interp} = *params;
+
+    let clock = parse_clock(clock)?;
+
+    let interp = match interp {
+        Some(n) => match Interp::parse(n) {
+            Some(n) => n,
+            None => {return Err(api_error(Status::BadRequest, format!("unknown interp \"{}\", expected none, hold or linear", n)))},
         },
-        */
-        "vel" => {
-        Json(compile_vec(&mut data.vel, points, is_neg))
+        None => if rate.is_some() {Interp::Linear} else {Interp::Exact},
+    };
+
+    let cursor = parse_cursor(&data, cursor)?;
+
+    let names: Vec<String> = parse_channels(channels);
+    let mut series: Vec<&Vec<(f32, f32)>> = vec![];
+    for name in names.iter() {
+        series.push(lookup(&data, name)?);
+    }
+
+    if series.is_empty() {
+        return Err(api_error(Status::BadRequest, "no channels given".to_string()));
+    }
+
+    // the first channel sets the timeline
+    let reference = series[0];
+    let range = Range {from, to, since, cursor};
+    let (mut start, end, next) = match clock {
+        Clock::Vehicle => query::resolve(reference.len(), |i| reference[i].0 as f64, &range),
+        Clock::Utc => query::resolve(reference.len().min(data.rx_time.len()), |i| data.rx_time[i], &range),
+    };
+
+    if let Some(last) = last {
+        start = start.max(end.saturating_sub(last));
+    }
+
+    let times: Vec<f64> = match rate {
+        Some(rate) if rate <= 0f64 || !rate.is_finite() => {return Err(api_error(Status::BadRequest, "rate must be positive".to_string()))},
+        Some(_) if start == end => vec![],
+        Some(rate) => {
+            let (first, last) = (reference[start].0 as f64, reference[end - 1].0 as f64);
+            if (last - first) * rate > query::MAX_ROWS as f64 {
+                return Err(api_error(Status::BadRequest, format!("{} hz over {:.0} s is more than {} rows, lower the rate or narrow from / to", rate, last - first, query::MAX_ROWS)));
+            }
+            query::grid(first, last, rate)
         },
+        None => reference[start..end].iter().map(|s| s.0 as f64).collect(),
+    };
 
-        "balt" => {
-        Json(compile_vec(&mut data.balt, points, is_neg))
+    let mut rows: Vec<Vec<Option<f32>>> = Vec::with_capacity(times.len());
+    for t in times {
+        let mut row: Vec<Option<f32>> = vec![Some(t as f32)];
+        for channel in series.iter() {
+            row.push(query::value_at(channel, t, interp));
         }
+        rows.push(row);
+    }
+
+    Ok(Rows {
+        channels: names,
+        rows,
+        cursor: query::encode_cursor(data.session, next),
+    })
+}
 
+/// a channel's description plus what has been received on it so far
+#[derive(Serialize)]
+pub struct ChannelInfo {
+    pub name: &'static str,
+    pub unit: &'static str,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub source: &'static str,
+    pub description: &'static str,
+    pub samples: usize,
+    /// vehicle time of the first and last sample
+    pub first_time: Option<f32>,
+    pub last_time: Option<f32>,
+    /// ground receive time of the first and last sample, UTC seconds
+    pub first_rx: Option<f64>,
+    pub last_rx: Option<f64>,
+}
+
+/// lists every channel the api serves
+#[rocket::get("/api/channels")]
+fn handle_channels(state: &State<TData>, _viewer: Viewer) -> Json<Vec<ChannelInfo>> {
+    let data = Arc::clone(&state);
+    let data = data.lock().expect("could not lock mutex");
 
+    let mut out: Vec<ChannelInfo> = vec![];
+    for def in CHANNELS.iter() {
+        let channel: &[(f32, f32)] = match data.channel(def.name) {
+            Some(n) => n,
+            None => &[],
+        };
 
-          /*  
-        "cont_main" => {
-        Json(compile_vec(&mut data.cont_main, points, is_neg))
-        },
-            
-        "cont_droug" => {
-        Json(compile_vec(&mut data.cont_droug, points, is_neg))
+        out.push(ChannelInfo {
+            name: def.name,
+            unit: def.unit,
+            kind: def.kind,
+            source: def.source,
+            description: def.description,
+            samples: channel.len(),
+            first_time: channel.first().map(|n| n.0),
+            last_time: channel.last().map(|n| n.0),
+            first_rx: if channel.is_empty() {None} else {data.rx_time.first().copied()},
+            last_rx: if channel.is_empty() {None} else {data.rx_time.get(channel.len() - 1).copied()},
+        });
+    }
+
+    Json(out)
+}
+
+/// an export file, sent as a download
+#[derive(rocket::Responder)]
+pub struct Download {
+    inner: (ContentType, Vec<u8>),
+    disposition: Header<'static>,
+}
+
+/// exports the current session as `csv`, `jsonl` or `parquet`
+/// 
+/// `channels` is a comma separated list (all by default), the time range parameters work as in `handle_range`.
+/// `table=commands` exports the command audit log instead of the telemetry frames
+#[rocket::get("/api/export/<format>?<table>&<channels>&<from>&<to>&<since>&<clock>")]
+fn handle_export(state: &State<TData>, _viewer: Viewer, format: &str, table: Option<&str>, channels: Option<&str>, from: Option<f64>, to: Option<f64>, since: Option<f64>, clock: Option<&str>) -> Result<Download, ApiError> {
+    let data = Arc::clone(&state);
+    let data = data.lock().expect("could not lock mutex");
+
+    let format = match Format::parse(format) {
+        Some(n) => n,
+        None => {return Err(api_error(Status::NotFound, format!("unknown format \"{}\", expected csv, jsonl or parquet", format)))},
+    };
+
+    let mut buf: Vec<u8> = vec![];
+    let name = match table.unwrap_or("frames") {
+        "frames" => {
+            let clock = parse_clock(clock)?;
+            let names: Vec<String> = match channels {
+                Some(n) => parse_channels(n),
+                None => CHANNELS.iter().map(|n| n.name.to_string()).collect(),
+            };
+            for name in names.iter() {
+                lookup(&data, name)?;
+            }
+
+            let range = Range {from, to, since, cursor: None};
+            let table = match export::table(&data, &names, &range, clock) {
+                Ok(n) => n,
+                Err(n) => {return Err(api_error(Status::BadRequest, n))},
+            };
+            let session = data.session;
+            drop(data);
+
+            match export::write(&table, format, &mut buf) {
+                Ok(_) => {},
+                Err(n) => {return Err(api_error(Status::InternalServerError, n))},
+            };
+            format!("session-{}", session)
         },
-        */
-                            
-        _ => {
-            Json(vec![])
+        "commands" => {
+            match export::write_commands(&data.commands.history, format, &mut buf) {
+                Ok(_) => {},
+                Err(n) => {return Err(api_error(Status::BadRequest, n))},
+            };
+            format!("session-{}-commands", data.session)
         },
-        
+        n => {return Err(api_error(Status::BadRequest, format!("unknown table \"{}\", expected frames or commands", n)))},
+    };
+
+    let content_type = ContentType::parse_flexible(format.content_type()).unwrap_or(ContentType::Binary);
+    Ok(Download {
+        inner: (content_type, buf),
+        disposition: Header::new("Content-Disposition", format!("attachment; filename=\"{}.{}\"", name, format.extension())),
+    })
+}
+
+/// json errors for anything under /api or /cmd that fails before reaching a handler
+#[rocket::catch(default)]
+fn api_catcher(status: Status, req: &Request) -> ApiError {
+    match status.code {
+        401 => api_error(status, String::from("a valid token is required, pass it as \"Authorization: Bearer <token>\" or log in at /api/login")),
+        403 => api_error(status, format!("{} needs the operator role", req.uri())),
+        _ => api_error(status, format!("{} {}", status, req.uri())),
     }
 }
 
-#[rocket::get("/cmd/<cmd>")]
-fn handle_cmd(state: &State<TData>, shutdown: Shutdown, cmd: &str) -> &'static str {    
+/// the latest sample of a channel
+#[derive(Serialize)]
+pub struct Latest {
+    /// vehicle time, seconds since boot
+    pub time: f32,
+    pub value: f32,
+    /// ground receive time, UTC seconds
+    pub rx_time: f64,
+    pub age_ms: u64,
+    /// extremes over the whole session
+    pub min: f32,
+    pub max: f32,
+}
+
+#[derive(Serialize)]
+pub struct LinkStatus {
+    pub state: LinkState,
+    /// ms since the last accepted frame
+    pub age_ms: Option<u64>,
+    #[serde(flatten)]
+    pub stats: LinkStats,
+}
+
+#[derive(Serialize)]
+pub struct Session {
+    pub id: u64,
+    /// UTC seconds
+    pub started: f64,
+    pub uptime_s: f64,
+}
+
+#[derive(Serialize)]
+pub struct Snapshot {
+    /// UTC seconds the snapshot was taken
+    pub now: f64,
+    pub channels: BTreeMap<String, Latest>,
+    pub link: LinkStatus,
+    pub session: Session,
+}
+
+/// the current value of every channel, plus link and session status
+#[rocket::get("/api/state")]
+fn handle_state(state: &State<TData>, _viewer: Viewer) -> Json<Snapshot> {
     let data = Arc::clone(&state);
-    let mut data = data.lock().expect("could not lock mutex");
+    let data = data.lock().expect("could not lock mutex");
+
+    let now = utc_now();
+
+    let mut channels: BTreeMap<String, Latest> = BTreeMap::new();
+    for def in CHANNELS.iter() {
+        let name = def.name;
+        let channel = match data.channel(name) {
+            Some(n) => n,
+            None => {continue;},
+        };
 
-    match cmd {
-        "quit" => {
-            data.do_quit = true;
-            shutdown.notify()
+        let (time, value) = match channel.last() {
+            Some(n) => *n,
+            None => {continue;},
+        };
+
+        let rx_time = data.rx_time.get(channel.len() - 1).copied().unwrap_or(now);
+        let (min, max) = data.extent.get(name).copied().unwrap_or((value, value));
+        channels.insert(name.to_string(), Latest {
+            time,
+            value,
+            rx_time,
+            age_ms: ((now - rx_time).max(0f64) * 1000f64) as u64,
+            min,
+            max,
+        });
+    }
+
+    Json(Snapshot {
+        now,
+        channels,
+        link: LinkStatus {
+            state: data.link.state(now),
+            age_ms: data.link.age(now).map(|n| (n * 1000f64) as u64),
+            stats: data.link.clone(),
         },
-        _ => {},
+        session: Session {
+            id: data.session,
+            started: data.started,
+            uptime_s: now - data.started,
+        },
+    })
+}
+
+/// live feed of accepted frames as server-sent events
+/// 
+/// each frame is a `frame` event holding `{"time", "rx_time", <channel>: value...}` for the
+/// comma separated `channels` (all by default), `decimate` forwards only every nth frame.
+/// detected flight events are sent to every subscriber as `event` events, see `/api/events`.
+/// a `lagged` event with the number of missed frames is sent if the client falls behind
+#[rocket::get("/api/stream?<channels>&<decimate>")]
+fn handle_stream(state: &State<TData>, _viewer: Viewer, mut shutdown: Shutdown, channels: Option<&str>, decimate: Option<usize>) -> Result<EventStream![], ApiError> {
+    let data = Arc::clone(&state);
+    let data = data.lock().expect("could not lock mutex");
+
+    let names: Vec<String> = match channels {
+        Some(n) => parse_channels(n),
+        None => CHANNELS.iter().map(|n| n.name.to_string()).collect(),
+    };
+    for name in names.iter() {
+        lookup(&data, name)?;
+    }
+
+    let mut sub = Subscription::new(names, decimate.unwrap_or(1));
+    let mut rx = data.feed.subscribe();
+    drop(data);
+
+    Ok(EventStream! {
+        loop {
+            let msg = select! {
+                msg = rx.recv() => msg,
+                _ = &mut shutdown => break,
+            };
+
+            match msg {
+                Ok(Message::Frame(frame, rx_time)) => {
+                    if let Some(sample) = sub.sample(&frame, rx_time) {
+                        yield Event::json(&sample).event("frame");
+                    }
+                },
+                Ok(Message::Event(event)) => {
+                    yield Event::json(&event).event("event");
+                },
+                Err(RecvError::Lagged(n)) => {
+                    yield Event::json(&n).event("lagged");
+                },
+                Err(RecvError::Closed) => break,
+            };
+        }
+    })
+}
+
+/// who sent a command, as recorded in the audit log
+fn client_name(operator: &Operator, ip: Option<IpAddr>) -> String {
+    match ip {
+        Some(n) => format!("{}@{}", operator.0.name, n),
+        None => operator.0.name.clone(),
+    }
+}
+
+/// the logged command, with a 400 if it was rejected and a 202 if it is armed or waiting on the radio
+fn command_response(cmd: Command) -> Custom<Json<Command>> {
+    let status = match cmd.status {
+        CommandStatus::Rejected => Status::BadRequest,
+        CommandStatus::Armed => Status::Accepted,
+        CommandStatus::Queued => Status::Accepted,
+        _ => Status::Ok,
     };
+    Custom(status, Json(cmd))
+}
 
-    ""
+#[rocket::post("/cmd/<cmd>")]
+fn handle_cmd(state: &State<TData>, operator: Operator, ip: Option<IpAddr>, shutdown: Shutdown, cmd: &str) -> Custom<Json<Command>> {
+    handle_cmd_val(state, operator, ip, shutdown, cmd, 0f32)
 }
 
-#[rocket::get("/cmd/<cmd>/<val>")]
-fn handle_cmd_val(state: &State<TData>, cmd: &str, val: f32) -> &'static str {
+#[rocket::post("/cmd/<cmd>/<val>")]
+fn handle_cmd_val(state: &State<TData>, operator: Operator, ip: Option<IpAddr>, shutdown: Shutdown, cmd: &str, val: f32) -> Custom<Json<Command>> {
     let data = Arc::clone(&state);
     let mut data = data.lock().expect("could not lock mutex");
-    
-    data.cmds.push((String::from(cmd), val));
 
+    let cmd = data.command(cmd, val, &client_name(&operator, ip));
+
+    if cmd.status == CommandStatus::Executed && cmd.name == "quit" {
+        data.do_quit = true;
+        shutdown.notify();
+    }
 
-    ""
+    command_response(cmd)
 }
 
-#[rocket::get("/view")]
-fn view() -> Template {
-    Template::render("view", rocket_dyn_templates::context!{})
+/// arms a hazardous command, it can then be executed once with the same value before the arm expires
+#[rocket::post("/cmd/arm/<cmd>/<val>")]
+fn handle_arm(state: &State<TData>, operator: Operator, ip: Option<IpAddr>, cmd: &str, val: f32) -> Custom<Json<Command>> {
+    let data = Arc::clone(&state);
+    let mut data = data.lock().expect("could not lock mutex");
+
+    command_response(data.arm(cmd, val, &client_name(&operator, ip)))
 }
 
-#[rocket::get("/static/<file>")]
-async fn get_file(file: PathBuf) -> Option<NamedFile> {
-    NamedFile::open(Path::new("public/").join(file)).await.ok()
+#[rocket::post("/cmd/disarm")]
+fn handle_disarm(state: &State<TData>, _operator: Operator) -> Json<InterlockStatus> {
+    let data = Arc::clone(&state);
+    let mut data = data.lock().expect("could not lock mutex");
+
+    data.interlock.disarm();
+    Json(data.interlock.status(utc_now()))
 }
 
+/// settings of the ground radio, and a change waiting to be applied
+#[rocket::get("/api/radio")]
+fn handle_radio(state: &State<TData>, _viewer: Viewer) -> Json<Tuning> {
+    let data = Arc::clone(&state);
+    let data = data.lock().expect("could not lock mutex");
 
-pub fn start_api(data: TData) -> bool {
-    let api_data = Arc::clone(&data);
-    rocket::tokio::runtime::Builder::new_multi_thread()
-        .worker_threads(Config::from(Config::figment()).workers)
-        // NOTE: graceful shutdown depends on the "rocket-worker" prefix.
-        .thread_name("rocket-worker-thread")
-        .enable_all()
-        .build()
-        .expect("create tokio runtime")
-        .block_on(async move {
-            let _ = rocket::build()
-            .mount("/", rocket::routes![handle_api, handle_cmd, handle_cmd_val, view, get_file])
-            .attach(Template::fairing())
-            .manage(api_data)
-            .launch()
-            .await;
-        });
+    Json(data.tuning.clone())
+}
 
-    let data = Arc::clone(&data);
+/// changes some of the ground radio settings, e.g. `{"power": 10}`
+///
+/// the radio thread applies the change between polls, poll `/api/radio` for the outcome
+#[rocket::put("/api/radio", data = "<patch>")]
+fn handle_radio_set(state: &State<TData>, operator: Operator, ip: Option<IpAddr>, patch: Json<Value>) -> Result<Custom<Json<RadioChange>>, ApiError> {
+    let data = Arc::clone(&state);
     let mut data = data.lock().expect("could not lock mutex");
-    data.is_alive = false;
-    return !data.do_quit;
-}
\ No newline at end of file
+
+    let settings = match data.tuning.settings.patch(&patch) {
+        Ok(n) => n,
+        Err(n) => {return Err(api_error(Status::BadRequest, format!("invalid radio settings: {}", n)))},
+    };
+
+    let change = data.request_tuning(settings, &client_name(&operator, ip));
+    Ok(Custom(Status::Accepted, Json(change)))
+}
+
+/// pad, station and last gps fix for the map page, with the range and bearing from the station
+#[rocket::get("/api/map")]
+fn handle_map(state: &State<TData>, _viewer: Viewer, tiles: &State<Option<Tiles>>) -> Json<MapStatus> {
+    let data = Arc::clone(&state);
+    let data = data.lock().expect("could not lock mutex");
+
+    Json(MapStatus::new(&data, &data.config.map, tiles.as_ref()))
+}
+
+/// a map tile from the `[map]` tiles file, `y` counts from the north like other web maps
+#[rocket::get("/api/tiles/<z>/<x>/<y>")]
+fn handle_tile(_viewer: Viewer, tiles: &State<Option<Tiles>>, z: u8, x: u32, y: u32) -> Result<(ContentType, Vec<u8>), ApiError> {
+    let tiles = match tiles.as_ref() {
+        Some(n) => n,
+        None => {return Err(api_error(Status::NotFound, String::from("no map tiles configured")))},
+    };
+
+    match tiles.tile(z, x, y) {
+        Ok(Some(n)) => Ok(n),
+        Ok(None) => Err(api_error(Status::NotFound, format!("no tile {}/{}/{}", z, x, y))),
+        Err(n) => Err(api_error(Status::InternalServerError, format!("could not read tile: {}", n))),
+    }
+}
+
+/// the vehicle model and how the IMU is mounted in it, for the attitude view
+#[rocket::get("/api/vehicle")]
+fn handle_vehicle(state: &State<TData>, _viewer: Viewer) -> Json<VehicleView> {
+    let data = Arc::clone(&state);
+    let data = data.lock().expect("could not lock mutex");
+
+    Json(VehicleView::new(&data.config.vehicle))
+}
+
+/// the configured vehicle model, or the stock one until a model is configured or uploaded
+#[rocket::get("/api/vehicle/model")]
+fn handle_vehicle_model(state: &State<TData>, _viewer: Viewer) -> Option<(ContentType, Vec<u8>)> {
+    let config = {
+        let data = Arc::clone(&state);
+        let data = data.lock().expect("could not lock mutex");
+        data.config.vehicle.clone()
+    };
+
+    let content_type = ContentType::new("model", "gltf-binary");
+    match config.load_model() {
+        Some(n) => Some((content_type, n)),
+        None => assets::public(Path::new("model.glb")).map(|(_, n)| (content_type, n.to_vec())),
+    }
+}
+
+/// replaces the vehicle model with the glTF binary in the body, kept at `vehicle.model`
+#[rocket::put("/api/vehicle/model", data = "<body>")]
+async fn handle_vehicle_upload(state: &State<TData>, _operator: Operator, body: rocket::Data<'_>) -> Result<Json<VehicleView>, ApiError> {
+    let bytes = match body.open(MAX_MODEL_SIZE.bytes()).into_bytes().await {
+        Ok(n) if n.is_complete() => n.into_inner(),
+        Ok(_) => {return Err(api_error(Status::PayloadTooLarge, format!("models are limited to {} MiB", MAX_MODEL_SIZE / 1024 / 1024)))},
+        Err(n) => {return Err(api_error(Status::BadRequest, format!("could not read the upload: {}", n)))},
+    };
+
+    let config = {
+        let data = Arc::clone(&state);
+        let data = data.lock().expect("could not lock mutex");
+        data.config.vehicle.clone()
+    };
+
+    if let Err(n) = vehicle::check_model(&bytes) {
+        return Err(api_error(Status::BadRequest, n));
+    }
+    match vehicle::save_model(&config.model, &bytes) {
+        Ok(_) => Ok(Json(VehicleView::new(&config))),
+        Err(n) => Err(api_error(Status::InternalServerError, n)),
+    }
+}
+
+/// session logs that can be replayed, newest first
+#[rocket::get("/api/sessions")]
+fn handle_sessions(state: &State<TData>, _viewer: Viewer) -> Result<Json<Vec<SessionFile>>, ApiError> {
+    let data = Arc::clone(&state);
+    let data = data.lock().expect("could not lock mutex");
+
+    match replay::sessions(&data.config.storage.session_dir, data.session) {
+        Ok(n) => Ok(Json(n)),
+        Err(n) => Err(api_error(Status::InternalServerError, n)),
+    }
+}
+
+/// opens a playback cursor over a session log, paused at its first frame
+#[rocket::post("/api/sessions/<session>/replay")]
+fn handle_replay_open(state: &State<TData>, _viewer: Viewer, replays: &State<Mutex<Replays>>, session: u64) -> Result<Custom<Json<Opened>>, ApiError> {
+    let dir = {
+        let data = Arc::clone(&state);
+        let data = data.lock().expect("could not lock mutex");
+        data.config.storage.session_dir.clone()
+    };
+
+    let mut replays = replays.lock().expect("could not lock mutex");
+    let now = utc_now();
+    let playback = match replays.open(&dir, session, now) {
+        Ok(n) => n,
+        Err(n) => {return Err(api_error(Status::NotFound, n))},
+    };
+
+    let markers = replay::markers(&playback.data);
+    Ok(Custom(Status::Created, Json(Opened {cursor: playback.cursor(now), markers})))
+}
+
+fn no_replay(id: u64) -> ApiError {
+    api_error(Status::NotFound, format!("no replay {}, it may have been idle too long", id))
+}
+
+/// where a playback cursor is, with every channel of the frame under it
+#[rocket::get("/api/replay/<id>")]
+fn handle_replay(_viewer: Viewer, replays: &State<Mutex<Replays>>, id: u64) -> Result<Json<Cursor>, ApiError> {
+    let mut replays = replays.lock().expect("could not lock mutex");
+    match replays.get(id) {
+        Some(n) => Ok(Json(n.cursor(utc_now()))),
+        None => Err(no_replay(id)),
+    }
+}
+
+/// moves a playback cursor, changes its speed or plays / pauses it, e.g. `{"position": 12.5, "playing": true}`
+#[rocket::put("/api/replay/<id>", data = "<seek>")]
+fn handle_replay_seek(_viewer: Viewer, replays: &State<Mutex<Replays>>, id: u64, seek: Json<Seek>) -> Result<Json<Cursor>, ApiError> {
+    let mut replays = replays.lock().expect("could not lock mutex");
+    let playback = match replays.get(id) {
+        Some(n) => n,
+        None => {return Err(no_replay(id))},
+    };
+
+    let now = utc_now();
+    match playback.seek(&seek, now) {
+        Ok(_) => Ok(Json(playback.cursor(now))),
+        Err(n) => Err(api_error(Status::BadRequest, n)),
+    }
+}
+
+#[rocket::delete("/api/replay/<id>")]
+fn handle_replay_close(_viewer: Viewer, replays: &State<Mutex<Replays>>, id: u64) -> Result<Status, ApiError> {
+    let mut replays = replays.lock().expect("could not lock mutex");
+    match replays.close(id) {
+        true => Ok(Status::NoContent),
+        false => Err(no_replay(id)),
+    }
+}
+
+/// aligned rows of the replayed session, with the same parameters as `/api/rows`
+#[rocket::get("/api/replay/<id>/rows?<channels>&<from>&<to>&<since>&<clock>&<cursor>&<last>&<rate>&<interp>")]
+fn handle_replay_rows(_viewer: Viewer, replays: &State<Mutex<Replays>>, id: u64, channels: &str, from: Option<f64>, to: Option<f64>, since: Option<f64>, clock: Option<&str>, cursor: Option<&str>, last: Option<usize>, rate: Option<f64>, interp: Option<&str>) -> Result<Json<Rows>, ApiError> {
+    let data = {
+        let mut replays = replays.lock().expect("could not lock mutex");
+        match replays.get(id) {
+            Some(n) => Arc::clone(&n.data),
+            None => {return Err(no_replay(id))},
+        }
+    };
+
+    let params = RowsQuery {channels, from, to, since, clock, cursor, last, rate, interp};
+    Ok(Json(rows(&data, &params)?))
+}
+
+/// filtered altitude and velocity of the replayed session, as `/api/fusion` but smoothed unless `smooth=false`
+#[rocket::get("/api/replay/<id>/fusion?<rate>&<from>&<to>&<smooth>")]
+fn handle_replay_fusion(state: &State<TData>, _viewer: Viewer, replays: &State<Mutex<Replays>>, id: u64, rate: Option<f64>, from: Option<f64>, to: Option<f64>, smooth: Option<bool>) -> Result<Json<Vec<Estimate>>, ApiError> {
+    let replayed = {
+        let mut replays = replays.lock().expect("could not lock mutex");
+        match replays.get(id) {
+            Some(n) => Arc::clone(&n.data),
+            None => {return Err(no_replay(id))},
+        }
+    };
+    // replays are filtered with the noise configured now, not what the session ran with
+    let config = {
+        let data = Arc::clone(&state);
+        let data = data.lock().expect("could not lock mutex");
+        data.config.fusion.clone()
+    };
+
+    match fusion::track(&replayed, &config, rate, from, to, smooth.unwrap_or(true)) {
+        Ok(n) => Ok(Json(n)),
+        Err(n) => Err(api_error(Status::BadRequest, n)),
+    }
+}
+
+/// station health for the launch checklist, 503 when the overall status is FAIL
+/// 
+/// readable without a token so monitoring can poll it, it holds no telemetry
+#[rocket::get("/api/health")]
+fn handle_health(state: &State<TData>) -> Custom<Json<Health>> {
+    let data = Arc::clone(&state);
+    let data = data.lock().expect("could not lock mutex");
+
+    let health = health::check(&data, utc_now());
+    let status = match health.status {
+        Level::Fail => Status::ServiceUnavailable,
+        _ => Status::Ok,
+    };
+    Custom(status, Json(health))
+}
+
+/// prometheus metrics for the ground station
+///
+/// they include the latest telemetry, so a scraper needs a viewer token unless `public_read` is set
+#[rocket::get("/metrics")]
+fn handle_metrics(state: &State<TData>, _viewer: Viewer, metrics: &State<Metrics>) -> (ContentType, String) {
+    let data = Arc::clone(&state);
+    let data = data.lock().expect("could not lock mutex");
+
+    let content_type = ContentType::new("text", "plain").with_params(("version", "0.0.4"));
+    (content_type, metrics::render(&data, metrics, utc_now()))
+}
+
+/// flight events detected this session, oldest first
+#[rocket::get("/api/events")]
+fn handle_events(state: &State<TData>, _viewer: Viewer) -> Json<Vec<FlightEvent>> {
+    let data = Arc::clone(&state);
+    let data = data.lock().expect("could not lock mutex");
+
+    Json(data.detector.events.clone())
+}
+
+/// the filtered altitude and velocity carried forward to now, 404 before the first frame
+#[rocket::get("/api/fusion/now")]
+fn handle_fusion_now(state: &State<TData>, _viewer: Viewer) -> Result<Json<Coasted>, ApiError> {
+    let data = Arc::clone(&state);
+    let data = data.lock().expect("could not lock mutex");
+
+    match data.fusion.now(utc_now()) {
+        Some(n) => Ok(Json(n)),
+        None => Err(api_error(Status::NotFound, String::from("no frames yet"))),
+    }
+}
+
+/// filtered altitude and velocity over this session, `rate` hz between `from` and `to` vehicle
+/// seconds or at every frame without a rate, `smooth=true` runs the backward pass too
+#[rocket::get("/api/fusion?<rate>&<from>&<to>&<smooth>")]
+fn handle_fusion(state: &State<TData>, _viewer: Viewer, rate: Option<f64>, from: Option<f64>, to: Option<f64>, smooth: Option<bool>) -> Result<Json<Vec<Estimate>>, ApiError> {
+    let data = Arc::clone(&state);
+    let data = data.lock().expect("could not lock mutex");
+
+    match fusion::track(&data, &data.fusion.config, rate, from, to, smooth.unwrap_or(false)) {
+        Ok(n) => Ok(Json(n)),
+        Err(n) => Err(api_error(Status::BadRequest, n)),
+    }
+}
+
+/// latched alarms, oldest first, `all=true` includes every alarm raised this session
+#[rocket::get("/api/alarms?<all>")]
+fn handle_alarms(state: &State<TData>, _viewer: Viewer, all: Option<bool>) -> Json<Vec<Alarm>> {
+    let data = Arc::clone(&state);
+    let mut data = data.lock().expect("could not lock mutex");
+
+    // the link can be lost while the radio thread is stuck, so its age is checked here too
+    data.check_alarms(utc_now());
+    match all.unwrap_or(false) {
+        true => Json(data.alarms.history.clone()),
+        false => Json(data.alarms.latched()),
+    }
+}
+
+/// the yellow and red limits alarms are raised from
+#[rocket::get("/api/alarms/limits")]
+fn handle_alarm_limits(state: &State<TData>, _viewer: Viewer) -> Json<Vec<Limit>> {
+    let data = Arc::clone(&state);
+    let data = data.lock().expect("could not lock mutex");
+
+    Json(data.alarms.limits.clone())
+}
+
+/// acknowledges an alarm, it leaves the banner once its channel is back in limits
+#[rocket::post("/api/alarms/<id>/ack")]
+fn handle_alarm_ack(state: &State<TData>, operator: Operator, ip: Option<IpAddr>, id: u64) -> Result<Json<Alarm>, ApiError> {
+    let data = Arc::clone(&state);
+    let mut data = data.lock().expect("could not lock mutex");
+
+    match data.ack_alarm(id, &client_name(&operator, ip)) {
+        Ok(n) => Ok(Json(n)),
+        Err(n) => Err(api_error(Status::NotFound, n)),
+    }
+}
+
+/// acknowledges every alarm, returns the ones that were waiting
+#[rocket::post("/api/alarms/ack")]
+fn handle_alarm_ack_all(state: &State<TData>, operator: Operator, ip: Option<IpAddr>) -> Json<Vec<Alarm>> {
+    let data = Arc::clone(&state);
+    let mut data = data.lock().expect("could not lock mutex");
+
+    Json(data.ack_alarms(&client_name(&operator, ip)))
+}
+
+/// hazardous commands, and what is armed right now
+#[rocket::get("/api/interlock")]
+fn handle_interlock(state: &State<TData>, _viewer: Viewer) -> Json<InterlockStatus> {
+    let data = Arc::clone(&state);
+    let mut data = data.lock().expect("could not lock mutex");
+
+    Json(data.interlock.status(utc_now()))
+}
+
+/// every command with its argument range, and whether it needs arming
+#[rocket::get("/api/commands/defs")]
+fn handle_command_defs(state: &State<TData>, _viewer: Viewer) -> Json<Vec<CommandInfo>> {
+    let data = Arc::clone(&state);
+    let data = data.lock().expect("could not lock mutex");
+
+    Json(COMMANDS.iter().map(|n| n.info(data.interlock.is_hazardous(n.name))).collect())
+}
+
+/// the command audit log, `since` only returns commands with a higher id
+#[rocket::get("/api/commands?<since>")]
+fn handle_commands(state: &State<TData>, _viewer: Viewer, since: Option<u64>) -> Json<Vec<Command>> {
+    let data = Arc::clone(&state);
+    let data = data.lock().expect("could not lock mutex");
+
+    let since = since.unwrap_or(0);
+    Json(data.commands.history.iter().filter(|n| n.id > since).cloned().collect())
+}
+
+#[rocket::get("/api/commands/<id>")]
+fn handle_command(state: &State<TData>, _viewer: Viewer, id: u64) -> Result<Json<Command>, ApiError> {
+    let data = Arc::clone(&state);
+    let data = data.lock().expect("could not lock mutex");
+
+    match data.commands.get(id) {
+        Some(n) => Ok(Json(n.clone())),
+        None => Err(api_error(Status::NotFound, format!("no command with id {}", id))),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Login {
+    pub token: String,
+}
+
+/// checks a token and keeps it in a cookie, so pages and `EventSource` can use it
+#[rocket::post("/api/login", data = "<login>")]
+fn handle_login(auth: &State<Auth>, jar: &CookieJar<'_>, login: Json<Login>) -> Result<Json<User>, ApiError> {
+    match auth.user(&login.token) {
+        Some(user) => {
+            Auth::login(jar, &login.token);
+            Ok(Json(user.clone()))
+        },
+        None => Err(api_error(Status::Unauthorized, String::from("unknown token"))),
+    }
+}
+
+#[rocket::post("/api/logout")]
+fn handle_logout(jar: &CookieJar<'_>) -> Status {
+    Auth::logout(jar);
+    Status::NoContent
+}
+
+#[rocket::get("/view")]
+fn view() -> Option<(ContentType, &'static [u8])> {
+    assets::page("view")
+}
+
+/// live charts of every channel, readouts, link status and the attitude view
+#[rocket::get("/dashboard")]
+fn dashboard() -> Option<(ContentType, &'static [u8])> {
+    assets::page("dashboard")
+}
+
+/// scrub and play back a logged session
+#[rocket::get("/replay")]
+fn replay_page() -> Option<(ContentType, &'static [u8])> {
+    assets::page("replay")
+}
+
+/// lists the commands, sends them with a confirmation step and follows their status
+#[rocket::get("/console")]
+fn console() -> Option<(ContentType, &'static [u8])> {
+    assets::page("console")
+}
+
+/// ground track of the vehicle over offline map tiles
+#[rocket::get("/map")]
+fn map() -> Option<(ContentType, &'static [u8])> {
+    assets::page("map")
+}
+
+/// files from `public/`, embedded in the binary so the ui works offline from any directory
+#[rocket::get("/static/<file..>")]
+fn get_file(file: PathBuf) -> Option<(ContentType, &'static [u8])> {
+    assets::public(&file)
+}
+
+
+/// builds the api server around the shared data struct, set up from its station config
+pub fn build(data: TData) -> rocket::Rocket<rocket::Build> {
+    let config = data.lock().expect("could not lock mutex").config.clone();
+    if !config.auth.users.iter().any(|n| n.role == Role::Operator) {
+        println!("no operator tokens configured, commands are disabled");
+    }
+
+    // the rest of the http settings still come from Rocket.toml and ROCKET_* env vars
+    let figment = Config::figment()
+        .merge(("address", config.server.address.clone()))
+        .merge(("port", config.server.port));
+
+    let tiles = match config.map.tiles.as_ref().map(|n| Tiles::open(n)) {
+        Some(Ok(n)) => Some(n),
+        Some(Err(n)) => {
+            println!("map tiles unavailable, {}", n);
+            None
+        },
+        None => None,
+    };
+
+    let mut rocket = mount(rocket::custom(figment), data, config.auth, tiles);
+    if config.metrics.enabled {
+        rocket = rocket.mount("/", rocket::routes![handle_metrics]);
+    }
+
+    return rocket;
+}
+
+/// builds the api server with default http settings, everything mounted but map tiles
+#[cfg(test)]
+pub fn build_with(data: TData, auth: Auth) -> rocket::Rocket<rocket::Build> {
+    mount(rocket::build(), data, auth, None).mount("/", rocket::routes![handle_metrics])
+}
+
+fn mount(rocket: rocket::Rocket<rocket::Build>, data: TData, auth: Auth, tiles: Option<Tiles>) -> rocket::Rocket<rocket::Build> {
+    rocket
+        .mount("/", rocket::routes![handle_api, handle_range, handle_rows, handle_state, handle_stream, handle_channels, handle_export, handle_commands, handle_command_defs, handle_command, handle_health, handle_radio, handle_radio_set, handle_vehicle, handle_vehicle_model, handle_vehicle_upload, handle_map, handle_tile, handle_sessions, handle_replay_open, handle_replay, handle_replay_seek, handle_replay_close, handle_replay_rows, handle_replay_fusion, handle_interlock, handle_events, handle_fusion, handle_fusion_now, handle_alarms, handle_alarm_limits, handle_alarm_ack, handle_alarm_ack_all, handle_login, handle_logout, handle_arm, handle_disarm, handle_cmd, handle_cmd_val, view, dashboard, console, map, replay_page, get_file])
+        .register("/api", rocket::catchers![api_catcher])
+        .register("/cmd", rocket::catchers![api_catcher])
+        .attach(RequestTimer)
+        .manage(data)
+        .manage(auth)
+        .manage(Metrics::default())
+        .manage(tiles)
+        .manage(Mutex::new(Replays::default()))
+}
+
+/// runs the api until rocket shuts down, from `/cmd/quit`, SIGINT or SIGTERM
+pub fn start_api(data: TData) {
+    let api_data = Arc::clone(&data);
+    rocket::tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(Config::from(Config::figment()).workers)
+        // NOTE: graceful shutdown depends on the "rocket-worker" prefix.
+        .thread_name("rocket-worker-thread")
+        .enable_all()
+        .build()
+        .expect("create tokio runtime")
+        .block_on(async move {
+            let _ = build(api_data)
+            .launch()
+            .await;
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alarm::{AlarmConfig, Alarms};
+    use crate::api::{build_with, utc_now, Data, TData};
+    use crate::auth::{Auth, Role, User};
+    use crate::command::CommandStatus;
+    use crate::event::EventKind;
+    use crate::interlock::{Interlock, InterlockConfig};
+    use crate::link::RadioState;
+    use crate::protocol::{Frame, Gps};
+    use crate::query;
+    use crate::store::{Record, Store};
+
+    use rocket::local::blocking::Client;
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::serde::json::{self, Value, json};
+    use std::env;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    fn frame(time: f32, alt: f32) -> Frame {
+        Frame {time, alt, balt: alt, vel: 0f32, w: 1f32, x: 0f32, y: 0f32, z: 0f32, gps: None}
+    }
+
+    /// an api client over a data struct holding `count` frames, 0.5s apart
+    fn client(count: usize) -> (Client, TData) {
+        let mut data = Data::new();
+        for i in 0..count {
+            data.record(&frame(i as f32 * 0.5, i as f32));
+        }
+
+        let data: TData = Arc::new(Mutex::new(data));
+        let client = Client::tracked(build_with(Arc::clone(&data), Auth::default())).expect("valid rocket instance");
+        return (client, data);
+    }
+
+    /// an api client with an operator token "op" and a viewer token "view"
+    fn secured(public_read: bool) -> (Client, TData) {
+        let auth = Auth {
+            public_read,
+            users: vec![
+                User {name: "rso".to_string(), token: "op".to_string(), role: Role::Operator},
+                User {name: "pad".to_string(), token: "view".to_string(), role: Role::Viewer},
+            ],
+        };
+
+        let data: TData = Arc::new(Mutex::new(Data::new()));
+        let client = Client::tracked(build_with(Arc::clone(&data), auth)).expect("valid rocket instance");
+        return (client, data);
+    }
+
+    fn bearer(token: &str) -> Header<'static> {
+        Header::new("Authorization", format!("Bearer {}", token))
+    }
+
+    fn get(client: &Client, uri: &str) -> Value {
+        let res = client.get(uri).dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        json::from_str(&res.into_string().expect("response body")).expect("json body")
+    }
+
+    #[test]
+    fn last_n() {
+        let (client, _) = client(4);
+        let res = get(&client, "/api/alt/-2");
+        assert_eq!(res, json!([[1.0, 2.0], [1.5, 3.0]]));
+    }
+
+    #[test]
+    fn time_range() {
+        let (client, _) = client(10);
+
+        let res = get(&client, "/api/alt?from=1&to=2");
+        assert_eq!(res["samples"], json!([[1.0, 2.0], [1.5, 3.0], [2.0, 4.0]]));
+
+        let res = get(&client, "/api/alt?since=3.5");
+        assert_eq!(res["samples"], json!([[4.0, 8.0], [4.5, 9.0]]));
+
+        let res = get(&client, "/api/alt?from=0&clock=utc");
+        assert_eq!(res["samples"].as_array().unwrap().len(), 10);
+
+        assert_eq!(client.get("/api/alt?clock=mars").dispatch().status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn cursor_polling() {
+        let (client, data) = client(3);
+
+        let res = get(&client, "/api/alt?since=0");
+        assert_eq!(res["samples"].as_array().unwrap().len(), 2);
+        let cursor = res["cursor"].as_str().unwrap().to_string();
+
+        // nothing new yet
+        let res = get(&client, &format!("/api/alt?cursor={}", cursor));
+        assert_eq!(res["samples"], json!([]));
+        let cursor = res["cursor"].as_str().unwrap().to_string();
+
+        data.lock().unwrap().record(&frame(1.5, 3f32));
+        data.lock().unwrap().record(&frame(2.0, 4f32));
+
+        let res = get(&client, &format!("/api/alt?cursor={}", cursor));
+        assert_eq!(res["samples"], json!([[1.5, 3.0], [2.0, 4.0]]));
+
+        let stale = query::encode_cursor(data.lock().unwrap().session + 1, 0);
+        assert_eq!(client.get(format!("/api/alt?cursor={}", stale)).dispatch().status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn max_points() {
+        let (client, _) = client(1000);
+
+        let res = get(&client, "/api/alt?from=0&max_points=100");
+        assert_eq!(res["samples"].as_array().unwrap().len(), 100);
+
+        let res = get(&client, "/api/alt/0?max_points=100&method=minmax");
+        assert_eq!(res.as_array().unwrap().len(), 100);
+
+        assert_eq!(client.get("/api/alt/0?max_points=10&method=median").dispatch().status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn aligned_rows() {
+        let (client, _) = client(4);
+
+        let res = get(&client, "/api/rows?channels=alt,balt&last=2");
+        assert_eq!(res["channels"], json!(["alt", "balt"]));
+        assert_eq!(res["rows"], json!([[1.0, 2.0, 2.0], [1.5, 3.0, 3.0]]));
+
+        let res = get(&client, "/api/rows?channels=alt,w&rate=4");
+        assert_eq!(res["rows"].as_array().unwrap().len(), 7);
+        assert_eq!(res["rows"][1], json!([0.25, 0.5, 1.0]));
+
+        assert_eq!(client.get("/api/rows?channels=alt,nope").dispatch().status(), Status::NotFound);
+        assert_eq!(client.get("/api/rows?channels=alt&rate=0").dispatch().status(), Status::BadRequest);
+        assert_eq!(client.get("/api/rows?channels=alt&rate=inf").dispatch().status(), Status::BadRequest);
+        assert_eq!(client.get("/api/rows?channels=alt&rate=NaN").dispatch().status(), Status::BadRequest);
+        assert_eq!(client.get("/api/rows?channels=alt&rate=1e9").dispatch().status(), Status::BadRequest);
+
+        // the data lock is still usable
+        assert_eq!(get(&client, "/api/rows?channels=alt&rate=4")["rows"].as_array().unwrap().len(), 7);
+    }
+
+    #[test]
+    fn ui_pages() {
+        let (client, _) = client(0);
+
+        let res = client.get("/dashboard").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let body = res.into_string().unwrap();
+        assert!(body.contains("/api/stream"));
+        assert!(body.contains("src=\"/view\""));
+
+        let res = client.get("/view").dispatch();
+        assert_eq!(res.content_type(), Some(ContentType::HTML));
+        // the embedded copy is tried before the CDN
+        let body = res.into_string().unwrap();
+        let local = body.find("/static/vendor/three/three.min.js").unwrap();
+        assert!(body.find("cdn.").map(|n| n > local).unwrap_or(true));
+        assert_eq!(client.get("/static/model.glb").dispatch().status(), Status::Ok);
+        assert_eq!(client.get("/static/nope.js").dispatch().status(), Status::NotFound);
+    }
+
+    #[test]
+    fn state_snapshot() {
+        let (empty, _) = client(0);
+        let res = get(&empty, "/api/state");
+        assert_eq!(res["channels"], json!({}));
+        assert_eq!(res["link"]["state"], "waiting");
+
+        let (client, data) = client(3);
+        let res = get(&client, "/api/state");
+        assert_eq!(res["channels"].as_object().unwrap().len(), 11);
+        assert_eq!(res["channels"]["alt"]["time"], 1.0);
+        assert_eq!(res["channels"]["alt"]["value"], 2.0);
+        assert_eq!(res["channels"]["alt"]["min"], 0.0);
+        assert_eq!(res["channels"]["alt"]["max"], 2.0);
+        assert!(res["channels"]["alt"]["age_ms"].as_u64().unwrap() < 1000);
+        assert_eq!(res["link"]["state"], "locked");
+        assert_eq!(res["link"]["frames"], 3);
+
+        // extremes are kept as frames arrive, NaN never becomes one
+        data.lock().unwrap().record(&frame(1.5, -4.0));
+        data.lock().unwrap().record(&frame(2.0, f32::NAN));
+        let res = get(&client, "/api/state");
+        assert_eq!(res["channels"]["alt"]["min"], -4.0);
+        assert_eq!(res["channels"]["alt"]["max"], 2.0);
+    }
+
+    #[rocket::async_test]
+    async fn live_stream() {
+        use rocket::local::asynchronous::Client;
+        use rocket::tokio::io::AsyncReadExt;
+
+        let data: TData = Arc::new(Mutex::new(Data::new()));
+        let client = Client::tracked(build_with(Arc::clone(&data), Auth::default())).await.expect("valid rocket instance");
+
+        assert_eq!(client.get("/api/stream?channels=nope").dispatch().await.status(), Status::NotFound);
+
+        let mut res = client.get("/api/stream?channels=alt&decimate=2").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+
+        for i in 0..3 {
+            data.lock().unwrap().record(&frame(i as f32, i as f32 * 10f32));
+        }
+
+        let mut body = String::new();
+        while body.matches("event:frame").count() < 2 || !body.ends_with("\n\n") {
+            let mut buf = [0u8; 256];
+            let n = res.read(&mut buf).await.expect("stream body");
+            body.push_str(std::str::from_utf8(&buf[..n]).unwrap());
+        }
+
+        assert!(body.contains("\"alt\":0.0"));
+        assert!(!body.contains("\"alt\":10.0"));
+        assert!(body.contains("\"alt\":20.0"));
+        assert!(!body.contains("\"vel\""));
+    }
+
+    #[rocket::async_test]
+    async fn flight_events() {
+        use rocket::local::asynchronous::Client;
+        use rocket::tokio::io::AsyncReadExt;
+
+        let data: TData = Arc::new(Mutex::new(Data::new()));
+        let client = Client::tracked(build_with(Arc::clone(&data), Auth::default())).await.expect("valid rocket instance");
+
+        // events are not decimated away
+        let mut res = client.get("/api/stream?channels=alt&decimate=100").dispatch().await;
+
+        for i in 0..4 {
+            let mut climbing = frame(i as f32 * 0.1, i as f32 * 2.0);
+            climbing.vel = 20.0;
+            data.lock().unwrap().record(&climbing);
+        }
+
+        let mut body = String::new();
+        while !body.contains("event:event") || !body.ends_with("\n\n") {
+            let mut buf = [0u8; 256];
+            let n = res.read(&mut buf).await.expect("stream body");
+            body.push_str(std::str::from_utf8(&buf[..n]).unwrap());
+        }
+        assert!(body.contains("\"kind\":\"liftoff\""));
+
+        let events: Value = client.get("/api/events").dispatch().await.into_json().await.unwrap();
+        assert_eq!(events.as_array().unwrap().len(), 1);
+        assert_eq!(events[0]["kind"], "liftoff");
+        assert_eq!(events[0]["time"], 0.0);
+        assert_eq!(events[0]["confidence"], "medium");
+    }
+
+    #[test]
+    fn channel_metadata() {
+        let (client, _) = client(3);
+
+        let res = get(&client, "/api/channels");
+        let channels = res.as_array().unwrap();
+        assert_eq!(channels.len(), 11);
+
+        let alt = channels.iter().find(|n| n["name"] == "alt").unwrap();
+        assert_eq!(alt["unit"], "m");
+        assert_eq!(alt["type"], "f32");
+        assert_eq!(alt["source"], "telemetry");
+        assert_eq!(alt["samples"], 3);
+        assert_eq!(alt["first_time"], 0.0);
+        assert_eq!(alt["last_time"], 1.0);
+    }
+
+    #[test]
+    fn unknown_channel() {
+        let (client, _) = client(3);
+
+        for uri in ["/api/nope/-1", "/api/nope?from=0", "/api/rows?channels=alt,nope"] {
+            let res = client.get(uri).dispatch();
+            assert_eq!(res.status(), Status::NotFound);
+
+            let body: Value = json::from_str(&res.into_string().unwrap()).unwrap();
+            assert!(body["error"].as_str().unwrap().contains("nope"));
+        }
+
+        let res = client.get("/api/alt/1/2/3").dispatch();
+        assert_eq!(res.status(), Status::NotFound);
+        let body: Value = json::from_str(&res.into_string().unwrap()).unwrap();
+        assert!(body["error"].is_string());
+    }
+
+    #[test]
+    fn export_download() {
+        let (client, _) = client(4);
+
+        let res = client.get("/api/export/csv?channels=alt,vel&from=0.5").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert!(res.headers().get_one("Content-Disposition").unwrap().contains(".csv"));
+        let body = res.into_string().unwrap();
+        assert_eq!(body.lines().count(), 4);
+        assert!(body.starts_with("time,rx_time,alt,vel\n0.5,"));
+
+        let res = client.get("/api/export/parquet").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert!(res.into_bytes().unwrap().starts_with(b"PAR1"));
+
+        assert_eq!(client.get("/api/export/xlsx").dispatch().status(), Status::NotFound);
+        assert_eq!(client.get("/api/export/csv?channels=nope").dispatch().status(), Status::NotFound);
+    }
+
+    #[test]
+    fn commands_need_operator() {
+        let (client, data) = secured(true);
+
+        // a prefetched link can no longer send anything
+        assert_eq!(client.get("/cmd/quit").dispatch().status(), Status::NotFound);
+
+        assert_eq!(client.post("/cmd/test/1").dispatch().status(), Status::Unauthorized);
+        assert_eq!(client.post("/cmd/test/1").header(bearer("nope")).dispatch().status(), Status::Unauthorized);
+        assert_eq!(client.post("/cmd/test/1").header(bearer("view")).dispatch().status(), Status::Forbidden);
+        assert!(data.lock().unwrap().commands.history.is_empty());
+
+        assert_eq!(client.post("/cmd/test/1").header(bearer("op")).dispatch().status(), Status::Accepted);
+        assert_eq!(data.lock().unwrap().commands.pending().len(), 1);
+
+        let res = client.post("/cmd/quit").header(bearer("view")).dispatch();
+        assert_eq!(res.status(), Status::Forbidden);
+        let body: Value = json::from_str(&res.into_string().unwrap()).unwrap();
+        assert!(body["error"].as_str().unwrap().contains("operator"));
+        assert!(!data.lock().unwrap().do_quit);
+    }
+
+    #[test]
+    fn private_reads() {
+        let (public, _) = secured(true);
+        assert_eq!(public.get("/api/state").dispatch().status(), Status::Ok);
+
+        let (client, _) = secured(false);
+        assert_eq!(client.get("/api/state").dispatch().status(), Status::Unauthorized);
+        assert_eq!(client.get("/api/alt/0").dispatch().status(), Status::Unauthorized);
+        assert_eq!(client.get("/api/state").header(bearer("view")).dispatch().status(), Status::Ok);
+        assert_eq!(client.get("/api/state").header(bearer("op")).dispatch().status(), Status::Ok);
+    }
+
+    #[test]
+    fn login_cookie() {
+        let (client, _) = secured(false);
+
+        assert_eq!(client.post("/api/login").body(r#"{"token": "nope"}"#).dispatch().status(), Status::Unauthorized);
+
+        let res = client.post("/api/login").body(r#"{"token": "view"}"#).dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let body: Value = json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(body, json!({"name": "pad", "role": "viewer"}));
+
+        // the tracked client sends the cookie back
+        assert_eq!(client.get("/api/state").dispatch().status(), Status::Ok);
+
+        assert_eq!(client.post("/api/logout").dispatch().status(), Status::NoContent);
+        assert_eq!(client.get("/api/state").dispatch().status(), Status::Unauthorized);
+    }
+
+    #[test]
+    fn command_audit() {
+        let (client, data) = secured(true);
+
+        let res = client.post("/cmd/fire/1").header(bearer("op")).dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+        let body: Value = json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(body["status"], "rejected");
+
+        client.post("/cmd/test/2.5").header(bearer("op")).dispatch();
+
+        // the radio thread sends it
+        let mut cmd = data.lock().unwrap().commands.pending()[0].clone();
+        cmd.status = CommandStatus::Sent;
+        data.lock().unwrap().update_command(cmd);
+
+        let res = get(&client, "/api/commands");
+        assert_eq!(res.as_array().unwrap().len(), 2);
+        assert_eq!(res[0]["name"], "fire");
+        assert!(res[0]["error"].as_str().unwrap().contains("unknown command"));
+        assert_eq!(res[1]["value"], 2.5);
+        assert_eq!(res[1]["client"], "rso");
+        assert_eq!(res[1]["status"], "sent");
+
+        let res = get(&client, "/api/commands?since=1");
+        assert_eq!(res.as_array().unwrap().len(), 1);
+        assert_eq!(get(&client, "/api/commands/2")["status"], "sent");
+        assert_eq!(client.get("/api/commands/9").dispatch().status(), Status::NotFound);
+
+        let res = client.get("/api/export/csv?table=commands").dispatch();
+        let body = res.into_string().unwrap();
+        assert!(body.starts_with("id,requested,name,value,client,status,attempts,sent,error\n"));
+        assert_eq!(body.lines().count(), 3);
+    }
+
+    #[test]
+    fn vehicle_model() {
+        let (client, data) = secured(true);
+        let dir = env::temp_dir().join(format!("gndapi-api-vehicle-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        data.lock().unwrap().config.vehicle.model = dir.join("model.glb");
+
+        let res = get(&client, "/api/vehicle");
+        assert_eq!(res["custom_model"], false);
+        assert_eq!(res["mounting"]["yaw"], -90.0);
+
+        // the stock model until one is uploaded
+        let res = client.get("/api/vehicle/model").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert!(res.into_bytes().unwrap().starts_with(b"glTF"));
+
+        let mut glb = b"glTF".to_vec();
+        glb.extend(2u32.to_le_bytes());
+        glb.extend(16u32.to_le_bytes());
+        glb.extend(b"    ");
+
+        assert_eq!(client.put("/api/vehicle/model").header(bearer("view")).body(glb.clone()).dispatch().status(), Status::Forbidden);
+        assert_eq!(client.put("/api/vehicle/model").header(bearer("op")).body("{}").dispatch().status(), Status::BadRequest);
+
+        let res = client.put("/api/vehicle/model").header(bearer("op")).body(glb.clone()).dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert!(res.into_string().unwrap().contains("\"custom_model\":true"));
+        assert_eq!(client.get("/api/vehicle/model").dispatch().into_bytes().unwrap(), glb);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn command_defs() {
+        let (client, data) = secured(true);
+        data.lock().unwrap().interlock = Interlock::new(InterlockConfig {hazardous: vec!["test".to_string()], arm_timeout: 10.0, require_locked: false});
+
+        let res = get(&client, "/api/commands/defs");
+        let defs = res.as_array().unwrap();
+        assert_eq!(defs.len(), 2);
+        assert_eq!(defs[0]["name"], "quit");
+        assert_eq!(defs[0]["argument"], "none");
+        assert_eq!(defs[1]["hazardous"], true);
+        assert_eq!(defs[1]["uplink"], true);
+
+        let res = client.get("/console").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.content_type(), Some(ContentType::HTML));
+    }
+
+    #[test]
+    fn hazardous_interlock() {
+        let (client, data) = secured(true);
+        data.lock().unwrap().interlock = Interlock::new(InterlockConfig {hazardous: vec!["test".to_string()], arm_timeout: 10.0, require_locked: true});
+
+        let res = client.post("/cmd/test/1").header(bearer("op")).dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+        assert!(res.into_string().unwrap().contains("arm it first"));
+
+        assert_eq!(client.post("/cmd/arm/test/1").header(bearer("view")).dispatch().status(), Status::Forbidden);
+        assert_eq!(client.post("/cmd/arm/test/1").header(bearer("op")).dispatch().status(), Status::Accepted);
+
+        let res = get(&client, "/api/interlock");
+        assert_eq!(res["hazardous"], json!(["test"]));
+        assert_eq!(res["armed"]["name"], "test");
+        assert_eq!(res["armed"]["by"], "rso");
+        assert!(res["remaining_s"].as_f64().unwrap() > 9.0);
+
+        // no frames yet, so the link is not locked
+        let res = client.post("/cmd/test/1").header(bearer("op")).dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+        assert!(res.into_string().unwrap().contains("waiting"));
+
+        data.lock().unwrap().record(&frame(0.0, 0.0));
+        assert_eq!(client.post("/cmd/test/2").header(bearer("op")).dispatch().status(), Status::BadRequest);
+        assert_eq!(client.post("/cmd/test/1").header(bearer("op")).dispatch().status(), Status::Accepted);
+        assert!(get(&client, "/api/interlock")["armed"].is_null());
+
+        let statuses: Vec<Value> = get(&client, "/api/commands").as_array().unwrap().iter().map(|n| n["status"].clone()).collect();
+        assert_eq!(statuses, vec![json!("rejected"), json!("armed"), json!("rejected"), json!("rejected"), json!("queued")]);
+
+        client.post("/cmd/arm/test/1").header(bearer("op")).dispatch();
+        client.post("/cmd/disarm").header(bearer("op")).dispatch();
+        assert!(get(&client, "/api/interlock")["armed"].is_null());
+    }
+
+    #[test]
+    fn alarm_ack() {
+        let (client, data) = secured(true);
+        data.lock().unwrap().alarms = Alarms::new(&AlarmConfig::default());
+        assert_eq!(get(&client, "/api/alarms/limits").as_array().unwrap().len(), 4);
+
+        // falling at 20 m/s under the main
+        data.lock().unwrap().alarms.event(EventKind::Main);
+        let mut fast = frame(0.0, 200.0);
+        fast.vel = -20.0;
+        data.lock().unwrap().record(&fast);
+
+        let res = get(&client, "/api/alarms");
+        assert_eq!(res.as_array().unwrap().len(), 1);
+        assert_eq!(res[0]["name"], "descent rate under main");
+        assert_eq!(res[0]["severity"], "red");
+        assert_eq!(res[0]["active"], true);
+        let id = res[0]["id"].as_u64().unwrap();
+
+        assert_eq!(client.post(format!("/api/alarms/{}/ack", id)).dispatch().status(), Status::Unauthorized);
+        assert_eq!(client.post(format!("/api/alarms/{}/ack", id)).header(bearer("view")).dispatch().status(), Status::Forbidden);
+        assert_eq!(client.post("/api/alarms/99/ack").header(bearer("op")).dispatch().status(), Status::NotFound);
+
+        let res = client.post(format!("/api/alarms/{}/ack", id)).header(bearer("op")).dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res: Value = json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(res["acked"]["by"], "rso");
+
+        // acknowledged but still out of limits
+        assert_eq!(get(&client, "/api/alarms")[0]["acked"]["by"], "rso");
+
+        data.lock().unwrap().record(&frame(0.5, 190.0));
+        assert_eq!(get(&client, "/api/alarms"), json!([]));
+        assert_eq!(get(&client, "/api/alarms?all=true").as_array().unwrap().len(), 1);
+
+        data.lock().unwrap().record(&fast);
+        let res = client.post("/api/alarms/ack").header(bearer("op")).dispatch();
+        assert_eq!(json::from_str::<Value>(&res.into_string().unwrap()).unwrap().as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn radio_settings() {
+        let (client, data) = secured(true);
+
+        assert_eq!(get(&client, "/api/radio")["settings"]["power"], 14.0);
+
+        assert_eq!(client.put("/api/radio").header(bearer("view")).body(r#"{"power": 10}"#).dispatch().status(), Status::Forbidden);
+
+        let res = client.put("/api/radio").header(bearer("op")).body(r#"{"power": 30}"#).dispatch();
+        assert_eq!(res.status(), Status::BadRequest);
+        assert!(res.into_string().unwrap().contains("power"));
+
+        let res = client.put("/api/radio").header(bearer("op")).body(r#"{"power": 10, "modulation": "fsk2"}"#).dispatch();
+        assert_eq!(res.status(), Status::Accepted);
+
+        // applied by the radio thread, not the request
+        let res = get(&client, "/api/radio");
+        assert_eq!(res["settings"]["power"], 14.0);
+        assert_eq!(res["pending"]["settings"]["power"], 10.0);
+        assert_eq!(res["pending"]["client"], "rso");
+
+        let mut data = data.lock().unwrap();
+        let mut change = data.tuning.pending.take().unwrap();
+        change.applied = Some(utc_now());
+        data.update_tuning(change);
+        drop(data);
+
+        let res = get(&client, "/api/radio");
+        assert_eq!(res["settings"]["modulation"], "fsk2");
+        assert!(res["pending"].is_null());
+    }
+
+    #[test]
+    fn fusion_estimates() {
+        let (client, data) = client(0);
+        assert_eq!(client.get("/api/fusion/now").dispatch().status(), Status::NotFound);
+        assert_eq!(get(&client, "/api/fusion"), json!([]));
+
+        for i in 0..20 {
+            let mut climbing = frame(i as f32 * 0.5, i as f32 * 5.0);
+            climbing.vel = 10.0;
+            data.lock().unwrap().record(&climbing);
+        }
+
+        let res = get(&client, "/api/fusion/now");
+        assert!(res["coasting"].as_f64().unwrap() >= 0.0);
+        assert!((res["vel"].as_f64().unwrap() - 10.0).abs() < 1.0);
+
+        assert_eq!(get(&client, "/api/fusion").as_array().unwrap().len(), 20);
+        let res = get(&client, "/api/fusion?rate=1&from=2&to=6&smooth=true");
+        assert_eq!(res.as_array().unwrap().len(), 5);
+        assert_eq!(res[0]["time"], 2.0);
+        assert!(res[0]["alt_sd"].as_f64().unwrap() > 0.0);
+        assert_eq!(client.get("/api/fusion?rate=-1").dispatch().status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn replay_session() {
+        let dir = env::temp_dir().join(format!("gndapi-api-replay-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut store = Store::create(&dir, 42, 1000.0).unwrap();
+        for i in 0..10 {
+            store.append(&Record::Frame {rx_time: 1000.0 + i as f64, frame: frame(i as f32, i as f32 * 10.0)});
+        }
+        store.close().unwrap();
+
+        let mut data = Data::new();
+        data.config.storage.session_dir = dir.clone();
+        let client = Client::tracked(build_with(Arc::new(Mutex::new(data)), Auth::default())).expect("valid rocket instance");
+
+        assert_eq!(get(&client, "/api/sessions")[0]["id"], 42);
+        assert_eq!(client.post("/api/sessions/7/replay").dispatch().status(), Status::NotFound);
+
+        let res = client.post("/api/sessions/42/replay").dispatch();
+        assert_eq!(res.status(), Status::Created);
+        let opened: Value = json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(opened["end"], 9.0);
+        assert!(opened["markers"].is_array());
+        let id = opened["id"].as_u64().unwrap();
+
+        let res = client.put(format!("/api/replay/{}", id)).body(r#"{"position": 4.5}"#).dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let res = get(&client, &format!("/api/replay/{}", id));
+        assert_eq!(res["position"], 4.5);
+        assert_eq!(res["values"]["alt"], 40.0);
+
+        assert_eq!(client.put(format!("/api/replay/{}", id)).body(r#"{"speed": 50}"#).dispatch().status(), Status::BadRequest);
+
+        assert_eq!(client.get(format!("/api/replay/{}/rows?channels=alt&rate=inf", id)).dispatch().status(), Status::BadRequest);
+        assert_eq!(client.get(format!("/api/replay/{}/rows?channels=alt&rate=1e9", id)).dispatch().status(), Status::BadRequest);
+
+        // the replays are still usable
+        let res = get(&client, &format!("/api/replay/{}/rows?channels=alt&rate=0.5", id));
+        assert_eq!(res["rows"].as_array().unwrap().len(), 5);
+
+        let res = get(&client, &format!("/api/replay/{}/fusion?rate=1", id));
+        assert_eq!(res.as_array().unwrap().len(), 10);
+        assert_eq!(client.get(format!("/api/replay/{}/fusion?rate=0", id)).dispatch().status(), Status::BadRequest);
+
+        assert_eq!(client.delete(format!("/api/replay/{}", id)).dispatch().status(), Status::NoContent);
+        assert_eq!(client.get(format!("/api/replay/{}", id)).dispatch().status(), Status::NotFound);
+        let res = client.get("/replay").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.content_type(), Some(ContentType::HTML));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn map_status() {
+        let (client, data) = client(2);
+
+        let res = get(&client, "/api/map");
+        assert!(res["pad"].is_null());
+        assert!(res["last"].is_null());
+        assert!(res["tiles"].is_null());
+
+        let mut fix = frame(1.5, 3.0);
+        fix.gps = Some(Gps {lat: 47.5, long: -122.25, fix: 3.0, quality: 1.0});
+        data.lock().unwrap().record(&fix);
+
+        let res = get(&client, "/api/map");
+        assert_eq!(res["pad"], json!({"lat": 47.5, "long": -122.25}));
+        assert_eq!(res["last"]["last"]["time"], 1.5);
+        assert!(res["last"]["range"].is_null());
+
+        let res = get(&client, "/api/rows?channels=lat,long");
+        assert_eq!(res["rows"], json!([[0.0, null, null], [0.5, null, null], [1.5, 47.5, -122.25]]));
+
+        assert_eq!(client.get("/api/tiles/0/0/0").dispatch().status(), Status::NotFound);
+        let res = client.get("/map").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.content_type(), Some(ContentType::HTML));
+    }
+
+    #[test]
+    fn health_check() {
+        let (client, data) = secured(false);
+
+        // no token needed
+        let res = client.get("/api/health").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        let body: Value = json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(body["status"], "WARN");
+        assert_eq!(body["radio"]["state"], "starting");
+        assert_eq!(body["link"]["state"], "waiting");
+        assert!(body["storage"]["path"].is_null());
+
+        data.lock().unwrap().radio = RadioState::Stopped;
+        let res = client.get("/api/health").dispatch();
+        assert_eq!(res.status(), Status::ServiceUnavailable);
+        let body: Value = json::from_str(&res.into_string().unwrap()).unwrap();
+        assert_eq!(body["status"], "FAIL");
+    }
+
+    #[test]
+    fn prometheus_metrics() {
+        let (client, _) = client(3);
+        get(&client, "/api/state");
+        get(&client, "/api/alt/0");
+
+        let res = client.get("/metrics").dispatch();
+        assert_eq!(res.status(), Status::Ok);
+        assert!(res.content_type().unwrap().to_string().starts_with("text/plain"));
+
+        let body = res.into_string().unwrap();
+        assert!(body.contains("gndapi_frames_received_total 3\n"));
+        assert!(body.contains("gndapi_channel_value{channel=\"alt\",unit=\"m\"} 2\n"));
+        assert!(body.contains("gndapi_http_request_duration_seconds_count{method=\"GET\",route=\"/api/state\"} 1\n"));
+        assert!(body.contains("route=\"/api/<field>/<points>\""));
+
+        // scrapers need a token once reads do
+        let (client, _) = secured(false);
+        assert_eq!(client.get("/metrics").dispatch().status(), Status::Unauthorized);
+        assert_eq!(client.get("/metrics").header(bearer("view")).dispatch().status(), Status::Ok);
+    }
+}
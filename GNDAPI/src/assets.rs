@@ -0,0 +1,55 @@
+use rocket::http::ContentType;
+
+use std::path::Path;
+
+// `TEMPLATES` and `PUBLIC`, generated by build.rs
+include!(concat!(env!("OUT_DIR"), "/embedded.rs"));
+
+/// an embedded file from `table` by its path relative to the embedded directory
+pub fn get(table: &'static [(&'static str, &'static [u8])], path: &Path) -> Option<(ContentType, &'static [u8])> {
+    // keys always use `/`, whatever the platform the binary was built on
+    let key = path.components()
+        .map(|n| n.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<String>>()
+        .join("/");
+
+    let (_, bytes) = table.iter().find(|n| n.0 == key)?;
+
+    let content_type = path.extension()
+        .and_then(|n| n.to_str())
+        .and_then(ContentType::from_extension)
+        .unwrap_or(ContentType::Binary);
+
+    Some((content_type, bytes))
+}
+
+/// a page from `templates/`
+pub fn page(name: &str) -> Option<(ContentType, &'static [u8])> {
+    get(TEMPLATES, Path::new(&format!("{}.html", name)))
+}
+
+/// a file from `public/`
+pub fn public(path: &Path) -> Option<(ContentType, &'static [u8])> {
+    get(PUBLIC, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assets::{page, public};
+
+    use rocket::http::ContentType;
+    use std::path::Path;
+
+    #[test]
+    fn embedded() {
+        let (content_type, bytes) = page("view").unwrap();
+        assert_eq!(content_type, ContentType::HTML);
+        assert!(String::from_utf8_lossy(bytes).contains("/api/vehicle"));
+
+        assert!(public(Path::new("model.glb")).is_some());
+        assert!(String::from_utf8_lossy(page("dashboard").unwrap().1).contains("/static/alarms.js"));
+        assert_eq!(public(Path::new("alarms.js")).unwrap().0, ContentType::JavaScript);
+        assert!(public(Path::new("../Cargo.toml")).is_none());
+        assert!(page("nope").is_none());
+    }
+}
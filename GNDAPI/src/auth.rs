@@ -0,0 +1,159 @@
+use rocket::{
+    http::{Cookie, CookieJar, SameSite, Status},
+    request::{FromRequest, Outcome},
+    Request,
+};
+use serde::{Serialize, Deserialize};
+
+/// cookie `/api/login` stores the token in, for browsers that can not set headers on `EventSource`
+pub const TOKEN_COOKIE: &str = "token";
+
+/// what a token is allowed to do, each role can do everything the ones before it can
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// read telemetry
+    Viewer,
+    /// send commands to the vehicle and the ground station
+    Operator,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct User {
+    /// shown in logs, never the token itself
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub token: String,
+    pub role: Role,
+}
+
+/// the `[auth]` table of the station config
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Auth {
+    /// telemetry read endpoints work without a token
+    pub public_read: bool,
+    pub users: Vec<User>,
+}
+
+impl Default for Auth {
+    fn default() -> Auth {
+        Auth {
+            public_read: true,
+            users: vec![],
+        }
+    }
+}
+
+impl Auth {
+    /// finds the user holding `token`
+    pub fn user(&self, token: &str) -> Option<&User> {
+        self.users.iter().find(|n| !n.token.is_empty() && same(n.token.as_bytes(), token.as_bytes()))
+    }
+
+    /// the user a request authenticates as, from a `Authorization: Bearer` header or the token cookie
+    pub fn identify(&self, req: &Request) -> Option<&User> {
+        let token = match req.headers().get_one("Authorization") {
+            Some(n) => n.strip_prefix("Bearer ").map(|n| n.trim().to_string()),
+            None => req.cookies().get(TOKEN_COOKIE).map(|n| n.value().to_string()),
+        };
+
+        token.and_then(|n| self.user(&n))
+    }
+
+    /// stores a token in the login cookie
+    pub fn login(jar: &CookieJar, token: &str) {
+        let cookie = Cookie::build(TOKEN_COOKIE, token.to_string())
+            .http_only(true)
+            .same_site(SameSite::Strict)
+            .path("/")
+            .finish();
+        jar.add(cookie);
+    }
+
+    pub fn logout(jar: &CookieJar) {
+        jar.remove(Cookie::named(TOKEN_COOKIE));
+    }
+}
+
+/// compares tokens without returning early, so timing does not leak how much of a guess was right
+fn same(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// checks a request holds at least `role`
+///
+/// fails with a 401 for a missing or unknown token and a 403 for a role that is too low
+fn require<'r>(req: &'r Request<'_>, role: Role) -> Outcome<Option<User>, ()> {
+    let auth = match req.rocket().state::<Auth>() {
+        Some(n) => n,
+        None => {return Outcome::Failure((Status::InternalServerError, ()))},
+    };
+
+    match auth.identify(req) {
+        Some(user) if user.role >= role => Outcome::Success(Some(user.clone())),
+        Some(_) => Outcome::Failure((Status::Forbidden, ())),
+        None if role == Role::Viewer && auth.public_read => Outcome::Success(None),
+        None => Outcome::Failure((Status::Unauthorized, ())),
+    }
+}
+
+/// request guard for telemetry reads, passes anyone with a token or everyone when reads are public
+pub struct Viewer;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Viewer {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        require(req, Role::Viewer).map(|_| Viewer)
+    }
+}
+
+/// request guard for commands
+pub struct Operator(pub User);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Operator {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match require(req, Role::Operator) {
+            Outcome::Success(Some(user)) => Outcome::Success(Operator(user)),
+            Outcome::Success(None) => Outcome::Failure((Status::Unauthorized, ())),
+            Outcome::Failure(n) => Outcome::Failure(n),
+            Outcome::Forward(n) => Outcome::Forward(n),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::{Auth, Role, User};
+
+    #[test]
+    fn lookup() {
+        let auth = Auth {public_read: false, users: vec![
+            User {name: "rso".to_string(), token: "abc".to_string(), role: Role::Operator},
+            User {name: "anon".to_string(), token: "".to_string(), role: Role::Operator},
+        ]};
+
+        assert_eq!(auth.user("abc").unwrap().name, "rso");
+        assert!(auth.user("ab").is_none());
+        assert!(auth.user("abcd").is_none());
+        assert!(auth.user("").is_none());
+    }
+
+    #[test]
+    fn roles_ordered() {
+        assert!(Role::Operator > Role::Viewer);
+
+        let user = User {name: "rso".to_string(), token: "abc".to_string(), role: Role::Operator};
+        assert!(!rocket::serde::json::to_string(&user).unwrap().contains("abc"));
+    }
+}
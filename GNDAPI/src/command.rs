@@ -0,0 +1,322 @@
+use crate::protocol::{encode_command, COMMAND_SIZE};
+
+use ArmlabRadio::radio_serial::{Radio, RadioError};
+
+use serde::{Serialize, Deserialize};
+
+/// transmit attempts before an uplink command is marked failed
+pub const TRANSMIT_ATTEMPTS: usize = 3;
+
+/// static description of a command
+pub struct CommandDef {
+    pub name: &'static str,
+    /// uplink opcode, `None` for commands handled by the ground station itself
+    pub opcode: Option<u8>,
+    /// inclusive range of the argument
+    pub min: f32,
+    pub max: f32,
+    pub description: &'static str,
+}
+
+/// every command `/cmd` accepts
+pub const COMMANDS: [CommandDef; 2] = [
+    CommandDef {name: "quit", opcode: None, min: 0f32, max: 0f32, description: "shut down the ground station"},
+    CommandDef {name: "test", opcode: Some(2), min: f32::MIN, max: f32::MAX, description: "uplink test, the vehicle ignores the argument"},
+];
+
+pub fn lookup(name: &str) -> Option<&'static CommandDef> {
+    COMMANDS.iter().find(|n| n.name == name)
+}
+
+/// a command as listed by `/api/commands/defs` for the console
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CommandInfo {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// sent to the vehicle, rather than carried out by the ground station
+    pub uplink: bool,
+    /// `f32`, or `none` for commands that take no argument
+    pub argument: &'static str,
+    /// inclusive range of the argument, none where it is unbounded
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+    /// needs an arm step first, see `Interlock`
+    pub hazardous: bool,
+}
+
+impl CommandDef {
+    pub fn info(&self, hazardous: bool) -> CommandInfo {
+        let takes_value = self.min != self.max;
+        CommandInfo {
+            name: self.name,
+            description: self.description,
+            uplink: self.opcode.is_some(),
+            argument: if takes_value {"f32"} else {"none"},
+            min: if takes_value && self.min > f32::MIN {Some(self.min)} else {None},
+            max: if takes_value && self.max < f32::MAX {Some(self.max)} else {None},
+            hazardous,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandStatus {
+    /// failed validation, never queued
+    Rejected,
+    /// first step of a hazardous command, see `Interlock`
+    Armed,
+    /// waiting for the radio thread
+    Queued,
+    /// carried out by the ground station
+    Executed,
+    /// transmitted, the vehicle sends no acknowledgement
+    Sent,
+    /// every transmit attempt failed
+    Failed,
+}
+
+/// outcome of a single transmit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attempt {
+    /// UTC seconds
+    pub time: f64,
+    pub error: Option<String>,
+}
+
+/// a command request and everything that happened to it, the audit log entry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Command {
+    pub id: u64,
+    pub name: String,
+    pub value: f32,
+    /// user name and address of whoever sent it
+    pub client: String,
+    /// UTC seconds
+    pub requested: f64,
+    pub status: CommandStatus,
+    /// why the command was rejected or failed
+    pub error: Option<String>,
+    pub attempts: Vec<Attempt>,
+}
+
+impl Command {
+    /// UTC seconds of the last successful transmit
+    pub fn sent(&self) -> Option<f64> {
+        self.attempts.iter().rev().find(|n| n.error.is_none()).map(|n| n.time)
+    }
+}
+
+/// checks a command against `COMMANDS`
+pub fn validate(name: &str, value: f32) -> Result<&'static CommandDef, String> {
+    let def = match lookup(name) {
+        Some(n) => n,
+        None => {return Err(format!("unknown command \"{}\"", name))},
+    };
+
+    if !value.is_finite() || value < def.min || value > def.max {
+        return Err(format!("{} is out of range for {}, expected {} to {}", value, name, def.min, def.max));
+    }
+
+    return Ok(def);
+}
+
+/// every command requested this session, in order
+#[derive(Default)]
+pub struct CommandLog {
+    pub history: Vec<Command>,
+}
+
+impl CommandLog {
+    /// validates and logs a request, uplink commands are queued for the radio thread
+    pub fn request(&mut self, name: &str, value: f32, client: &str, now: f64) -> &Command {
+        let mut cmd = Command {
+            id: self.history.last().map(|n| n.id + 1).unwrap_or(1),
+            name: name.to_string(),
+            value,
+            client: client.to_string(),
+            requested: now,
+            status: CommandStatus::Queued,
+            error: None,
+            attempts: vec![],
+        };
+
+        match validate(name, value) {
+            Ok(def) => {
+                if def.opcode.is_none() {
+                    cmd.status = CommandStatus::Executed;
+                }
+            },
+            Err(n) => {
+                cmd.status = CommandStatus::Rejected;
+                cmd.error = Some(n);
+            },
+        };
+
+        self.history.push(cmd);
+        return self.history.last().unwrap();
+    }
+
+    /// commands waiting to be transmitted, oldest first
+    pub fn pending(&self) -> Vec<Command> {
+        self.history.iter().filter(|n| n.status == CommandStatus::Queued).cloned().collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Command> {
+        self.history.iter().find(|n| n.id == id)
+    }
+
+    /// rejects a logged request that passed validation, e.g. because of the interlock
+    pub fn reject(&mut self, id: u64, reason: String) -> Option<&Command> {
+        let cmd = self.history.iter_mut().find(|n| n.id == id)?;
+        cmd.status = CommandStatus::Rejected;
+        cmd.error = Some(reason);
+        Some(cmd)
+    }
+
+    /// replaces the entry with the same id, or adds it when replaying a session log
+    pub fn update(&mut self, cmd: Command) {
+        match self.history.iter_mut().find(|n| n.id == cmd.id) {
+            Some(n) => {*n = cmd;},
+            None => {self.history.push(cmd);},
+        };
+    }
+}
+
+/// anything that can put a command on the air
+///
+/// implemented for the serial `Radio`, and by mock radios in tests
+pub trait Uplink {
+    fn transmit(&mut self, buf: &[u8]) -> Result<(), RadioError>;
+
+    /// resyncs the serial link after a failed transmit
+    fn recover(&mut self) {}
+}
+
+impl Uplink for Radio {
+    fn transmit(&mut self, buf: &[u8]) -> Result<(), RadioError> {
+        Radio::transmit(self, buf)
+    }
+
+    fn recover(&mut self) {
+        self.sync(10).ok();
+    }
+}
+
+/// transmits a queued command, retrying up to `TRANSMIT_ATTEMPTS` times
+///
+/// `now` is called for the timestamp of each attempt
+pub fn transmit<U: Uplink, F: FnMut() -> f64>(radio: &mut U, cmd: &mut Command, mut now: F) {
+    let opcode = match lookup(&cmd.name).and_then(|n| n.opcode) {
+        Some(n) => n,
+        None => {
+            cmd.status = CommandStatus::Failed;
+            cmd.error = Some(format!("{} is not an uplink command", cmd.name));
+            return;
+        }
+    };
+
+    let buf: [u8; COMMAND_SIZE] = encode_command(opcode, cmd.value);
+
+    for _ in 0..TRANSMIT_ATTEMPTS {
+        match radio.transmit(&buf) {
+            Ok(_) => {
+                cmd.attempts.push(Attempt {time: now(), error: None});
+                cmd.status = CommandStatus::Sent;
+                cmd.error = None;
+                return;
+            },
+            Err(n) => {
+                cmd.attempts.push(Attempt {time: now(), error: Some(format!("{:?}", n))});
+                radio.recover();
+            },
+        };
+    }
+
+    cmd.status = CommandStatus::Failed;
+    cmd.error = Some(format!("transmit failed {} times", TRANSMIT_ATTEMPTS));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::{lookup, transmit, CommandLog, CommandStatus, Uplink, TRANSMIT_ATTEMPTS};
+
+    use ArmlabRadio::radio_serial::RadioError;
+
+    /// fails the first `failures` transmits
+    struct MockUplink {
+        failures: usize,
+        sent: Vec<Vec<u8>>,
+    }
+
+    impl Uplink for MockUplink {
+        fn transmit(&mut self, buf: &[u8]) -> Result<(), RadioError> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(RadioError::WriteError);
+            }
+            self.sent.push(buf.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn info() {
+        let quit = lookup("quit").unwrap().info(false);
+        assert_eq!(quit.argument, "none");
+        assert!(!quit.uplink);
+
+        let test = lookup("test").unwrap().info(true);
+        assert_eq!((test.argument, test.min, test.max), ("f32", None, None));
+        assert!(test.uplink && test.hazardous);
+    }
+
+    #[test]
+    fn validation() {
+        let mut log = CommandLog::default();
+
+        assert_eq!(log.request("test", 1.0, "rso", 10.0).status, CommandStatus::Queued);
+        assert_eq!(log.request("quit", 0.0, "rso", 10.0).status, CommandStatus::Executed);
+
+        let cmd = log.request("fire", 1.0, "rso", 10.0).clone();
+        assert_eq!(cmd.status, CommandStatus::Rejected);
+        assert!(cmd.error.unwrap().contains("fire"));
+
+        assert_eq!(log.request("test", f32::NAN, "rso", 10.0).status, CommandStatus::Rejected);
+
+        assert_eq!(log.history.iter().map(|n| n.id).collect::<Vec<u64>>(), vec![1, 2, 3, 4]);
+        assert_eq!(log.pending().len(), 1);
+    }
+
+    #[test]
+    fn retries() {
+        let mut log = CommandLog::default();
+        let mut cmd = log.request("test", 1.5, "rso", 10.0).clone();
+
+        let mut radio = MockUplink {failures: 2, sent: vec![]};
+        transmit(&mut radio, &mut cmd, || 11.0);
+        assert_eq!(cmd.status, CommandStatus::Sent);
+        assert_eq!(cmd.attempts.len(), 3);
+        assert_eq!(cmd.sent(), Some(11.0));
+        assert_eq!(radio.sent, vec![vec![2, 0, 0, 0xc0, 0x3f]]);
+
+        let mut cmd = log.request("test", 1.5, "rso", 10.0).clone();
+        let mut radio = MockUplink {failures: TRANSMIT_ATTEMPTS, sent: vec![]};
+        transmit(&mut radio, &mut cmd, || 11.0);
+        assert_eq!(cmd.status, CommandStatus::Failed);
+        assert_eq!(cmd.attempts.len(), TRANSMIT_ATTEMPTS);
+        assert!(cmd.attempts.iter().all(|n| n.error.is_some()));
+    }
+
+    #[test]
+    fn update_replaces() {
+        let mut log = CommandLog::default();
+        let mut cmd = log.request("test", 1.0, "rso", 10.0).clone();
+
+        cmd.status = CommandStatus::Sent;
+        log.update(cmd.clone());
+        assert_eq!(log.history.len(), 1);
+        assert_eq!(log.get(cmd.id).unwrap().status, CommandStatus::Sent);
+        assert!(log.pending().is_empty());
+    }
+}
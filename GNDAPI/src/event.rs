@@ -0,0 +1,523 @@
+use crate::protocol::Frame;
+
+use serde::{Serialize, Deserialize};
+
+/// standard gravity, m/s^2
+pub const GRAVITY: f64 = 9.81;
+
+/// change of the descent rate per second under which it counts as settled on a parachute, m/s^2
+pub const SETTLE_ACCEL: f64 = 2.0;
+
+/// the `[events]` table, thresholds of the flight event detector
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventConfig {
+    /// frames in a row a condition has to hold for before it counts
+    pub confirm: usize,
+    /// vertical velocity that counts as lifted off, m/s
+    pub liftoff_velocity: f64,
+    /// height above the pad that counts as lifted off, m. deployments are only detected above it
+    pub liftoff_alt: f64,
+    /// fall under the highest altitude that confirms apogee, m
+    pub apogee_drop: f64,
+    /// slowing of the descent rate that counts as a parachute opening, m/s
+    pub deploy_drop: f64,
+    /// speed under which the vehicle counts as still, m/s
+    pub landing_velocity: f64,
+    /// seconds it has to stay still to count as landed
+    pub landing_time: f64,
+}
+
+impl Default for EventConfig {
+    fn default() -> EventConfig {
+        EventConfig {
+            confirm: 3,
+            liftoff_velocity: 15.0,
+            liftoff_alt: 10.0,
+            apogee_drop: 5.0,
+            deploy_drop: 5.0,
+            landing_velocity: 2.0,
+            landing_time: 3.0,
+        }
+    }
+}
+
+impl EventConfig {
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors: Vec<String> = vec![];
+
+        if self.confirm == 0 {
+            errors.push(String::from("confirm must be at least 1"));
+        }
+        let positive = [
+            ("liftoff_velocity", self.liftoff_velocity),
+            ("liftoff_alt", self.liftoff_alt),
+            ("apogee_drop", self.apogee_drop),
+            ("deploy_drop", self.deploy_drop),
+            ("landing_velocity", self.landing_velocity),
+            ("landing_time", self.landing_time),
+        ];
+        for (name, value) in positive {
+            if !(value > 0.0) || !value.is_finite() {
+                errors.push(format!("{} must be positive", name));
+            }
+        }
+        if self.landing_velocity >= self.liftoff_velocity {
+            errors.push(format!("landing_velocity ({}) must be under liftoff_velocity ({})", self.landing_velocity, self.liftoff_velocity));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(errors)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Liftoff,
+    Burnout,
+    Apogee,
+    Drogue,
+    Main,
+    Landing,
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::Liftoff => "liftoff",
+            EventKind::Burnout => "burnout",
+            EventKind::Apogee => "apogee",
+            EventKind::Drogue => "drogue",
+            EventKind::Main => "main",
+            EventKind::Landing => "landing",
+        }
+    }
+}
+
+/// how sure the detector is of an event, ordered from least to most
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+}
+
+/// a detected flight event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlightEvent {
+    pub kind: EventKind,
+    /// vehicle time the event happened at, seconds, earlier than the frame that confirmed it
+    pub time: f32,
+    /// ground receive time of the frame that confirmed it, UTC seconds
+    pub detected: f64,
+    /// fused altitude and vertical velocity at `time`
+    pub alt: f32,
+    pub vel: f32,
+    pub confidence: Confidence,
+    /// what the detection was based on
+    pub detail: String,
+}
+
+/// where the detector thinks the flight is
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    Pad,
+    Boost,
+    Coast,
+    /// past apogee, waiting for the drogue
+    Descent,
+    Drogue,
+    Main,
+    Landed,
+}
+
+/// the parts of a frame the detector looks at
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    time: f32,
+    alt: f32,
+    vel: f32,
+}
+
+impl Sample {
+    fn descent(&self) -> f64 {
+        -self.vel as f64
+    }
+}
+
+/// finds flight events in the frames as they arrive, one flight per session
+pub struct Detector {
+    pub config: EventConfig,
+    pub phase: Phase,
+    /// oldest first
+    pub events: Vec<FlightEvent>,
+    /// averaged altitude while on the pad
+    pad_alt: Option<f64>,
+    last: Option<Sample>,
+    /// frames in a row the condition of the current phase has held, and the first of them
+    run: usize,
+    run_start: Option<Sample>,
+    /// frames in a row the descent rate has been steady
+    settled: usize,
+    settle_start: Option<Sample>,
+    /// fastest climb, highest point and first frame with a falling velocity of the flight
+    peak_vel: Option<Sample>,
+    top: Option<Sample>,
+    crossing: Option<Sample>,
+    /// fastest descent since apogee or the last deployment, and the last frame close to it
+    /// that a deployment is timed from
+    peak_descent: Option<Sample>,
+    leaving_peak: Option<Sample>,
+    /// first frame of the vehicle standing still
+    still: Option<Sample>,
+}
+
+impl Default for Detector {
+    fn default() -> Detector {
+        Detector::new(EventConfig::default())
+    }
+}
+
+impl Detector {
+    pub fn new(config: EventConfig) -> Detector {
+        Detector {
+            config,
+            phase: Phase::Pad,
+            events: vec![],
+            pad_alt: None,
+            last: None,
+            run: 0,
+            run_start: None,
+            settled: 0,
+            settle_start: None,
+            peak_vel: None,
+            top: None,
+            crossing: None,
+            peak_descent: None,
+            leaving_peak: None,
+            still: None,
+        }
+    }
+
+    /// counts a frame towards the condition of the current phase, true once it has held for `confirm` frames
+    fn hold(&mut self, condition: bool, sample: Sample) -> bool {
+        if !condition {
+            self.run = 0;
+            self.run_start = None;
+            return false;
+        }
+
+        self.run += 1;
+        if self.run_start.is_none() {
+            self.run_start = Some(sample);
+        }
+        self.run >= self.config.confirm
+    }
+
+    fn enter(&mut self, phase: Phase) {
+        self.phase = phase;
+        self.run = 0;
+        self.run_start = None;
+        self.settled = 0;
+        self.settle_start = None;
+    }
+
+    fn event(&mut self, kind: EventKind, at: Sample, detected: f64, confidence: Confidence, detail: String) -> FlightEvent {
+        let event = FlightEvent {kind, time: at.time, detected, alt: at.alt, vel: at.vel, confidence, detail};
+        self.events.push(event.clone());
+        return event;
+    }
+
+    fn height(&self, sample: Sample) -> f64 {
+        sample.alt as f64 - self.pad_alt.unwrap_or(0.0)
+    }
+
+    /// checks a frame received at `rx_time`, frames without a usable altitude or velocity are skipped
+    ///
+    /// ## Returns
+    /// the events the frame confirmed
+    pub fn push(&mut self, frame: &Frame, rx_time: f64) -> Vec<FlightEvent> {
+        if !frame.alt.is_finite() || !frame.vel.is_finite() {
+            return vec![];
+        }
+        let s = Sample {time: frame.time, alt: frame.alt, vel: frame.vel};
+        let last = self.last.replace(s);
+        if self.pad_alt.is_none() {
+            self.pad_alt = Some(s.alt as f64);
+        }
+
+        let mut found: Vec<FlightEvent> = vec![];
+        match self.phase {
+            Phase::Pad => {
+                let fast = s.vel as f64 > self.config.liftoff_velocity;
+                let high = self.height(s) > self.config.liftoff_alt;
+                if !self.hold(fast || high, s) {
+                    // only averaged while nothing is happening, so the climb does not drag it up
+                    if self.run == 0 {
+                        self.pad_alt = self.pad_alt.map(|n| n * 0.9 + s.alt as f64 * 0.1);
+                    }
+                    return found;
+                }
+
+                let start = self.run_start.unwrap_or(s);
+                let (confidence, detail) = match (fast, high) {
+                    (true, true) => (Confidence::High, format!("climbing at {:.0} m/s and {:.0} m above the pad", s.vel, self.height(s))),
+                    (true, false) => (Confidence::Medium, format!("climbing at {:.0} m/s, altitude not up yet", s.vel)),
+                    _ => (Confidence::Medium, format!("{:.0} m above the pad, velocity not up yet", self.height(s))),
+                };
+                found.push(self.event(EventKind::Liftoff, start, rx_time, confidence, detail));
+                self.peak_vel = Some(s);
+                self.top = Some(s);
+                self.enter(Phase::Boost);
+            },
+            Phase::Boost => {
+                self.climb(s);
+                let peak = self.peak_vel.unwrap_or(s);
+                if !self.hold(s.vel < peak.vel, s) {
+                    return found;
+                }
+
+                // after burnout gravity and drag slow the vehicle by at least about 1 g
+                let decel = (peak.vel - s.vel) as f64 / (s.time - peak.time).max(1e-3) as f64;
+                let confidence = if decel >= GRAVITY * 0.8 {Confidence::High} else {Confidence::Medium};
+                let detail = format!("velocity peaked at {:.0} m/s, slowing by {:.1} m/s^2", peak.vel, decel);
+                found.push(self.event(EventKind::Burnout, peak, rx_time, confidence, detail));
+                self.enter(Phase::Coast);
+            },
+            Phase::Coast => {
+                self.climb(s);
+
+                let top = self.top.unwrap_or(s);
+                if !((top.alt - s.alt) as f64 >= self.config.apogee_drop) {
+                    return found;
+                }
+
+                // the velocity sign change gives the time, the fall from the highest altitude confirms it
+                let (at, confidence, detail) = match self.crossing {
+                    Some(n) if (n.time - top.time).abs() <= 2.0 => (n, Confidence::High, format!("velocity changed sign {:.1} s from the highest altitude", (n.time - top.time).abs())),
+                    Some(n) => (n, Confidence::Medium, format!("velocity changed sign {:.1} s away from the highest altitude", (n.time - top.time).abs())),
+                    None => (top, Confidence::Low, String::from("altitude is falling but velocity never changed sign")),
+                };
+                let at = Sample {alt: top.alt, ..at};
+                found.push(self.event(EventKind::Apogee, at, rx_time, confidence, detail));
+                self.peak_descent = Some(s);
+                self.leaving_peak = Some(s);
+                self.enter(Phase::Descent);
+            },
+            Phase::Descent | Phase::Drogue | Phase::Main => {
+                if let Some(n) = self.deployment(s, last, rx_time) {
+                    found.push(n);
+                }
+                if let Some(n) = self.landing(s, rx_time) {
+                    found.push(n);
+                }
+            },
+            Phase::Landed => {},
+        };
+
+        return found;
+    }
+
+    /// keeps the fastest climb, the highest point and the first falling frame of the flight
+    fn climb(&mut self, s: Sample) {
+        if s.vel <= 0.0 && self.crossing.is_none() {
+            self.crossing = Some(s);
+        }
+        if self.peak_vel.map(|n| s.vel > n.vel).unwrap_or(true) {
+            self.peak_vel = Some(s);
+        }
+        if self.top.map(|n| s.alt > n.alt).unwrap_or(true) {
+            self.top = Some(s);
+        }
+    }
+
+    /// a parachute opening, seen as the descent rate slowing, or for a drogue at apogee as it settling
+    fn deployment(&mut self, s: Sample, last: Option<Sample>, rx_time: f64) -> Option<FlightEvent> {
+        if self.phase == Phase::Main {
+            return None;
+        }
+        if self.peak_descent.map(|n| s.descent() > n.descent()).unwrap_or(true) {
+            self.peak_descent = Some(s);
+        }
+        let peak = self.peak_descent.unwrap_or(s);
+        if s.descent() >= peak.descent() - self.config.deploy_drop * 0.2 {
+            self.leaving_peak = Some(s);
+        }
+
+        // too close to the ground, touching down also slows the descent
+        let airborne = self.height(s) > self.config.liftoff_alt && s.descent() > self.config.landing_velocity;
+        let kind = if self.phase == Phase::Descent {EventKind::Drogue} else {EventKind::Main};
+
+        let slowed = airborne && s.descent() < peak.descent() - self.config.deploy_drop;
+        if self.hold(slowed, s) {
+            let detail = format!("descent slowed from {:.0} to {:.0} m/s", peak.descent(), s.descent());
+            let event = self.event(kind, self.leaving_peak.unwrap_or(peak), rx_time, Confidence::High, detail);
+            self.peak_descent = Some(s);
+            self.leaving_peak = Some(s);
+            self.enter(if kind == EventKind::Drogue {Phase::Drogue} else {Phase::Main});
+            return Some(event);
+        }
+
+        if kind != EventKind::Drogue {
+            return None;
+        }
+        let steady = match last {
+            Some(last) if s.time > last.time => ((s.descent() - last.descent()) / (s.time - last.time) as f64).abs() < SETTLE_ACCEL,
+            _ => false,
+        };
+        if !(airborne && steady) {
+            self.settled = 0;
+            self.settle_start = None;
+            return None;
+        }
+        self.settled += 1;
+        let start = *self.settle_start.get_or_insert(s);
+        if self.settled < self.config.confirm {
+            return None;
+        }
+
+        let detail = format!("descent rate settled at {:.0} m/s", s.descent());
+        let event = self.event(EventKind::Drogue, start, rx_time, Confidence::Medium, detail);
+        self.peak_descent = Some(s);
+        self.leaving_peak = Some(s);
+        self.enter(Phase::Drogue);
+        Some(event)
+    }
+
+    /// still for `landing_time` seconds
+    fn landing(&mut self, s: Sample, rx_time: f64) -> Option<FlightEvent> {
+        if (s.vel as f64).abs() >= self.config.landing_velocity {
+            self.still = None;
+            return None;
+        }
+
+        let start = *self.still.get_or_insert(s);
+        if ((s.time - start.time) as f64) < self.config.landing_time {
+            return None;
+        }
+
+        let height = self.height(start);
+        let (confidence, detail) = match height.abs() <= self.config.liftoff_alt * 3.0 {
+            true => (Confidence::High, format!("still for {:.0} s, {:.0} m from the pad altitude", s.time - start.time, height.abs())),
+            false => (Confidence::Medium, format!("still for {:.0} s but {:.0} m from the pad altitude", s.time - start.time, height.abs())),
+        };
+        let event = self.event(EventKind::Landing, start, rx_time, confidence, detail);
+        self.enter(Phase::Landed);
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::event::{Confidence, Detector, EventConfig, EventKind, FlightEvent, Phase};
+    use crate::protocol::Frame;
+
+    /// a 10 Hz flight: 2 s boost at 50 m/s^2, coasting to about 515 m, falling until the drogue
+    /// slows it to 25 m/s (at apogee when `drogue_alt` is none), main at 250 m slowing it to 6 m/s
+    fn flight(drogue_alt: Option<f64>) -> Vec<Frame> {
+        let mut frames: Vec<Frame> = vec![];
+        let (mut alt, mut vel) = (0f64, 0f64);
+        let mut apogee = false;
+
+        for i in 0..1200 {
+            let time = i as f64 * 0.1;
+            let accel = match time {
+                t if t < 1.0 => 0.0,
+                t if t < 3.0 => 50.0,
+                // gravity and drag on the way up
+                _ if !apogee && vel > 0.0 => -12.0,
+                _ if alt <= 0.0 => {
+                    vel = 0.0;
+                    0.0
+                },
+                _ => {
+                    apogee = true;
+                    let rate = match alt {
+                        n if n < 250.0 => 6.0,
+                        n if drogue_alt.map(|d| n < d).unwrap_or(true) => 25.0,
+                        _ => f64::INFINITY,
+                    };
+                    // falls until it reaches the rate of whichever parachute is out, then slows towards it
+                    if -vel <= rate {-(9.81f64.min((rate + vel) / 0.1))} else {((-vel - rate) * 5.0).min(60.0)}
+                },
+            };
+            vel += accel * 0.1;
+            alt = (alt + vel * 0.1).max(0.0);
+            frames.push(Frame {time: time as f32, alt: alt as f32, balt: alt as f32, vel: vel as f32, w: 1f32, x: 0f32, y: 0f32, z: 0f32, gps: None});
+        }
+        frames
+    }
+
+    fn detect(frames: &[Frame]) -> Vec<FlightEvent> {
+        let mut detector = Detector::default();
+        let mut events: Vec<FlightEvent> = vec![];
+        for frame in frames {
+            events.extend(detector.push(frame, frame.time as f64));
+        }
+        assert_eq!(detector.phase, Phase::Landed);
+        assert_eq!(events, detector.events);
+        events
+    }
+
+    #[test]
+    fn full_flight() {
+        let events = detect(&flight(None));
+        let kinds: Vec<EventKind> = events.iter().map(|n| n.kind).collect();
+        assert_eq!(kinds, vec![EventKind::Liftoff, EventKind::Burnout, EventKind::Apogee, EventKind::Drogue, EventKind::Main, EventKind::Landing]);
+
+        let at = |kind: EventKind| events.iter().find(|n| n.kind == kind).unwrap();
+        assert!((at(EventKind::Liftoff).time - 1.1).abs() < 0.3);
+        assert!((at(EventKind::Burnout).time - 3.0).abs() < 0.2);
+        assert_eq!(at(EventKind::Burnout).confidence, Confidence::High);
+
+        // 100 m/s at burnout slowing at 12 m/s^2, about 8.3 s to apogee
+        let apogee = at(EventKind::Apogee);
+        assert!((apogee.time - 11.3).abs() < 0.3);
+        assert!((apogee.alt - 515.0).abs() < 10.0);
+        assert_eq!(apogee.confidence, Confidence::High);
+
+        // the drogue came out at apogee, so it is only seen settling
+        assert_eq!(at(EventKind::Drogue).confidence, Confidence::Medium);
+        let main = at(EventKind::Main);
+        assert_eq!(main.confidence, Confidence::High);
+        assert!((main.alt - 250.0).abs() < 5.0);
+        assert_eq!(at(EventKind::Landing).confidence, Confidence::High);
+
+        // confirmation never comes before the event
+        assert!(events.iter().all(|n| n.detected >= n.time as f64));
+    }
+
+    #[test]
+    fn late_drogue() {
+        // falls freely from apogee, so the drogue opening slows the descent
+        let events = detect(&flight(Some(350.0)));
+        let drogue = events.iter().find(|n| n.kind == EventKind::Drogue).unwrap();
+        assert_eq!(drogue.confidence, Confidence::High);
+        assert!((drogue.alt - 350.0).abs() < 10.0);
+        assert!(drogue.vel < -50.0);
+        assert_eq!(events.iter().filter(|n| n.kind == EventKind::Main).count(), 1);
+    }
+
+    #[test]
+    fn pad_noise() {
+        let mut detector = Detector::default();
+        // a single gust on the baro is not a liftoff
+        for i in 0..50 {
+            let alt = if i == 20 {130.0} else {100.0 + (i % 3) as f32};
+            let frame = Frame {time: i as f32 * 0.1, alt, balt: alt, vel: 0.0, w: 1f32, x: 0f32, y: 0f32, z: 0f32, gps: None};
+            assert!(detector.push(&frame, 0.0).is_empty());
+        }
+        assert_eq!(detector.phase, Phase::Pad);
+
+        assert!(EventConfig::default().validate().is_ok());
+        let bad = EventConfig {confirm: 0, landing_velocity: 20.0, ..EventConfig::default()};
+        assert_eq!(bad.validate().unwrap_err().len(), 2);
+    }
+}
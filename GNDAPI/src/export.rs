@@ -0,0 +1,412 @@
+use crate::api::{Data, CHANNELS};
+use crate::command::Command;
+use crate::query::{self, Clock, Range, Interp};
+use crate::store;
+
+use parquet::data_type::{DoubleType, FloatType};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use rocket::serde::json::{self, Value};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// file formats a session can be exported as
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// one row per frame, one column per channel
+    Csv,
+    /// one JSON object per frame
+    JsonLines,
+    Parquet,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "csv" => Some(Format::Csv),
+            "jsonl" | "ndjson" => Some(Format::JsonLines),
+            "parquet" => Some(Format::Parquet),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::JsonLines => "jsonl",
+            Format::Parquet => "parquet",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv",
+            Format::JsonLines => "application/x-ndjson",
+            Format::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+}
+
+/// selected channels of a session aligned on time, ready to be written out
+pub struct Table {
+    pub channels: Vec<String>,
+    /// vehicle time of each row
+    pub time: Vec<f32>,
+    /// ground receive time of each row, UTC seconds
+    pub rx_time: Vec<f64>,
+    /// `values[row][channel]`, `None` where a channel has no sample from that frame
+    pub values: Vec<Vec<Option<f32>>>,
+}
+
+/// builds an export table, rows follow the frames of the first channel
+pub fn table(data: &Data, channels: &[String], range: &Range, clock: Clock) -> Result<Table, String> {
+    let mut series: Vec<&Vec<(f32, f32)>> = vec![];
+    for name in channels.iter() {
+        match data.channel(name) {
+            Some(n) => series.push(n),
+            None => {return Err(format!("unknown channel \"{}\"", name))},
+        };
+    }
+
+    let mut out = Table {
+        channels: channels.to_vec(),
+        time: vec![],
+        rx_time: vec![],
+        values: vec![],
+    };
+
+    if series.is_empty() {
+        return Ok(out);
+    }
+
+    let reference = series[0];
+    let (start, end, _) = match clock {
+        Clock::Vehicle => query::resolve(reference.len(), |i| reference[i].0 as f64, range),
+        Clock::Utc => query::resolve(reference.len().min(data.rx_time.len()), |i| data.rx_time[i], range),
+    };
+
+    for i in start..end {
+        let t = reference[i].0;
+        out.time.push(t);
+        out.rx_time.push(data.rx_time.get(i).copied().unwrap_or(f64::NAN));
+        out.values.push(series.iter().map(|n| query::value_at(n, t as f64, Interp::Exact)).collect());
+    }
+
+    return Ok(out);
+}
+
+pub fn write<W: Write + Send>(table: &Table, format: Format, out: W) -> Result<(), String> {
+    match format {
+        Format::Csv => write_csv(table, out),
+        Format::JsonLines => write_jsonl(table, out),
+        Format::Parquet => write_parquet(table, out),
+    }
+}
+
+/// wide csv, `time,rx_time,<channels...>` with empty cells for missing values
+pub fn write_csv<W: Write>(table: &Table, mut out: W) -> Result<(), String> {
+    let mut header: Vec<String> = vec!["time".to_string(), "rx_time".to_string()];
+    header.extend(table.channels.iter().cloned());
+
+    let mut res = writeln!(out, "{}", header.join(","));
+
+    for (i, row) in table.values.iter().enumerate() {
+        let mut line: Vec<String> = vec![table.time[i].to_string(), format!("{:.3}", table.rx_time[i])];
+        for val in row {
+            line.push(match val {
+                Some(n) => n.to_string(),
+                None => String::new(),
+            });
+        }
+        res = res.and_then(|_| writeln!(out, "{}", line.join(",")));
+    }
+
+    return res.map_err(|n| format!("error writing csv: {}", n));
+}
+
+/// one `{"time", "rx_time", <channel>: value...}` object per line
+pub fn write_jsonl<W: Write>(table: &Table, mut out: W) -> Result<(), String> {
+    for (i, row) in table.values.iter().enumerate() {
+        let mut obj = json::serde_json::Map::new();
+        obj.insert("time".to_string(), Value::from(table.time[i]));
+        obj.insert("rx_time".to_string(), Value::from(table.rx_time[i]));
+        for (name, val) in table.channels.iter().zip(row.iter()) {
+            obj.insert(name.clone(), match val {
+                Some(n) => Value::from(*n),
+                None => Value::Null,
+            });
+        }
+
+        let line = match json::to_string(&obj) {
+            Ok(n) => n,
+            Err(n) => {return Err(format!("error encoding row: {}", n))},
+        };
+        match writeln!(out, "{}", line) {
+            Ok(_) => {},
+            Err(n) => {return Err(format!("error writing json lines: {}", n))},
+        };
+    }
+
+    Ok(())
+}
+
+/// a single row group with a required `time` / `rx_time` column and an optional float column per channel
+pub fn write_parquet<W: Write + Send>(table: &Table, out: W) -> Result<(), String> {
+    let mut schema = String::from("message telemetry { REQUIRED FLOAT time; REQUIRED DOUBLE rx_time; ");
+    for name in table.channels.iter() {
+        // channel names are checked against CHANNELS, so they are safe to put in the schema
+        schema.push_str(&format!("OPTIONAL FLOAT {}; ", name));
+    }
+    schema.push('}');
+
+    let schema = match parse_message_type(&schema) {
+        Ok(n) => Arc::new(n),
+        Err(n) => {return Err(format!("error building parquet schema: {}", n))},
+    };
+
+    let res = (|| -> parquet::errors::Result<()> {
+        let mut writer = SerializedFileWriter::new(out, schema, Arc::new(WriterProperties::builder().build()))?;
+        let mut group = writer.next_row_group()?;
+
+        let mut col: usize = 0;
+        while let Some(mut column) = group.next_column()? {
+            match col {
+                0 => {
+                    column.typed::<FloatType>().write_batch(&table.time, None, None)?;
+                },
+                1 => {
+                    column.typed::<DoubleType>().write_batch(&table.rx_time, None, None)?;
+                },
+                n => {
+                    let vals: Vec<f32> = table.values.iter().filter_map(|row| row[n - 2]).collect();
+                    let levels: Vec<i16> = table.values.iter().map(|row| if row[n - 2].is_some() {1} else {0}).collect();
+                    column.typed::<FloatType>().write_batch(&vals, Some(&levels), None)?;
+                },
+            };
+            column.close()?;
+            col += 1;
+        }
+
+        group.close()?;
+        writer.close()?;
+        Ok(())
+    })();
+
+    return res.map_err(|n| format!("error writing parquet: {}", n));
+}
+
+/// quotes a csv cell if it holds a separator, quote or newline
+fn csv_field(val: &str) -> String {
+    if val.contains(',') || val.contains('"') || val.contains('\n') {
+        return format!("\"{}\"", val.replace('"', "\"\""));
+    }
+    val.to_string()
+}
+
+/// the command audit log as csv or json lines, one command per row with its final status
+pub fn write_commands<W: Write>(commands: &[Command], format: Format, mut out: W) -> Result<(), String> {
+    let res = match format {
+        Format::Csv => {
+            let mut res = writeln!(out, "id,requested,name,value,client,status,attempts,sent,error");
+            for cmd in commands.iter() {
+                let status = json::to_string(&cmd.status).unwrap_or_default();
+                let line: Vec<String> = vec![
+                    cmd.id.to_string(),
+                    format!("{:.3}", cmd.requested),
+                    csv_field(&cmd.name),
+                    cmd.value.to_string(),
+                    csv_field(&cmd.client),
+                    status.trim_matches('"').to_string(),
+                    cmd.attempts.len().to_string(),
+                    cmd.sent().map(|n| format!("{:.3}", n)).unwrap_or_default(),
+                    csv_field(cmd.error.as_deref().unwrap_or("")),
+                ];
+                res = res.and_then(|_| writeln!(out, "{}", line.join(",")));
+            }
+            res
+        },
+        Format::JsonLines => {
+            let mut res = Ok(());
+            for cmd in commands.iter() {
+                let line = match json::to_string(cmd) {
+                    Ok(n) => n,
+                    Err(n) => {return Err(format!("error encoding command: {}", n))},
+                };
+                res = res.and_then(|_| writeln!(out, "{}", line));
+            }
+            res
+        },
+        Format::Parquet => {return Err(String::from("commands can be exported as csv or jsonl"))},
+    };
+
+    return res.map_err(|n| format!("error writing commands: {}", n));
+}
+
+pub const USAGE: &str = "usage: RadioAPI export <session.jsonl> <out file> [--format csv|jsonl|parquet] [--table frames|commands] [--channels a,b,...] [--from s] [--to s] [--since s] [--clock vehicle|utc]";
+
+/// `RadioAPI export ...`, writes a logged session to a file
+/// 
+/// the format defaults to the extension of the output file
+pub fn cli(args: &[String]) -> Result<(), String> {
+    if args.len() < 2 {
+        return Err(USAGE.to_string());
+    }
+
+    let input = Path::new(&args[0]);
+    let output = Path::new(&args[1]);
+
+    let mut format: Option<Format> = output.extension().and_then(|n| n.to_str()).and_then(Format::parse);
+    let mut channels: Option<Vec<String>> = None;
+    let mut range = Range::default();
+    let mut clock = Clock::Vehicle;
+    let mut commands = false;
+
+    let mut i: usize = 2;
+    while i < args.len() {
+        let val = match args.get(i + 1) {
+            Some(n) => n,
+            None => {return Err(format!("missing value for {}\n{}", args[i], USAGE))},
+        };
+
+        let num = || -> Result<Option<f64>, String> {
+            match val.parse::<f64>() {
+                Ok(n) => Ok(Some(n)),
+                Err(_) => Err(format!("{} expects a number, got \"{}\"", args[i], val)),
+            }
+        };
+
+        match args[i].as_str() {
+            "--format" => {
+                format = match Format::parse(val) {
+                    Some(n) => Some(n),
+                    None => {return Err(format!("unknown format \"{}\"", val))},
+                };
+            },
+            "--table" => {
+                commands = match val.as_str() {
+                    "frames" => false,
+                    "commands" => true,
+                    n => {return Err(format!("unknown table \"{}\", expected frames or commands", n))},
+                };
+            },
+            "--channels" => {
+                channels = Some(val.split(',').map(|n| n.trim().to_string()).filter(|n| !n.is_empty()).collect());
+            },
+            "--from" => {range.from = num()?},
+            "--to" => {range.to = num()?},
+            "--since" => {range.since = num()?},
+            "--clock" => {
+                clock = match Clock::parse(val) {
+                    Some(n) => n,
+                    None => {return Err(format!("unknown clock \"{}\"", val))},
+                };
+            },
+            n => {return Err(format!("unknown option {}\n{}", n, USAGE))},
+        };
+
+        i += 2;
+    }
+
+    let format = match format {
+        Some(n) => n,
+        None => {return Err(format!("could not tell the format of {}, pass --format", output.display()))},
+    };
+
+    let data = store::load(input)?;
+
+    if commands {
+        let file = match File::create(output) {
+            Ok(n) => n,
+            Err(n) => {return Err(format!("could not create {}: {}", output.display(), n))},
+        };
+        write_commands(&data.commands.history, format, BufWriter::new(file))?;
+
+        println!("wrote {} commands to {}", data.commands.history.len(), output.display());
+        return Ok(());
+    }
+
+    let channels = channels.unwrap_or(CHANNELS.iter().map(|n| n.name.to_string()).collect());
+    let table = table(&data, &channels, &range, clock)?;
+
+    let file = match File::create(output) {
+        Ok(n) => n,
+        Err(n) => {return Err(format!("could not create {}: {}", output.display(), n))},
+    };
+    write(&table, format, BufWriter::new(file))?;
+
+    println!("wrote {} rows to {}", table.time.len(), output.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::Data;
+    use crate::export::{table, write_csv, write_jsonl, write_parquet};
+    use crate::protocol::Frame;
+    use crate::query::{Clock, Range};
+
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use std::env;
+    use std::fs::{self, File};
+
+    fn data() -> Data {
+        let mut data = Data::new();
+        for i in 0..10 {
+            data.record_at(&Frame {time: i as f32 * 0.5, alt: i as f32, balt: 0f32, vel: 2f32, w: 1f32, x: 0f32, y: 0f32, z: 0f32, gps: None}, 100f64 + i as f64);
+        }
+        return data;
+    }
+
+    fn channels(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn csv() {
+        let data = data();
+        let range = Range {from: Some(1.0), to: Some(2.0), ..Default::default()};
+        let table = table(&data, &channels(&["alt", "vel"]), &range, Clock::Vehicle).unwrap();
+
+        let mut out: Vec<u8> = vec![];
+        write_csv(&table, &mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "time,rx_time,alt,vel\n1,102.000,2,2\n1.5,103.000,3,2\n2,104.000,4,2\n");
+    }
+
+    #[test]
+    fn json_lines() {
+        let data = data();
+        let range = Range {from: Some(108.0), ..Default::default()};
+        let table = table(&data, &channels(&["alt"]), &range, Clock::Utc).unwrap();
+
+        let mut out: Vec<u8> = vec![];
+        write_jsonl(&table, &mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "{\"alt\":8.0,\"rx_time\":108.0,\"time\":4.0}\n{\"alt\":9.0,\"rx_time\":109.0,\"time\":4.5}\n");
+    }
+
+    #[test]
+    fn parquet() {
+        let data = data();
+        let table = table(&data, &channels(&["alt", "w"]), &Range::default(), Clock::Vehicle).unwrap();
+
+        let path = env::temp_dir().join(format!("gndapi-export-{}.parquet", std::process::id()));
+        write_parquet(&table, File::create(&path).unwrap()).unwrap();
+
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let meta = reader.metadata().file_metadata();
+        assert_eq!(meta.num_rows(), 10);
+        assert_eq!(meta.schema_descr().num_columns(), 4);
+        assert_eq!(meta.schema_descr().column(2).name(), "alt");
+
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn unknown_channel() {
+        assert!(table(&data(), &channels(&["nope"]), &Range::default(), Clock::Vehicle).is_err());
+    }
+}
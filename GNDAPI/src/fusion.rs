@@ -0,0 +1,455 @@
+use crate::api::Data;
+use crate::protocol::Frame;
+use crate::query;
+
+use serde::{Serialize, Deserialize};
+
+/// most estimates a single track request returns
+pub const MAX_TRACK_POINTS: usize = 200_000;
+
+/// the `[fusion]` table, noise of the ground side altitude / velocity filter as standard deviations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FusionConfig {
+    /// acceleration the model does not know about, m/s^2. higher follows boost and deployments
+    /// closer, lower smooths more
+    pub accel_noise: f64,
+    /// of the altitude fused on the vehicle, m
+    pub alt_noise: f64,
+    /// of the barometric altitude, m
+    pub balt_noise: f64,
+    /// of the vertical velocity, m/s
+    pub vel_noise: f64,
+}
+
+impl Default for FusionConfig {
+    fn default() -> FusionConfig {
+        FusionConfig {
+            accel_noise: 10.0,
+            alt_noise: 2.0,
+            balt_noise: 6.0,
+            vel_noise: 1.5,
+        }
+    }
+}
+
+impl FusionConfig {
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors: Vec<String> = vec![];
+
+        let noise = [
+            ("accel_noise", self.accel_noise),
+            ("alt_noise", self.alt_noise),
+            ("balt_noise", self.balt_noise),
+            ("vel_noise", self.vel_noise),
+        ];
+        for (name, value) in noise {
+            if value <= 0.0 || !value.is_finite() {
+                errors.push(format!("{} must be positive", name));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(errors)
+        }
+    }
+}
+
+/// altitude and vertical velocity at a vehicle time
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Estimate {
+    /// vehicle time, seconds
+    pub time: f64,
+    pub alt: f64,
+    pub vel: f64,
+    /// one standard deviation of `alt` and `vel`
+    pub alt_sd: f64,
+    pub vel_sd: f64,
+}
+
+/// the live estimate, carried forward from the last frame to now
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Coasted {
+    #[serde(flatten)]
+    pub estimate: Estimate,
+    /// seconds since the last frame, the uncertainty grows with it
+    pub coasting: f64,
+}
+
+/// what one frame measured, channels that are not finite are left out
+#[derive(Debug, Clone, Copy)]
+pub struct Measurement {
+    pub alt: f32,
+    pub balt: f32,
+    pub vel: f32,
+}
+
+impl Measurement {
+    pub fn from_frame(frame: &Frame) -> Measurement {
+        Measurement {alt: frame.alt, balt: frame.balt, vel: frame.vel}
+    }
+}
+
+/// `[alt, vel]` and its covariance at a vehicle time
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct State {
+    time: f64,
+    x: [f64; 2],
+    p: [[f64; 2]; 2],
+}
+
+impl State {
+    /// the first estimate, straight from a measurement
+    fn start(time: f64, m: Measurement, config: &FusionConfig) -> Option<State> {
+        let (alt, alt_var) = match (m.alt.is_finite(), m.balt.is_finite()) {
+            (true, _) => (m.alt as f64, config.alt_noise.powi(2)),
+            (false, true) => (m.balt as f64, config.balt_noise.powi(2)),
+            _ => {return None;},
+        };
+        let (vel, vel_var) = match m.vel.is_finite() {
+            true => (m.vel as f64, config.vel_noise.powi(2)),
+            // nothing known yet, the next altitudes settle it
+            false => (0.0, 100f64.powi(2)),
+        };
+
+        Some(State {time, x: [alt, vel], p: [[alt_var, 0.0], [0.0, vel_var]]})
+    }
+
+    /// carries the state to `time` at constant velocity, the unknown acceleration adds to the covariance
+    fn predict(&self, time: f64, accel_noise: f64) -> State {
+        let dt = (time - self.time).max(0.0);
+        let q = accel_noise.powi(2);
+        let p = self.p;
+
+        // F P F^T + Q with F = [[1, dt], [0, 1]]
+        let fp = [[p[0][0] + dt * p[1][0], p[0][1] + dt * p[1][1]], [p[1][0], p[1][1]]];
+        State {
+            time,
+            x: [self.x[0] + dt * self.x[1], self.x[1]],
+            p: [
+                [fp[0][0] + dt * fp[0][1] + q * dt.powi(3) / 3.0, fp[0][1] + q * dt.powi(2) / 2.0],
+                [fp[1][0] + dt * fp[1][1] + q * dt.powi(2) / 2.0, fp[1][1] + q * dt],
+            ],
+        }
+    }
+
+    /// folds in a measurement `z` of state `i` with variance `r`
+    fn update(&self, z: f64, r: f64, i: usize) -> State {
+        let p = self.p;
+        let s = p[i][i] + r;
+        let k = [p[0][i] / s, p[1][i] / s];
+        let y = z - self.x[i];
+
+        let mut next = *self;
+        next.x = [self.x[0] + k[0] * y, self.x[1] + k[1] * y];
+        for a in 0..2 {
+            for b in 0..2 {
+                next.p[a][b] = p[a][b] - k[a] * p[i][b];
+            }
+        }
+        return next;
+    }
+
+    fn measure(&self, m: Measurement, config: &FusionConfig) -> State {
+        let mut state = *self;
+        if m.alt.is_finite() {
+            state = state.update(m.alt as f64, config.alt_noise.powi(2), 0);
+        }
+        if m.balt.is_finite() {
+            state = state.update(m.balt as f64, config.balt_noise.powi(2), 0);
+        }
+        if m.vel.is_finite() {
+            state = state.update(m.vel as f64, config.vel_noise.powi(2), 1);
+        }
+        return state;
+    }
+
+    fn estimate(&self) -> Estimate {
+        Estimate {
+            time: self.time,
+            alt: self.x[0],
+            vel: self.x[1],
+            alt_sd: self.p[0][0].max(0.0).sqrt(),
+            vel_sd: self.p[1][1].max(0.0).sqrt(),
+        }
+    }
+}
+
+/// the live filter, fed every frame as it arrives
+#[derive(Default)]
+pub struct Fusion {
+    pub config: FusionConfig,
+    state: Option<State>,
+    /// ground receive time of the last frame, UTC seconds
+    last_rx: Option<f64>,
+}
+
+impl Fusion {
+    pub fn new(config: FusionConfig) -> Fusion {
+        Fusion {
+            config,
+            ..Fusion::default()
+        }
+    }
+
+    /// folds in a frame received at `rx_time`, returns the estimate at its vehicle time
+    ///
+    /// the vehicle clock going backwards means it rebooted, so the filter starts over
+    pub fn push(&mut self, frame: &Frame, rx_time: f64) -> Option<Estimate> {
+        let time = frame.time as f64;
+        let m = Measurement::from_frame(frame);
+
+        let state = match self.state {
+            Some(n) if time >= n.time => Some(n.predict(time, self.config.accel_noise).measure(m, &self.config)),
+            _ => State::start(time, m, &self.config),
+        };
+        if state.is_some() {
+            self.state = state;
+            self.last_rx = Some(rx_time);
+        }
+        state.map(|n| n.estimate())
+    }
+
+    /// the estimate carried forward to UTC `now`, none before the first frame
+    pub fn now(&self, now: f64) -> Option<Coasted> {
+        let (state, last_rx) = (self.state?, self.last_rx?);
+        let coasting = (now - last_rx).max(0.0);
+
+        Some(Coasted {
+            estimate: state.predict(state.time + coasting, self.config.accel_noise).estimate(),
+            coasting,
+        })
+    }
+}
+
+/// one step of the forward pass, before and after its measurement
+struct Step {
+    prior: State,
+    post: State,
+    /// a time the caller asked for, rather than a frame
+    wanted: bool,
+}
+
+/// runs the filter over a whole session, returning estimates at `rate` hz between `from` and `to`
+/// (vehicle seconds, the first and last frame by default), or at every frame without a rate
+///
+/// gaps between frames are coasted through with a growing uncertainty. `smooth` adds a backward
+/// Rauch-Tung-Striebel pass, so every estimate also uses the frames after it
+pub fn track(data: &Data, config: &FusionConfig, rate: Option<f64>, from: Option<f64>, to: Option<f64>, smooth: bool) -> Result<Vec<Estimate>, String> {
+    let frames: Vec<(f64, Measurement)> = data.altitude.iter().enumerate().map(|(i, n)| {
+        let balt = data.balt.get(i).map(|n| n.1).unwrap_or(f32::NAN);
+        let vel = data.vel.get(i).map(|n| n.1).unwrap_or(f32::NAN);
+        (n.0 as f64, Measurement {alt: n.1, balt, vel})
+    }).collect();
+
+    let (first, last) = match (frames.first(), frames.last()) {
+        (Some(a), Some(b)) => (a.0, b.0),
+        _ => {return Ok(vec![]);},
+    };
+    let start = from.unwrap_or(first).max(first);
+    let end = to.unwrap_or(last).min(last);
+
+    let wanted: Vec<f64> = match rate {
+        Some(n) if n <= 0.0 || !n.is_finite() => {return Err(String::from("rate must be positive"))},
+        Some(n) => {
+            if (end - start) * n > MAX_TRACK_POINTS as f64 {
+                return Err(format!("{} hz over {:.0} s is more than {} estimates, lower the rate or narrow from / to", n, end - start, MAX_TRACK_POINTS));
+            }
+            query::grid(start, end, n)
+        },
+        None => frames.iter().map(|n| n.0).filter(|n| *n >= start && *n <= end).collect(),
+    };
+
+    // frames and wanted times in time order, a wanted time on a frame comes after it
+    let mut steps: Vec<Step> = vec![];
+    let mut state: Option<State> = None;
+    let mut next = wanted.iter().peekable();
+    for (time, m) in frames.iter() {
+        while let (Some(t), Some(s)) = (next.peek(), state) {
+            if **t >= *time {
+                break;
+            }
+            let prior = s.predict(**t, config.accel_noise);
+            steps.push(Step {prior, post: prior, wanted: true});
+            state = Some(prior);
+            next.next();
+        }
+
+        let prior = match state {
+            Some(n) => n.predict(*time, config.accel_noise),
+            None => match State::start(*time, *m, config) {
+                Some(n) => n,
+                None => {continue;},
+            },
+        };
+        let post = if state.is_some() {prior.measure(*m, config)} else {prior};
+        steps.push(Step {prior, post, wanted: false});
+        state = Some(post);
+
+        while let Some(t) = next.peek() {
+            if **t != *time {
+                break;
+            }
+            steps.push(Step {prior: post, post, wanted: true});
+            next.next();
+        }
+    }
+
+    let mut out: Vec<State> = steps.iter().map(|n| n.post).collect();
+    if smooth {
+        rts(&steps, &mut out, config.accel_noise);
+    }
+
+    Ok(steps.iter().zip(out.iter()).filter(|n| n.0.wanted).map(|n| n.1.estimate()).collect())
+}
+
+/// the backward pass, `out` holds the forward estimates and is smoothed in place
+fn rts(steps: &[Step], out: &mut [State], accel_noise: f64) {
+    for k in (0..steps.len().saturating_sub(1)).rev() {
+        let post = steps[k].post;
+        let prior = steps[k + 1].prior;
+        // the prior of the next step, as predicted from this one
+        let predicted = post.predict(prior.time, accel_noise);
+        let pp = predicted.p;
+
+        let det = pp[0][0] * pp[1][1] - pp[0][1] * pp[1][0];
+        if det.abs() < 1e-12 || !det.is_finite() {
+            continue;
+        }
+        let inv = [[pp[1][1] / det, -pp[0][1] / det], [-pp[1][0] / det, pp[0][0] / det]];
+
+        // C = P F^T Pp^-1 with F = [[1, dt], [0, 1]]
+        let dt = prior.time - post.time;
+        let p = post.p;
+        let pft = [[p[0][0] + dt * p[0][1], p[0][1]], [p[1][0] + dt * p[1][1], p[1][1]]];
+        let c = mul(pft, inv);
+
+        let smoothed = out[k + 1];
+        let dx = [smoothed.x[0] - predicted.x[0], smoothed.x[1] - predicted.x[1]];
+        let dp = [
+            [smoothed.p[0][0] - pp[0][0], smoothed.p[0][1] - pp[0][1]],
+            [smoothed.p[1][0] - pp[1][0], smoothed.p[1][1] - pp[1][1]],
+        ];
+        let ct = [[c[0][0], c[1][0]], [c[0][1], c[1][1]]];
+        let cdpct = mul(mul(c, dp), ct);
+
+        out[k] = State {
+            time: post.time,
+            x: [post.x[0] + c[0][0] * dx[0] + c[0][1] * dx[1], post.x[1] + c[1][0] * dx[0] + c[1][1] * dx[1]],
+            p: [
+                [p[0][0] + cdpct[0][0], p[0][1] + cdpct[0][1]],
+                [p[1][0] + cdpct[1][0], p[1][1] + cdpct[1][1]],
+            ],
+        };
+    }
+}
+
+fn mul(a: [[f64; 2]; 2], b: [[f64; 2]; 2]) -> [[f64; 2]; 2] {
+    [
+        [a[0][0] * b[0][0] + a[0][1] * b[1][0], a[0][0] * b[0][1] + a[0][1] * b[1][1]],
+        [a[1][0] * b[0][0] + a[1][1] * b[1][0], a[1][0] * b[0][1] + a[1][1] * b[1][1]],
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::Data;
+    use crate::fusion::{track, Estimate, Fusion, FusionConfig};
+    use crate::protocol::Frame;
+
+    /// climbing at 10 m/s with a repeatable +-3 m of noise on the altitudes, no frames from 10 to 20 s
+    fn climb() -> Data {
+        let mut data = Data::new();
+        for i in 0..300 {
+            let time = i as f32 * 0.1;
+            if time >= 10.0 && time < 20.0 {
+                continue;
+            }
+            let noise = ((i * 7919) % 13) as f32 / 2.0 - 3.0;
+            let frame = Frame {time, alt: time * 10.0 + noise, balt: time * 10.0 - noise, vel: 10.0 + noise / 3.0, w: 1f32, x: 0f32, y: 0f32, z: 0f32, gps: None};
+            data.record_at(&frame, 1000.0 + time as f64);
+        }
+        data
+    }
+
+    fn at(estimates: &[Estimate], time: f64) -> Estimate {
+        *estimates.iter().find(|n| (n.time - time).abs() < 1e-6).unwrap()
+    }
+
+    #[test]
+    fn filtered() {
+        let data = climb();
+        let config = FusionConfig::default();
+
+        let every = track(&data, &config, None, None, None, false).unwrap();
+        assert_eq!(every.len(), data.altitude.len());
+
+        let estimate = at(&every, 9.9);
+        assert!((estimate.alt - 99.0).abs() < 2.0);
+        assert!((estimate.vel - 10.0).abs() < 1.0);
+        // better than any single measurement
+        assert!(estimate.alt_sd < config.alt_noise);
+
+        assert!(track(&data, &config, Some(0.0), None, None, false).is_err());
+        assert!(track(&data, &config, Some(1e6), None, None, false).unwrap_err().contains("lower the rate"));
+        assert!(track(&Data::new(), &config, Some(10.0), None, None, true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn dropout() {
+        let data = climb();
+        let config = FusionConfig::default();
+
+        let filtered = track(&data, &config, Some(2.0), Some(5.0), Some(25.0), false).unwrap();
+        assert_eq!(filtered.len(), 41);
+        assert_eq!((filtered[0].time, filtered[40].time), (5.0, 25.0));
+
+        // coasting through the gap, the uncertainty keeps growing
+        let gap: Vec<&Estimate> = filtered.iter().filter(|n| n.time > 10.0 && n.time < 20.0).collect();
+        assert!(gap.windows(2).all(|n| n[1].alt_sd > n[0].alt_sd));
+        assert!((at(&filtered, 15.0).alt - 150.0).abs() < 10.0);
+
+        // the smoother knows where the gap ends, so it is most unsure in the middle
+        let smoothed = track(&data, &config, Some(2.0), Some(5.0), Some(25.0), true).unwrap();
+        let (mid, late) = (at(&smoothed, 15.0), at(&smoothed, 19.5));
+        assert!(mid.alt_sd > late.alt_sd);
+        assert!(mid.alt_sd < at(&filtered, 15.0).alt_sd);
+        assert!(late.alt_sd < at(&filtered, 19.5).alt_sd / 2.0);
+        assert!((mid.alt - 150.0).abs() < 3.0);
+        assert!((mid.vel - 10.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn live() {
+        let mut fusion = Fusion::default();
+        assert!(fusion.now(0.0).is_none());
+
+        for i in 0..50 {
+            let time = i as f32 * 0.1;
+            let frame = Frame {time, alt: time * 10.0, balt: time * 10.0, vel: 10.0, w: 1f32, x: 0f32, y: 0f32, z: 0f32, gps: None};
+            fusion.push(&frame, 1000.0 + time as f64);
+        }
+
+        let fresh = fusion.now(1004.9).unwrap();
+        assert_eq!(fresh.coasting, 0.0);
+        assert!((fresh.estimate.alt - 49.0).abs() < 0.5);
+
+        // two seconds without a frame
+        let coasted = fusion.now(1006.9).unwrap();
+        assert!((coasted.coasting - 2.0).abs() < 1e-5);
+        assert!((coasted.estimate.time - 6.9).abs() < 1e-5);
+        assert!((coasted.estimate.alt - 69.0).abs() < 1.0);
+        assert!(coasted.estimate.alt_sd > fresh.estimate.alt_sd * 2.0);
+
+        // a reboot resets the vehicle clock and the filter
+        let frame = Frame {time: 0.5, alt: 3.0, balt: 3.0, vel: 0.0, w: 1f32, x: 0f32, y: 0f32, z: 0f32, gps: None};
+        let restarted = fusion.push(&frame, 1010.0).unwrap();
+        assert_eq!((restarted.alt, restarted.vel), (3.0, 0.0));
+
+        let bad = FusionConfig {accel_noise: 0.0, vel_noise: f64::NAN, ..FusionConfig::default()};
+        assert_eq!(bad.validate().unwrap_err().len(), 2);
+    }
+}
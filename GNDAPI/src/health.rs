@@ -0,0 +1,165 @@
+use crate::api::Data;
+use crate::link::{LinkState, RadioState};
+
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// window the frame rate is averaged over, seconds
+pub const RATE_WINDOW: f64 = 5.0;
+
+/// overall verdict, ordered from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Level {
+    Ok,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RadioHealth {
+    pub state: RadioState,
+    pub port: Option<String>,
+    pub restarts: u32,
+    /// times the radio thread failed and was restarted
+    pub crashes: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkHealth {
+    pub state: LinkState,
+    /// ms since the last accepted frame
+    pub age_ms: Option<u64>,
+    pub frames: u64,
+    /// packets that could not be decoded
+    pub rejected: u64,
+    /// accepted frames per second over the last `RATE_WINDOW` seconds
+    pub rate_hz: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageHealth {
+    /// session log, none when running from memory only
+    pub path: Option<PathBuf>,
+    pub records: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Health {
+    pub status: Level,
+    /// why the status is not OK
+    pub problems: Vec<String>,
+    pub uptime_s: f64,
+    pub session: u64,
+    pub radio: RadioHealth,
+    pub link: LinkHealth,
+    pub storage: StorageHealth,
+}
+
+/// accepted frames per second over the `window` seconds before `now`
+fn frame_rate(rx_time: &[f64], now: f64, window: f64) -> f64 {
+    let recent = rx_time.iter().rev().take_while(|n| now - **n <= window).count();
+    recent as f64 / window
+}
+
+/// checks every part of the station and rolls them up into one level
+pub fn check(data: &Data, now: f64) -> Health {
+    let mut status = Level::Ok;
+    let mut problems: Vec<String> = vec![];
+    let mut flag = |level: Level, problem: String| {
+        if level > status {
+            status = level;
+        }
+        problems.push(problem);
+    };
+
+    match data.radio {
+        RadioState::Running => {},
+        RadioState::Starting => flag(Level::Warn, String::from("radio is starting")),
+        RadioState::Resetting => flag(Level::Warn, String::from("radio is resetting")),
+        RadioState::Stopped => flag(Level::Fail, String::from("radio thread has stopped")),
+    };
+
+    let link = data.link.state(now);
+    match link {
+        LinkState::Locked => {},
+        LinkState::Waiting => flag(Level::Warn, String::from("no frame received yet")),
+        LinkState::Degraded => flag(Level::Warn, String::from("link is degraded")),
+        LinkState::Lost => flag(Level::Fail, String::from("link is lost")),
+    };
+
+    let storage = match data.store.as_ref() {
+        Some(store) => {
+            if let Some(n) = store.error.as_ref() {
+                flag(Level::Fail, format!("session log: {}", n));
+            }
+            StorageHealth {path: Some(store.path.clone()), records: store.records, error: store.error.clone()}
+        },
+        None => {
+            flag(Level::Warn, String::from("session is not being logged to disk"));
+            StorageHealth {path: None, records: 0, error: None}
+        },
+    };
+
+    Health {
+        status,
+        problems,
+        uptime_s: now - data.started,
+        session: data.session,
+        radio: RadioHealth {
+            state: data.radio,
+            port: data.link.port.clone(),
+            restarts: data.link.restarts,
+            crashes: data.link.crashes,
+        },
+        link: LinkHealth {
+            state: link,
+            age_ms: data.link.age(now).map(|n| (n * 1000f64) as u64),
+            frames: data.link.frames,
+            rejected: data.link.rejected,
+            rate_hz: frame_rate(&data.rx_time, now, RATE_WINDOW),
+        },
+        storage,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::Data;
+    use crate::health::{check, Level};
+    use crate::link::RadioState;
+    use crate::protocol::Frame;
+
+    fn frame(time: f32) -> Frame {
+        Frame {time, alt: 0f32, balt: 0f32, vel: 0f32, w: 1f32, x: 0f32, y: 0f32, z: 0f32, gps: None}
+    }
+
+    #[test]
+    fn levels() {
+        let mut data = Data::new();
+        let now = data.started;
+
+        // starting, nothing received and no session log
+        let health = check(&data, now);
+        assert_eq!(health.status, Level::Warn);
+        assert_eq!(health.problems.len(), 3);
+
+        data.radio = RadioState::Running;
+        for i in 0..10 {
+            data.record_at(&frame(i as f32 * 0.5), now + i as f64 * 0.5);
+        }
+
+        let health = check(&data, now + 4.6);
+        assert_eq!(health.status, Level::Warn);
+        assert_eq!(health.problems, vec!["session is not being logged to disk".to_string()]);
+        assert_eq!(health.link.rate_hz, 2.0);
+
+        let health = check(&data, now + 10.0);
+        assert_eq!(health.status, Level::Fail);
+        assert!(health.problems.contains(&"link is lost".to_string()));
+
+        data.radio = RadioState::Stopped;
+        assert!(check(&data, now + 4.6).problems.contains(&"radio thread has stopped".to_string()));
+    }
+}
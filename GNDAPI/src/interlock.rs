@@ -0,0 +1,183 @@
+use crate::link::LinkState;
+
+use serde::{Serialize, Deserialize};
+
+/// the `[interlock]` table of the station config
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InterlockConfig {
+    /// commands that have to be armed before they can be executed
+    pub hazardous: Vec<String>,
+    /// seconds an arm stays valid
+    pub arm_timeout: f64,
+    /// only execute hazardous commands while the link is locked
+    pub require_locked: bool,
+}
+
+impl Default for InterlockConfig {
+    fn default() -> InterlockConfig {
+        InterlockConfig {
+            hazardous: vec![],
+            arm_timeout: 10.0,
+            require_locked: false,
+        }
+    }
+}
+
+/// a hazardous command waiting for its execute step
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Armed {
+    pub name: String,
+    pub value: f32,
+    /// client that armed it
+    pub by: String,
+    /// UTC seconds
+    pub armed_at: f64,
+    pub expires: f64,
+}
+
+/// state of the interlock as shown by the api
+#[derive(Debug, Clone, Serialize)]
+pub struct InterlockStatus {
+    #[serde(flatten)]
+    pub config: InterlockConfig,
+    pub armed: Option<Armed>,
+    /// seconds until the arm expires
+    pub remaining_s: Option<f64>,
+}
+
+/// two step arm / execute gate in front of hazardous commands
+#[derive(Default)]
+pub struct Interlock {
+    pub config: InterlockConfig,
+    pub armed: Option<Armed>,
+}
+
+impl Interlock {
+    pub fn new(config: InterlockConfig) -> Interlock {
+        Interlock {
+            config,
+            armed: None,
+        }
+    }
+
+    pub fn is_hazardous(&self, name: &str) -> bool {
+        self.config.hazardous.iter().any(|n| n == name)
+    }
+
+    /// drops an arm that has timed out
+    fn expire(&mut self, now: f64) {
+        if self.armed.as_ref().map(|n| now >= n.expires).unwrap_or(false) {
+            self.armed = None;
+        }
+    }
+
+    /// arms a hazardous command, replacing any earlier arm
+    pub fn arm(&mut self, name: &str, value: f32, by: &str, now: f64) -> Result<Armed, String> {
+        if !self.is_hazardous(name) {
+            return Err(format!("{} is not hazardous and does not need arming", name));
+        }
+
+        let armed = Armed {
+            name: name.to_string(),
+            value,
+            by: by.to_string(),
+            armed_at: now,
+            expires: now + self.config.arm_timeout,
+        };
+        self.armed = Some(armed.clone());
+
+        return Ok(armed);
+    }
+
+    pub fn disarm(&mut self) {
+        self.armed = None;
+    }
+
+    /// checks the execute step of a command, a successful execute disarms
+    ///
+    /// commands that are not hazardous always pass
+    pub fn execute(&mut self, name: &str, value: f32, link: LinkState, now: f64) -> Result<(), String> {
+        if !self.is_hazardous(name) {
+            return Ok(());
+        }
+
+        let was_armed = self.armed.is_some();
+        self.expire(now);
+
+        let armed = match self.armed.as_ref() {
+            Some(n) => n,
+            None if was_armed => {return Err(format!("the arm for {} expired, arm it again", name))},
+            None => {return Err(format!("{} is hazardous, arm it first with POST /cmd/arm/{}/{}", name, name, value))},
+        };
+
+        if armed.name != name || armed.value != value {
+            return Err(format!("armed for {} {}, not {} {}", armed.name, armed.value, name, value));
+        }
+
+        if self.config.require_locked && link != LinkState::Locked {
+            return Err(format!("link is {}, hazardous commands need a locked link", format!("{:?}", link).to_lowercase()));
+        }
+
+        self.armed = None;
+        Ok(())
+    }
+
+    pub fn status(&mut self, now: f64) -> InterlockStatus {
+        self.expire(now);
+
+        InterlockStatus {
+            config: self.config.clone(),
+            armed: self.armed.clone(),
+            remaining_s: self.armed.as_ref().map(|n| n.expires - now),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interlock::{Interlock, InterlockConfig};
+    use crate::link::LinkState;
+
+    fn interlock(require_locked: bool) -> Interlock {
+        Interlock::new(InterlockConfig {hazardous: vec!["test".to_string()], arm_timeout: 5.0, require_locked})
+    }
+
+    #[test]
+    fn arm_then_execute() {
+        let mut lock = interlock(false);
+
+        assert!(lock.execute("quit", 0.0, LinkState::Lost, 0.0).is_ok());
+        assert!(lock.arm("quit", 0.0, "rso", 0.0).is_err());
+
+        assert!(lock.execute("test", 1.0, LinkState::Locked, 0.0).is_err());
+
+        lock.arm("test", 1.0, "rso", 10.0).unwrap();
+        assert!(lock.execute("test", 2.0, LinkState::Locked, 11.0).unwrap_err().contains("armed for test 1"));
+        assert!(lock.execute("test", 1.0, LinkState::Locked, 11.0).is_ok());
+
+        // an arm is good for one execute
+        assert!(lock.execute("test", 1.0, LinkState::Locked, 12.0).is_err());
+    }
+
+    #[test]
+    fn arm_expires() {
+        let mut lock = interlock(false);
+
+        lock.arm("test", 1.0, "rso", 10.0).unwrap();
+        assert!(lock.status(14.0).remaining_s.unwrap() > 0.0);
+        assert!(lock.execute("test", 1.0, LinkState::Locked, 15.0).unwrap_err().contains("expired"));
+        assert!(lock.status(15.0).armed.is_none());
+    }
+
+    #[test]
+    fn needs_locked_link() {
+        let mut lock = interlock(true);
+
+        lock.arm("test", 1.0, "rso", 10.0).unwrap();
+        assert!(lock.execute("test", 1.0, LinkState::Degraded, 11.0).unwrap_err().contains("degraded"));
+
+        // still armed, so it can go once the link recovers
+        assert!(lock.execute("test", 1.0, LinkState::Locked, 12.0).is_ok());
+    }
+}
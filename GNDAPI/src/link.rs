@@ -0,0 +1,327 @@
+use crate::protocol::{Frame, Schema};
+
+use ArmlabRadio::radio_serial::{Radio, RadioError};
+
+use serde::Serialize;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// how long `Receiver::recv` waits for a new frame before reporting an empty poll,
+/// default of `radio.recv_timeout_ms`
+pub const RECV_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// pause between polls that returned no new frame, default of `radio.poll_backoff_ms`
+///
+/// the serial round trip of `get_packet` already takes a few ms, this only keeps
+/// us from hammering the dongle while the vehicle is quiet
+pub const POLL_BACKOFF: Duration = Duration::from_millis(5);
+
+/// last frame age under which the link counts as locked, default of `link.locked_age`
+pub const LOCKED_AGE: f64 = 1.0;
+
+/// last frame age after which the link counts as lost, default of `link.lost_age`
+pub const LOST_AGE: f64 = 3.0;
+
+/// health of the downlink, judged by the age of the last frame
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkState {
+    /// no frame received yet this session
+    Waiting,
+    /// frames are arriving at the expected rate
+    Locked,
+    /// frames are late, but the link has not been given up on
+    Degraded,
+    /// no frame for longer than `LinkStats::lost_age`
+    Lost,
+}
+
+/// what the radio thread is doing
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RadioState {
+    /// finding the port and configuring the radio
+    Starting,
+    /// polling for frames
+    Running,
+    /// re-initializing the radio after it went quiet
+    Resetting,
+    /// the thread has exited
+    Stopped,
+}
+
+/// running counters for the downlink, shared with the api through `Data::link`
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkStats {
+    /// serial port of the ground radio
+    pub port: Option<String>,
+    /// frames accepted
+    pub frames: u64,
+    /// packets that could not be decoded as a frame
+    pub rejected: u64,
+    /// times the radio was reset after going quiet
+    pub restarts: u32,
+    /// times the radio thread failed and was restarted by the supervisor
+    pub crashes: u32,
+    /// ground receive time of the last accepted frame, UTC seconds
+    pub last_rx: Option<f64>,
+    /// thresholds of `state`, from the `[link]` config
+    #[serde(skip)]
+    pub locked_age: f64,
+    #[serde(skip)]
+    pub lost_age: f64,
+}
+
+impl Default for LinkStats {
+    fn default() -> LinkStats {
+        LinkStats {
+            port: None,
+            frames: 0,
+            rejected: 0,
+            restarts: 0,
+            crashes: 0,
+            last_rx: None,
+            locked_age: LOCKED_AGE,
+            lost_age: LOST_AGE,
+        }
+    }
+}
+
+impl LinkStats {
+    /// seconds since the last accepted frame
+    pub fn age(&self, now: f64) -> Option<f64> {
+        self.last_rx.map(|n| (now - n).max(0f64))
+    }
+
+    pub fn state(&self, now: f64) -> LinkState {
+        match self.age(now) {
+            None => LinkState::Waiting,
+            Some(n) if n < self.locked_age => LinkState::Locked,
+            Some(n) if n < self.lost_age => LinkState::Degraded,
+            Some(_) => LinkState::Lost,
+        }
+    }
+}
+
+/// anything that hands out the most recent radio packet
+///
+/// implemented for the serial `Radio`, and by mock sources in tests
+pub trait PacketSource {
+    /// gets the most recent packet, empty if none is available
+    fn get_packet(&mut self) -> Result<Vec<u8>, RadioError>;
+
+    /// waits between polls that returned nothing new
+    fn backoff(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+impl PacketSource for Radio {
+    fn get_packet(&mut self) -> Result<Vec<u8>, RadioError> {
+        Radio::get_packet(self)
+    }
+}
+
+/// pulls frames off a `PacketSource` as fast as the link delivers them
+///
+/// the radio only holds its latest packet, so any time spent not polling is
+/// a chance to miss a frame. the receiver never sleeps after a good frame and
+/// only backs off briefly between empty polls
+pub struct Receiver {
+    last_time: Option<f32>,
+    last_rejected: Vec<u8>,
+    /// packets that could not be decoded as a frame
+    pub rejected: u64,
+    /// layout of the frames, from the `[packet]` config
+    pub schema: Schema,
+    /// pause between empty polls
+    pub poll_backoff: Duration,
+}
+
+impl Receiver {
+    #[cfg(test)]
+    pub fn new() -> Receiver {
+        Receiver::with_schema(Schema::default(), POLL_BACKOFF)
+    }
+
+    pub fn with_schema(schema: Schema, poll_backoff: Duration) -> Receiver {
+        Receiver {
+            last_time: None,
+            last_rejected: vec![],
+            rejected: 0,
+            schema,
+            poll_backoff,
+        }
+    }
+
+    /// blocks until a new frame arrives or `timeout` passes
+    ///
+    /// ## Returns
+    /// `Ok(Some(frame))` for a frame we have not seen before
+    ///
+    /// `Ok(None)` if no new frame arrived within `timeout`
+    pub fn recv<S: PacketSource>(&mut self, source: &mut S, timeout: Duration) -> Result<Option<Frame>, RadioError> {
+        let start = Instant::now();
+
+        loop {
+            let buf = source.get_packet()?;
+
+            // the radio keeps handing out its latest packet until a new one lands
+            if buf.len() > 0 {
+                match self.schema.decode(&buf) {
+                    Ok(frame) => {
+                        if self.last_time != Some(frame.time) {
+                            self.last_time = Some(frame.time);
+                            return Ok(Some(frame));
+                        }
+                    },
+                    Err(n) => {
+                        if buf != self.last_rejected {
+                            println!("Error decoding frame | {}", n);
+                            self.rejected += 1;
+                            self.last_rejected = buf;
+                        }
+                    }
+                };
+            }
+
+            if start.elapsed() >= timeout {
+                return Ok(None);
+            }
+
+            source.backoff(self.poll_backoff);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::link::{PacketSource, Receiver, RECV_TIMEOUT, LinkStats, LinkState};
+    use crate::protocol::{Frame, encode_frame};
+
+    use ArmlabRadio::radio_serial::RadioError;
+
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// round trip of a `get_packet` call over 115200 baud serial
+    const SERIAL_LATENCY: Duration = Duration::from_millis(4);
+
+    /// behaves like the dongle: holds only the latest packet, and every read costs a serial round trip
+    struct MockRadio {
+        latest: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl PacketSource for MockRadio {
+        fn get_packet(&mut self) -> Result<Vec<u8>, RadioError> {
+            thread::sleep(SERIAL_LATENCY);
+            Ok(self.latest.lock().expect("could not lock mutex").clone())
+        }
+    }
+
+    /// a vehicle transmitting at a fixed rate into the dongle, on a simulated clock
+    /// 
+    /// serial round trips and backoff advance the clock instead of sleeping, so the
+    /// result does not depend on how busy the test machine is
+    struct SimRadio {
+        clock: Duration,
+        period: Duration,
+        count: usize,
+    }
+
+    impl SimRadio {
+        fn done(&self) -> bool {
+            self.clock > self.period * (self.count as u32 + 1)
+        }
+    }
+
+    impl PacketSource for SimRadio {
+        fn get_packet(&mut self) -> Result<Vec<u8>, RadioError> {
+            self.clock += SERIAL_LATENCY;
+
+            // the dongle holds whichever frame landed last
+            let sent = (self.clock.as_micros() / self.period.as_micros()) as usize;
+            if sent == 0 {
+                return Ok(vec![]);
+            }
+
+            let i = sent.min(self.count) - 1;
+            let frame = Frame {time: i as f32 * 0.001, alt: i as f32, balt: 0f32, vel: 0f32, w: 1f32, x: 0f32, y: 0f32, z: 0f32, gps: None};
+            Ok(encode_frame(&frame).to_vec())
+        }
+
+        fn backoff(&mut self, duration: Duration) {
+            self.clock += duration;
+        }
+    }
+
+    /// transmits `count` frames at `rate` hz and returns how many were received
+    fn run_at(rate: u64, count: usize) -> usize {
+        let mut source = SimRadio {clock: Duration::ZERO, period: Duration::from_micros(1_000_000 / rate), count};
+
+        let mut rx = Receiver::new();
+        let mut received: Vec<f32> = vec![];
+        while !source.done() {
+            match rx.recv(&mut source, RECV_TIMEOUT).expect("sim radio errored") {
+                Some(frame) => received.push(frame.alt),
+                None => {},
+            };
+        }
+
+        // no duplicates and in order, so a full count means no gaps either
+        assert!(received.windows(2).all(|w| w[0] < w[1]));
+
+        return received.len();
+    }
+
+    #[test]
+    fn no_drops_20hz() {
+        assert_eq!(run_at(20, 400), 400);
+    }
+
+    #[test]
+    fn no_drops_50hz() {
+        assert_eq!(run_at(50, 1000), 1000);
+    }
+
+    #[test]
+    fn drops_above_poll_rate() {
+        // sanity check the simulation, frames faster than a poll cycle must be lost
+        assert!(run_at(500, 1000) < 1000);
+    }
+
+    #[test]
+    fn timeout_when_quiet() {
+        let mut source = MockRadio {latest: Arc::new(Mutex::new(vec![]))};
+        let mut rx = Receiver::new();
+
+        let start = Instant::now();
+        assert!(rx.recv(&mut source, Duration::from_millis(30)).expect("mock radio errored").is_none());
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn rejects_counted_once() {
+        let latest = Arc::new(Mutex::new(vec![1u8, 2u8, 3u8]));
+        let mut source = MockRadio {latest: Arc::clone(&latest)};
+        let mut rx = Receiver::new();
+
+        assert!(rx.recv(&mut source, Duration::from_millis(30)).expect("mock radio errored").is_none());
+        assert_eq!(rx.rejected, 1);
+    }
+
+    #[test]
+    fn link_state() {
+        let mut stats = LinkStats::default();
+        assert_eq!(stats.state(100.0), LinkState::Waiting);
+
+        stats.last_rx = Some(100.0);
+        assert_eq!(stats.state(100.5), LinkState::Locked);
+        assert_eq!(stats.state(102.0), LinkState::Degraded);
+        assert_eq!(stats.state(104.0), LinkState::Lost);
+
+        stats.lost_age = 5.0;
+        assert_eq!(stats.state(104.0), LinkState::Degraded);
+    }
+}
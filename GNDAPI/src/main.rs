@@ -1,9 +1,53 @@
 use crate::api::start_api;
 mod api;
 
-use crate::protocol::{RocketData, decode_stream, DATA_STREAM_SIZE};
+mod assets;
+
 mod protocol;
 
+use crate::link::{Receiver, RadioState};
+mod link;
+
+mod query;
+
+mod stream;
+
+use crate::store::Store;
+mod store;
+
+mod export;
+
+mod auth;
+
+mod command;
+
+use crate::tuning::Tuner;
+mod tuning;
+
+mod interlock;
+
+mod health;
+
+mod metrics;
+
+use crate::supervisor::{Backoff, Supervisor};
+mod supervisor;
+
+use crate::station::{Args, StationConfig};
+mod station;
+
+mod map;
+
+mod replay;
+
+mod vehicle;
+
+mod alarm;
+
+mod event;
+
+mod fusion;
+
 use ArmlabRadio::radio_serial::{Radio, prompt_port};
 
 
@@ -13,21 +57,37 @@ use std::sync::{Arc, Mutex};
 
 
 fn radio(arc_data: api::TData) {
-    let port = prompt_port();
+    // only ask for a port the first time if none is configured, restarts reuse it
+    let (known, config, schema) = {
+        let mut data = arc_data.lock().expect("could not lock mutex");
+        data.radio = RadioState::Starting;
+        (data.link.port.clone().or(data.config.radio.port.clone()), data.config.radio.clone(), data.config.packet.fields.clone())
+    };
+    let port = match known {
+        Some(n) => n,
+        None => prompt_port(),
+    };
+    arc_data.lock().expect("could not lock mutex").link.port = Some(port.clone());
 
     let mut radio = Radio::new(&port).expect("Error Creating Radio");
-    radio.set_power(14f32).expect("error setting power");
+    tune(&arc_data, &mut radio);
 
-    let mut iter_np: u16 = 0;
+    // reset once nothing has been received for radio.reset_after_ms
+    let mut last_frame = Instant::now();
     
-    let mut last_time: f32 = 0f32;
+    let mut rx = Receiver::with_schema(schema, config.poll_backoff());
 
-    let mut total_restarts: i32 = 0;
+    // carried over from before a supervisor restart
+    let (mut total_restarts, rejected) = {
+        let data = arc_data.lock().expect("could not lock mutex");
+        (data.link.restarts, data.link.rejected)
+    };
+    rx.rejected = rejected;
 
     loop {
-        if iter_np == 50 {
+        if last_frame.elapsed() >= config.reset_after() {
             total_restarts += 1;
-            iter_np = 0;
+            arc_data.lock().expect("could not lock mutex").radio = RadioState::Resetting;
 
             radio.soft_reset().unwrap();
 
@@ -38,9 +98,15 @@ fn radio(arc_data: api::TData) {
                     Ok(n) => {break n;},
                     Err(_) => {},
                 };
+
+                if !arc_data.lock().expect("could not lock mutex").is_alive {
+                    return ();
+                }
+                thread::sleep(config.reopen_delay());
             };
 
-            radio.set_power(14f32).expect("error setting power");
+            tune(&arc_data, &mut radio);
+            last_frame = Instant::now();
 
             //thread::sleep(Duration::from_millis(30));
             println!("reset radio, re-initialized serial coms");
@@ -56,249 +122,97 @@ fn radio(arc_data: api::TData) {
 
         // handle thread quit
         if !data.is_alive {
+            data.radio = RadioState::Stopped;
             return ();
         }
 
-        /* 
-        // handle commands
-        for command in data.cmds.iter() {
-            let (cmd, arg) = command;
-
-            println!("got cmd {}, with args {}", cmd, arg);
-
-            let mut buf: [u8; 5] = [0u8; 5];
-            match cmd.as_str() {
-                "test" => {
-                    buf[0] = 2;
-                    
-                    let f = arg.to_le_bytes();
-                    buf[1] = f[0];
-                    buf[2] = f[1];
-                    buf[3] = f[2];
-                    buf[4] = f[3];
+        // send what is queued
+        let pending = data.commands.pending();
 
-                }
-                _ => {
-                    println!("unknown command");
-                }
-            }
-            
-            match radio.transmit(&buf) {
-                Ok(_) => {},
-                Err(n) => {
-                    radio.sync(10);
-                    println!("transmit error: {:?} | skipping command: {}", n, cmd);
-                }
-            };
-        }
-        data.cmds.clear();
-        */
-
-        /*
-        // downlink
-        let mut handle_packet = || -> () {
-            tot_packets += 1;
-            // get data stream
-            let buf = match radio.get_packet() {
-                Ok(n) => {
-                    if n.len() == 0 {
-                        return;
-                    }
-                    n
-                },
-                Err(n) => {
-                    radio.sync(10);
-                    println!("Error getting packet: {:?}", n);
-                    return;
-                }
-            };
+        // settings changes go in between polls, never while a packet is being read
+        let change = data.tuning.pending.take().map(|n| (n, data.tuning.settings.clone()));
+        drop(data);
 
-            let buf: [u8; DATA_STREAM_SIZE] = match buf.try_into() {
-                Ok(n) => n,
-                Err(n) => {
-                    println!("Error | expected length {} got {} ", DATA_STREAM_SIZE, n.len());
-                    return;
-                }
-            };
+        if let Some((mut change, current)) = change {
+            let res = tuning::retune(&mut radio, &current, &change.settings);
+            change.applied = Some(api::utc_now());
+            change.error = res.err();
+            println!("radio settings from {} | {}", change.client, change.error.as_deref().unwrap_or("applied"));
 
-            let rec_data: RocketData = match decode_stream(buf) {
-                Ok(n) => n,
-                Err(n) => {
-                    println!("Error decoding stream | {}", n);
-                    return;
-                }
+            match arc_data.lock() {
+                Ok(mut n) => n.update_tuning(change),
+                Err(_) => println!("could not lock mutex"),
             };
+        }
 
-            good_packets += 1;
+        for mut cmd in pending {
+            command::transmit(&mut radio, &mut cmd, api::utc_now);
+            println!("cmd {} {} | {:?} after {} attempts", cmd.name, cmd.value, cmd.status, cmd.attempts.len());
 
-            let time: f32 = rec_data.time as f32 / 1000f32;
+            match arc_data.lock() {
+                Ok(mut n) => n.update_command(cmd),
+                Err(_) => println!("could not lock mutex"),
+            };
+        }
 
-            data.altitude.push((time, rec_data.altitude)); 
-            data.orx.push((time, rec_data.orx));
-            data.ory.push((time, rec_data.ory));
-            data.orz.push((time, rec_data.orz));
-            data.lat.push((time, rec_data.lat));
-            data.long.push((time, rec_data.long));
-            data.fix.push((time, rec_data.fix as f32));
-            data.quality.push((time, rec_data.quality as f32));
-            data.cont_droug.push((time, if rec_data.cont1 {1f32} else {0f32}));
-            data.cont_main.push((time, if rec_data.cont2 {1f32} else {0f32}));
+        // poll without holding the mutex so the api is never blocked on serial io
+        let res = rx.recv(&mut radio, config.recv_timeout());
 
+        let mut data = match arc_data.lock() {
+            Ok(n) => n,
+            Err(_) => {
+                println!("could not lock mutex");
+                continue;
+            } 
         };
 
-        handle_packet();
-
-        drop(data);
-
-        // if we are unable to parse a data stream we continue; this skips the heartbeat section alltogether
-        
-        if start_time.elapsed() >= Duration::from_millis(1000) {
-            println!("{} / {} | {}%", good_packets, tot_packets, (good_packets*100/tot_packets));
-            if tot_packets > 0 && (good_packets*100/tot_packets) >= 50 {
-                iter += 1;
-                // transmit heartbeat
-                println!("sending heartbeat {}", iter);
-                
-                
-                match radio.transmit(&[1, 1, 1, 1, 1]) {
-                    Ok(_) => {},
-                    Err(n) => {
-                        radio.sync(10);
-                        println!("transmit error: {:?} | skipping heartbeat", n);
-                    }
-                }
-                thread::sleep(Duration::from_millis(100));
-            }
-            else {
-                println!("skipping heartbeat");
-            }
+        data.link.rejected = rx.rejected;
+        data.link.restarts = total_restarts;
+        data.check_alarms(api::utc_now());
 
-            start_time = Instant::now();
-            good_packets = 0;
-            tot_packets = 0;
-        }
-        */
-        
-        // give api a chance to aquire mutex lock
-
-        let buf = match radio.get_packet() {
-            Ok(n) => {
-                if n.len() == 0 || n.len() != 32 {
-                    iter_np += 1;
-                    drop(data);
-                    thread::sleep(Duration::from_millis(20));
-                    continue;
-                }
-                n
-            },
+        let frame = match res {
+            Ok(Some(n)) => n,
+            Ok(None) => {continue;},
             Err(n) => {
+                drop(data);
                 radio.sync(10).ok();
                 println!("Error getting packet: {:?}", n);
                 continue;
             }
         };
 
-
-        let time: f32 = f32::from_le_bytes(match (&buf[0..4]).try_into() {
-            Ok(n) => n,
-            Err(n) => {
-                //println!("Error | expected length {} got {} ", DATA_STREAM_SIZE, n.len());
-                continue;
-            }
-        });
-
-        let alt: f32 = f32::from_le_bytes(match (&buf[4..8]).try_into() {
-            Ok(n) => n,
-            Err(n) => {
-                //println!("Error | expected length {} got {} ", DATA_STREAM_SIZE, n.len());
-                continue;
-            }
-        });
-
-        let balt: f32 = f32::from_le_bytes(match (&buf[8..12]).try_into() {
-            Ok(n) => n,
-            Err(n) => {
-                //println!("Error | expected length {} got {} ", DATA_STREAM_SIZE, n.len());
-                continue;
-            }
-        });
-
-        let vel: f32 = f32::from_le_bytes(match (&buf[12..16]).try_into() {
-            Ok(n) => n,
-            Err(n) => {
-                //println!("Error | expected length {} got {} ", DATA_STREAM_SIZE, n.len());
-                continue;
-            }
-        });
-
-        let w: f32 = f32::from_le_bytes(match (&buf[16..20]).try_into() {
-            Ok(n) => n,
-            Err(n) => {
-                //println!("Error | expected length {} got {} ", DATA_STREAM_SIZE, n.len());
-                continue;
-            }
-        });
-
-        let x: f32 = f32::from_le_bytes(match (&buf[20..24]).try_into() {
-            Ok(n) => n,
-            Err(n) => {
-                //println!("Error | expected length {} got {} ", DATA_STREAM_SIZE, n.len());
-                continue;
-            }
-        });
-
-        let y: f32 = f32::from_le_bytes(match (&buf[24..28]).try_into() {
-            Ok(n) => n,
-            Err(n) => {
-                //println!("Error | expected length {} got {} ", DATA_STREAM_SIZE, n.len());
-                continue;
-            }
-        });
-
-        let z: f32 = f32::from_le_bytes(match (&buf[28..32]).try_into() {
-            Ok(n) => n,
-            Err(n) => {
-                //println!("Error | expected length {} got {} ", DATA_STREAM_SIZE, n.len());
-                continue;
-            }
-        });
-
-        if time != last_time {
-            data.altitude.push((time, alt));
-            data.balt.push((time, balt));
-            data.vel.push((time, vel));
-
-            data.w.push((time, w));
-            data.x.push((time, x));
-            data.y.push((time, y));
-            data.z.push((time, z));
-
-            last_time = time;
-        }
-
+        data.record(&frame);
 
         drop(data);
 
         println!("time: {}, alt: {}, balt: {}, vel: {}, or: [{}, {}, {}, {}] || fails: {}",
-            time,
-            alt,
-            balt,
-            vel,
-            w,
-            x,
-            y,
-            z,
+            frame.time,
+            frame.alt,
+            frame.balt,
+            frame.vel,
+            frame.w,
+            frame.x,
+            frame.y,
+            frame.z,
             total_restarts
         );
 
 
-        iter_np = 0;
-
-        thread::sleep(Duration::from_millis(100));
+        last_frame = Instant::now();
     } 
 
 }
 
+/// applies the current radio settings, on startup and after every reset
+fn tune(arc_data: &api::TData, radio: &mut Radio) {
+    let settings = arc_data.lock().expect("could not lock mutex").tuning.settings.clone();
+    radio.tune(&settings).expect("error configuring radio");
+
+    let mut data = arc_data.lock().expect("could not lock mutex");
+    data.tuning.applied = Some(api::utc_now());
+    data.radio = RadioState::Running;
+}
+
 fn sim(arc_data: api::TData) {
 
     let time: Vec<f32> = vec![];
@@ -323,21 +237,21 @@ fn sim(arc_data: api::TData) {
         }
 
         // handle commands
-        for command in data.cmds.iter() {
-            let (cmd, arg) = command;
-
-            println!("got cmd {}, with args {}", cmd, arg);
+        for mut command in data.commands.pending() {
+            println!("got cmd {}, with args {}", command.name, command.value);
 
-            match cmd.as_str() {
+            match command.name.as_str() {
                 "sim" => {
-                    if arg == &1.0 {
+                    if command.value == 1.0 {
                         start = true;
                     }
                 }
                 _ => {}
             }
+
+            command.status = command::CommandStatus::Executed;
+            data.update_command(command);
         }
-        data.cmds.clear();
 
         if (start) {
             data.altitude.push((time[i], alt[i])); 
@@ -368,34 +282,59 @@ fn sim(arc_data: api::TData) {
 
 
 fn main() {
-    let data = api::Data::new();
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() > 1 && args[1] == "export" {
+        match export::cli(&args[2..]) {
+            Ok(_) => {},
+            Err(n) => {
+                println!("{}", n);
+                std::process::exit(1);
+            }
+        };
+        return;
+    }
+
+    // Station.toml, then GNDAPI_* env vars, then the command line
+    let config = match Args::parse(&args[1..]).and_then(|n| StationConfig::load(&n).map(|c| (n, c))) {
+        Ok((args, config)) => {
+            if args.check {
+                println!("{}", rocket::serde::json::to_pretty_string(&config).expect("config serializes"));
+                return;
+            }
+            config
+        },
+        Err(n) => {
+            println!("{}", n);
+            std::process::exit(1);
+        }
+    };
+
+    let backoff = Backoff::new(
+        Duration::from_millis(config.supervisor.backoff_initial_ms),
+        Duration::from_millis(config.supervisor.backoff_max_ms),
+    );
+
+    let mut data = api::Data::with_config(config);
+    let store = Store::create(&data.config.storage.session_dir, data.session, data.started).expect("error creating session log");
+    println!("logging session to {}", store.path.display());
+    data.store = Some(store);
+
+    if !data.config.interlock.hazardous.is_empty() {
+        println!("hazardous commands need arming: {}", data.config.interlock.hazardous.join(", "));
+    }
+
     let thread_data: api::TData = Arc::new(Mutex::new(data));
-    let collect = Arc::clone(&thread_data);
 
+    // the radio is restarted with backoff if it fails, the api shutting down
+    // (from /cmd/quit, SIGINT or SIGTERM) stops the radio and closes the session log
+    let mut supervisor = Supervisor::new(thread_data, backoff);
 
-    
-    // move serial radio handler to thread with shared data struct
-    let handle = thread::spawn(move || {
-        println!("setting up thread");
-        radio(collect);
-    });
-    
-    // move api to thread with same shared data struct
     println!("starting api");
-    let handle2 = thread::spawn(move || {
-        start_api(thread_data);
-    });
+    supervisor.spawn("api", start_api, false);
 
-    // check if either thread quits and terminate the program if they do
-    // radio will panic with a radio error
-    // api will close once a quit command is sent
-    loop {
-        if handle.is_finished() || handle2.is_finished() {
-            println!("one of the threads closed, terminating");
-            handle.join().unwrap();
-            handle2.join().unwrap();
-            return
-        }
-    }
+    println!("setting up thread");
+    supervisor.spawn("radio", radio, true);
 
+    supervisor.run();
+    println!("ground station stopped");
 }
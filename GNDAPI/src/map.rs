@@ -0,0 +1,367 @@
+use crate::api::Data;
+
+use rocket::http::ContentType;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use serde::{Serialize, Deserialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// mean earth radius used for ranges, m
+pub const EARTH_RADIUS: f64 = 6371008.8;
+
+/// a point on the ground, degrees
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Position {
+    pub lat: f64,
+    pub long: f64,
+}
+
+impl Position {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(-90.0..=90.0).contains(&self.lat) {
+            return Err(format!("lat {} is outside -90 to 90", self.lat));
+        }
+        if !(-180.0..=180.0).contains(&self.long) {
+            return Err(format!("long {} is outside -180 to 180", self.long));
+        }
+        Ok(())
+    }
+
+    /// great circle distance to `other`, m
+    pub fn range(&self, other: &Position) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlong = (other.long - self.long).to_radians();
+
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlong / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS * a.sqrt().asin()
+    }
+
+    /// initial bearing towards `other`, degrees clockwise from north
+    pub fn bearing(&self, other: &Position) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlong = (other.long - self.long).to_radians();
+
+        let y = dlong.sin() * lat2.cos();
+        let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * dlong.cos();
+        (y.atan2(x).to_degrees() + 360.0) % 360.0
+    }
+}
+
+/// the `[map]` table
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MapConfig {
+    /// MBTiles file of raster tiles, downloaded beforehand for use without internet
+    pub tiles: Option<PathBuf>,
+    /// launch pad, the first gps fix of the session when not set
+    pub pad: Option<Position>,
+    /// ground station antenna, ranges and bearings are measured from here
+    pub station: Option<Position>,
+}
+
+impl MapConfig {
+    /// checks positions are on the globe and the tiles file can be served, returns every problem found
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors: Vec<String> = vec![];
+
+        for (name, pos) in [("pad", self.pad), ("station", self.station)] {
+            if let Some(Err(n)) = pos.map(|n| n.validate()) {
+                errors.push(format!("{}: {}", name, n));
+            }
+        }
+        if let Some(path) = self.tiles.as_ref() {
+            if let Err(n) = Tiles::open(path) {
+                errors.push(format!("tiles: {}", n));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(errors)
+        }
+    }
+}
+
+/// the `metadata` table of an MBTiles file, keys that are missing are left out
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TileInfo {
+    pub name: Option<String>,
+    /// `png`, `jpg` or `webp`
+    pub format: String,
+    pub minzoom: Option<u8>,
+    pub maxzoom: Option<u8>,
+    /// west, south, east, north
+    pub bounds: Option<[f64; 4]>,
+    /// long, lat, zoom
+    pub center: Option<[f64; 3]>,
+}
+
+/// raster tiles from an MBTiles file, see https://github.com/mapbox/mbtiles-spec
+pub struct Tiles {
+    conn: Mutex<Connection>,
+    pub info: TileInfo,
+}
+
+/// parses a comma separated list of numbers, as used by `bounds` and `center`
+fn numbers<const N: usize>(text: &str) -> Option<[f64; N]> {
+    let vals: Vec<f64> = text.split(',').map(|n| n.trim().parse::<f64>()).collect::<Result<_, _>>().ok()?;
+    vals.try_into().ok()
+}
+
+impl Tiles {
+    /// opens an MBTiles file read only
+    pub fn open(path: &Path) -> Result<Tiles, String> {
+        if !path.is_file() {
+            return Err(format!("{} does not exist", path.display()));
+        }
+
+        let conn = match Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY) {
+            Ok(n) => n,
+            Err(n) => {return Err(format!("could not open {}: {}", path.display(), n))},
+        };
+
+        let mut info = TileInfo::default();
+        let rows: Result<Vec<(String, String)>, rusqlite::Error> = conn
+            .prepare("SELECT name, value FROM metadata")
+            .and_then(|mut n| n.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?.collect());
+        let rows = match rows {
+            Ok(n) => n,
+            Err(n) => {return Err(format!("{} is not an MBTiles file: {}", path.display(), n))},
+        };
+
+        for (name, value) in rows {
+            match name.as_str() {
+                "name" => {info.name = Some(value);},
+                "format" => {info.format = value;},
+                "minzoom" => {info.minzoom = value.parse().ok();},
+                "maxzoom" => {info.maxzoom = value.parse().ok();},
+                "bounds" => {info.bounds = numbers(&value);},
+                "center" => {info.center = numbers(&value);},
+                _ => {},
+            }
+        }
+
+        if Tiles::content_type(&info.format).is_none() {
+            return Err(format!("{} holds \"{}\" tiles, only png, jpg and webp raster tiles can be shown", path.display(), info.format));
+        }
+
+        Ok(Tiles {
+            conn: Mutex::new(conn),
+            info,
+        })
+    }
+
+    fn content_type(format: &str) -> Option<ContentType> {
+        match format {
+            "png" => Some(ContentType::PNG),
+            "jpg" | "jpeg" => Some(ContentType::JPEG),
+            "webp" => Some(ContentType::new("image", "webp")),
+            _ => None,
+        }
+    }
+
+    /// the tile at zoom `z`, column `x` and row `y` counted from the north like the usual web map urls
+    pub fn tile(&self, z: u8, x: u32, y: u32) -> Result<Option<(ContentType, Vec<u8>)>, String> {
+        if z > 30 || x >= 1 << z || y >= 1 << z {
+            return Ok(None);
+        }
+        // MBTiles rows count from the south
+        let row = (1u32 << z) - 1 - y;
+
+        let conn = self.conn.lock().expect("could not lock mutex");
+        let tile: Option<Vec<u8>> = match conn
+            .query_row("SELECT tile_data FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3", (z, x, row), |n| n.get(0))
+            .optional() {
+            Ok(n) => n,
+            Err(n) => {return Err(n.to_string())},
+        };
+
+        let content_type = Tiles::content_type(&self.info.format).expect("format checked on open");
+        Ok(tile.map(|n| (content_type, n)))
+    }
+}
+
+/// a gps fix of the vehicle
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Fix {
+    /// vehicle time, seconds
+    pub time: f32,
+    /// ground receive time, UTC seconds
+    pub rx_time: f64,
+    pub lat: f64,
+    pub long: f64,
+    pub fix: f32,
+    pub quality: f32,
+}
+
+impl Fix {
+    pub fn position(&self) -> Position {
+        Position {lat: self.lat, long: self.long}
+    }
+}
+
+/// fix of the frame at `index`, none if the frame had no usable fix
+pub fn fix_at(data: &Data, index: usize) -> Option<Fix> {
+    let (time, lat) = *data.lat.get(index)?;
+    let long = data.long[index].1;
+    let fix = data.fix[index].1;
+
+    // fix type 0 is no fix, NaN is a frame without gps
+    if !(fix > 0.0 && lat.is_finite() && long.is_finite()) {
+        return None;
+    }
+
+    Some(Fix {
+        time,
+        rx_time: data.rx_time[index],
+        lat: lat as f64,
+        long: long as f64,
+        fix,
+        quality: data.quality[index].1,
+    })
+}
+
+/// where the vehicle was last seen, relative to the ground station
+#[derive(Debug, Clone, Serialize)]
+pub struct Track {
+    pub last: Fix,
+    /// from the station, m
+    pub range: Option<f64>,
+    /// from the station, degrees clockwise from north
+    pub bearing: Option<f64>,
+}
+
+/// what the map page needs besides the track itself, served by `/api/map`
+#[derive(Debug, Clone, Serialize)]
+pub struct MapStatus {
+    pub pad: Option<Position>,
+    /// true if the pad is the first fix of the session rather than configured
+    pub pad_from_fix: bool,
+    pub station: Option<Position>,
+    pub last: Option<Track>,
+    /// none when no tiles file is configured
+    pub tiles: Option<TileInfo>,
+}
+
+impl MapStatus {
+    pub fn new(data: &Data, config: &MapConfig, tiles: Option<&Tiles>) -> MapStatus {
+        let first = (0..data.rx_time.len()).find_map(|n| fix_at(data, n));
+        let last = (0..data.rx_time.len()).rev().find_map(|n| fix_at(data, n));
+
+        let station = config.station;
+        let last = last.map(|n| Track {
+            last: n,
+            range: station.map(|s| s.range(&n.position())),
+            bearing: station.map(|s| s.bearing(&n.position())),
+        });
+
+        MapStatus {
+            pad: config.pad.or(first.map(|n| n.position())),
+            pad_from_fix: config.pad.is_none() && first.is_some(),
+            station,
+            last,
+            tiles: tiles.map(|n| n.info.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::Data;
+    use crate::map::{MapConfig, MapStatus, Position, Tiles};
+    use crate::protocol::{Frame, Gps};
+
+    use rocket::http::ContentType;
+    use rusqlite::Connection;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn range_and_bearing() {
+        let station = Position {lat: 0.0, long: 0.0};
+        let north = Position {lat: 1.0, long: 0.0};
+        let east = Position {lat: 0.0, long: 1.0};
+
+        assert!((station.range(&north) - 111195.0).abs() < 1.0);
+        assert!(station.bearing(&north).abs() < 1e-9);
+        assert!((station.bearing(&east) - 90.0).abs() < 1e-9);
+        assert!((north.bearing(&station) - 180.0).abs() < 1e-9);
+
+        assert!(Position {lat: 91.0, long: 0.0}.validate().is_err());
+        assert!(Position {lat: 0.0, long: -181.0}.validate().is_err());
+    }
+
+    #[test]
+    fn mbtiles() {
+        let dir = env::temp_dir().join(format!("gndapi-map-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("field.mbtiles");
+
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch("
+            CREATE TABLE metadata (name text, value text);
+            CREATE TABLE tiles (zoom_level integer, tile_column integer, tile_row integer, tile_data blob);
+            INSERT INTO metadata VALUES ('name', 'field'), ('format', 'png'), ('minzoom', '0'), ('maxzoom', '2'),
+                ('bounds', '-180,-85.0511,180,85.0511'), ('center', '0,0,1');
+            INSERT INTO tiles VALUES (1, 0, 1, x'0102'), (1, 0, 0, x'0304');
+        ").unwrap();
+        drop(conn);
+
+        let tiles = Tiles::open(&path).unwrap();
+        assert_eq!(tiles.info.name.as_deref(), Some("field"));
+        assert_eq!(tiles.info.maxzoom, Some(2));
+        assert_eq!(tiles.info.center, Some([0.0, 0.0, 1.0]));
+
+        // rows are flipped, y = 0 is the northern row
+        assert_eq!(tiles.tile(1, 0, 0).unwrap(), Some((ContentType::PNG, vec![1, 2])));
+        assert_eq!(tiles.tile(1, 0, 1).unwrap(), Some((ContentType::PNG, vec![3, 4])));
+        assert_eq!(tiles.tile(1, 1, 0).unwrap(), None);
+        assert_eq!(tiles.tile(1, 2, 0).unwrap(), None);
+
+        let config = MapConfig {tiles: Some(path.clone()), ..MapConfig::default()};
+        assert!(config.validate().is_ok());
+
+        let conn = Connection::open(&path).unwrap();
+        conn.execute("UPDATE metadata SET value = 'pbf' WHERE name = 'format'", ()).unwrap();
+        drop(conn);
+        assert!(config.validate().unwrap_err()[0].contains("pbf"));
+
+        let missing = MapConfig {tiles: Some(dir.join("nope.mbtiles")), ..MapConfig::default()};
+        assert!(missing.validate().unwrap_err()[0].contains("does not exist"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn status() {
+        let frame = |time: f32, gps: Option<Gps>| Frame {time, alt: 0f32, balt: 0f32, vel: 0f32, w: 1f32, x: 0f32, y: 0f32, z: 0f32, gps};
+
+        let mut data = Data::new();
+        data.record(&frame(0.0, None));
+        data.record(&frame(1.0, Some(Gps {lat: 0.0, long: 0.0, fix: 0.0, quality: 0.0})));
+        data.record(&frame(2.0, Some(Gps {lat: 10.0, long: 20.0, fix: 3.0, quality: 1.0})));
+        data.record(&frame(3.0, Some(Gps {lat: 10.5, long: 20.0, fix: 3.0, quality: 1.0})));
+        data.record(&frame(4.0, None));
+
+        let config = MapConfig {station: Some(Position {lat: 10.0, long: 20.0}), ..MapConfig::default()};
+        let status = MapStatus::new(&data, &config, None);
+        assert_eq!(status.pad, Some(Position {lat: 10.0, long: 20.0}));
+        assert!(status.pad_from_fix);
+
+        let track = status.last.unwrap();
+        assert_eq!(track.last.time, 3.0);
+        assert!((track.range.unwrap() - 55597.0).abs() < 1.0);
+        assert!(track.bearing.unwrap().abs() < 1e-6);
+
+        let config = MapConfig {pad: Some(Position {lat: 1.0, long: 2.0}), ..MapConfig::default()};
+        let status = MapStatus::new(&Data::new(), &config, None);
+        assert_eq!(status.pad, Some(Position {lat: 1.0, long: 2.0}));
+        assert!(!status.pad_from_fix);
+        assert!(status.last.is_none());
+    }
+}
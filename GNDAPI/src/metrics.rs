@@ -0,0 +1,178 @@
+use crate::api::{Data, CHANNELS};
+use crate::link::LinkState;
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    Request, Response,
+};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// upper bounds of the request latency histogram buckets, seconds
+pub const LATENCY_BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    /// count of observations in each of `LATENCY_BUCKETS`, not cumulative
+    pub buckets: [u64; LATENCY_BUCKETS.len()],
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, val: f64) {
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|n| val <= *n) {
+            self.buckets[i] += 1;
+        }
+        self.sum += val;
+        self.count += 1;
+    }
+}
+
+/// http request latencies by method and route, managed by the api server
+#[derive(Default)]
+pub struct Metrics {
+    pub requests: Mutex<BTreeMap<(String, String), Histogram>>,
+}
+
+impl Metrics {
+    pub fn observe(&self, method: &str, route: &str, seconds: f64) {
+        let mut requests = self.requests.lock().expect("could not lock mutex");
+        requests.entry((method.to_string(), route.to_string())).or_default().observe(seconds);
+    }
+}
+
+/// when a request arrived, kept in the request local cache
+struct Started(Instant);
+
+/// times every request into the managed `Metrics`
+pub struct RequestTimer;
+
+#[rocket::async_trait]
+impl Fairing for RequestTimer {
+    fn info(&self) -> Info {
+        Info {
+            name: "request timer",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut rocket::Data<'_>) {
+        req.local_cache(|| Started(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, _: &mut Response<'r>) {
+        let started = req.local_cache(|| Started(Instant::now()));
+        let metrics = match req.rocket().state::<Metrics>() {
+            Some(n) => n,
+            None => {return;},
+        };
+
+        // label by route pattern, not the raw uri, so the number of series stays fixed
+        let route = match req.route() {
+            Some(n) => n.uri.origin.path().to_string(),
+            None => String::from("unmatched"),
+        };
+        metrics.observe(req.method().as_str(), &route, started.0.elapsed().as_secs_f64());
+    }
+}
+
+/// escapes a prometheus label value
+fn label(val: &str) -> String {
+    val.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// renders everything in the prometheus text exposition format
+pub fn render(data: &Data, metrics: &Metrics, now: f64) -> String {
+    let mut out = String::new();
+
+    // writing to a String can not fail
+    let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, f64)>| {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for (labels, val) in samples {
+            let _ = writeln!(out, "{}{} {}", name, labels, val);
+        }
+    };
+
+    metric("gndapi_frames_received_total", "counter", "telemetry frames accepted", vec![(String::new(), data.link.frames as f64)]);
+    metric("gndapi_frames_rejected_total", "counter", "packets that could not be decoded as a frame", vec![(String::new(), data.link.rejected as f64)]);
+    metric("gndapi_radio_restarts_total", "counter", "radio resets after the link went quiet", vec![(String::new(), data.link.restarts as f64)]);
+    metric("gndapi_radio_crashes_total", "counter", "radio thread failures restarted by the supervisor", vec![(String::new(), data.link.crashes as f64)]);
+
+    let mut latest: Vec<(String, f64)> = vec![];
+    for def in CHANNELS.iter() {
+        if let Some((_, val)) = data.channel(def.name).and_then(|n| n.last()) {
+            latest.push((format!("{{channel=\"{}\",unit=\"{}\"}}", def.name, label(def.unit)), *val as f64));
+        }
+    }
+    metric("gndapi_channel_value", "gauge", "latest value of each telemetry channel", latest);
+
+    let state = data.link.state(now);
+    let states = [LinkState::Waiting, LinkState::Locked, LinkState::Degraded, LinkState::Lost];
+    metric("gndapi_link_state", "gauge", "1 for the current link state", states.iter().map(|n| {
+        (format!("{{state=\"{}\"}}", format!("{:?}", n).to_lowercase()), if *n == state {1f64} else {0f64})
+    }).collect());
+    if let Some(age) = data.link.age(now) {
+        metric("gndapi_link_age_seconds", "gauge", "seconds since the last accepted frame", vec![(String::new(), age)]);
+    }
+
+    metric("gndapi_samples", "gauge", "telemetry frames held in memory", vec![(String::new(), data.rx_time.len() as f64)]);
+    if let Some(store) = data.store.as_ref() {
+        let bytes = fs::metadata(&store.path).map(|n| n.len()).unwrap_or(0);
+        metric("gndapi_store_records", "gauge", "records written to the session log", vec![(String::new(), store.records as f64)]);
+        metric("gndapi_store_bytes", "gauge", "size of the session log on disk", vec![(String::new(), bytes as f64)]);
+    }
+
+    metric("gndapi_uptime_seconds", "gauge", "seconds since the ground station started", vec![(String::new(), now - data.started)]);
+
+    let requests = metrics.requests.lock().expect("could not lock mutex").clone();
+    let mut samples: Vec<(String, f64)> = vec![];
+    for ((method, route), hist) in requests.iter() {
+        let labels = format!("method=\"{}\",route=\"{}\"", method, label(route));
+
+        let mut total: u64 = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(hist.buckets.iter()) {
+            total += count;
+            samples.push((format!("_bucket{{{},le=\"{}\"}}", labels, bound), total as f64));
+        }
+        samples.push((format!("_bucket{{{},le=\"+Inf\"}}", labels), hist.count as f64));
+        samples.push((format!("_sum{{{}}}", labels), hist.sum));
+        samples.push((format!("_count{{{}}}", labels), hist.count as f64));
+    }
+    metric("gndapi_http_request_duration_seconds", "histogram", "time to respond to http requests", samples);
+
+    return out;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::Data;
+    use crate::metrics::{render, Metrics};
+    use crate::protocol::Frame;
+
+    #[test]
+    fn text_format() {
+        let mut data = Data::new();
+        let now = data.started;
+        data.record_at(&Frame {time: 1f32, alt: 120.5, balt: 0f32, vel: 0f32, w: 1f32, x: 0f32, y: 0f32, z: 0f32, gps: None}, now);
+
+        let metrics = Metrics::default();
+        metrics.observe("GET", "/api/state", 0.002);
+        metrics.observe("GET", "/api/state", 3.0);
+
+        let out = render(&data, &metrics, now + 0.5);
+        assert!(out.contains("# TYPE gndapi_frames_received_total counter\ngndapi_frames_received_total 1\n"));
+        assert!(out.contains("gndapi_channel_value{channel=\"alt\",unit=\"m\"} 120.5\n"));
+        assert!(out.contains("gndapi_link_state{state=\"locked\"} 1\n"));
+        assert!(out.contains("gndapi_link_state{state=\"lost\"} 0\n"));
+        assert!(out.contains("gndapi_http_request_duration_seconds_bucket{method=\"GET\",route=\"/api/state\",le=\"0.001\"} 0\n"));
+        assert!(out.contains("gndapi_http_request_duration_seconds_bucket{method=\"GET\",route=\"/api/state\",le=\"0.005\"} 1\n"));
+        assert!(out.contains("gndapi_http_request_duration_seconds_bucket{method=\"GET\",route=\"/api/state\",le=\"2.5\"} 1\n"));
+        assert!(out.contains("gndapi_http_request_duration_seconds_bucket{method=\"GET\",route=\"/api/state\",le=\"+Inf\"} 2\n"));
+        assert!(out.contains("gndapi_http_request_duration_seconds_count{method=\"GET\",route=\"/api/state\"} 2\n"));
+    }
+}
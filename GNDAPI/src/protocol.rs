@@ -1,5 +1,94 @@
+use serde::{Serialize, Deserialize};
+
+/// size of the big endian `RocketData` stream of the old firmware
+#[cfg(test)]
 pub const DATA_STREAM_SIZE: usize = 34;
 
+/// size of the little endian telemetry frame sent by `STM32/main.cpp::transmit()`
+#[cfg(test)]
+pub const FRAME_SIZE: usize = 32;
+
+/// a single telemetry frame as transmitted by the vehicle
+/// 
+/// all values are little endian f32s, `time` is in seconds since boot
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    #[serde(with = "lossless")]
+    pub time: f32,
+    #[serde(with = "lossless")]
+    pub alt: f32,
+    #[serde(with = "lossless")]
+    pub balt: f32,
+    #[serde(with = "lossless")]
+    pub vel: f32,
+    #[serde(with = "lossless")]
+    pub w: f32,
+    #[serde(with = "lossless")]
+    pub x: f32,
+    #[serde(with = "lossless")]
+    pub y: f32,
+    #[serde(with = "lossless")]
+    pub z: f32,
+    /// only present when the packet schema has the `GPS_FIELDS`
+    #[serde(flatten)]
+    pub gps: Option<Gps>,
+}
+
+/// gps fix carried by a frame, as in the old `RocketData`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Gps {
+    /// degrees
+    #[serde(with = "lossless")]
+    pub lat: f32,
+    /// degrees
+    #[serde(with = "lossless")]
+    pub long: f32,
+    /// fix type, 0 for no fix
+    #[serde(with = "lossless")]
+    pub fix: f32,
+    /// fix quality
+    #[serde(with = "lossless")]
+    pub quality: f32,
+}
+
+/// serde for the frame floats that keeps NaN and the infinities, which JSON numbers can't hold
+///
+/// they are written as `"NaN"`, `"inf"` and `"-inf"`, a `null` from an older session log reads as NaN
+mod lossless {
+    use serde::{Serializer, Deserializer, Deserialize, de::Error};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Number(f32),
+        Text(String),
+    }
+
+    pub fn serialize<S: Serializer>(value: &f32, serializer: S) -> Result<S::Ok, S::Error> {
+        match *value {
+            n if n.is_finite() => serializer.serialize_f32(n),
+            n if n.is_nan() => serializer.serialize_str("NaN"),
+            n if n > 0f32 => serializer.serialize_str("inf"),
+            _ => serializer.serialize_str("-inf"),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+        match Option::<Repr>::deserialize(deserializer)? {
+            Some(Repr::Number(n)) => Ok(n),
+            None => Ok(f32::NAN),
+            Some(Repr::Text(n)) => match n.as_str() {
+                "NaN" => Ok(f32::NAN),
+                "inf" => Ok(f32::INFINITY),
+                "-inf" => Ok(f32::NEG_INFINITY),
+                _ => Err(D::Error::custom(format!("expected a number, \"NaN\", \"inf\" or \"-inf\", got \"{}\"", n))),
+            },
+        }
+    }
+}
+
+/// telemetry of the old firmware, only the protocol tests still use it
+#[cfg(test)]
 #[derive(Debug)]
 pub struct RocketData {
     pub time: u32,
@@ -16,6 +105,7 @@ pub struct RocketData {
     pub cont2: bool,
 }
 
+#[cfg(test)]
 impl PartialEq for RocketData {
     fn eq(&self, other: &Self) -> bool {
         let b_time = self.time == other.time;
@@ -35,6 +125,7 @@ impl PartialEq for RocketData {
     }
 }
 
+#[cfg(test)]
 pub fn encode_stream(data: &RocketData) -> Result<[u8; DATA_STREAM_SIZE], String> {
     let mut buf: Vec<u8> = vec![];
 
@@ -68,6 +159,7 @@ pub fn encode_stream(data: &RocketData) -> Result<[u8; DATA_STREAM_SIZE], String
     }
 }
 
+#[cfg(test)]
 pub fn decode_stream(buf: [u8; DATA_STREAM_SIZE]) -> Result<RocketData, String> {
     let time: u32 = u32::from_be_bytes(match buf[0..4].try_into(){
         Ok(n) => n,
@@ -118,10 +210,156 @@ pub fn decode_stream(buf: [u8; DATA_STREAM_SIZE]) -> Result<RocketData, String>
     Ok(RocketData {time, altitude, orx, ory, orz, lat, long, speed, fix, quality, cont1, cont2})
 }
 
+impl Frame {
+    /// sets a field by name, `time` included, false if there is no such field
+    pub fn set(&mut self, name: &str, val: f32) -> bool {
+        match name {
+            "time" => self.time = val,
+            "alt" => self.alt = val,
+            "balt" => self.balt = val,
+            "vel" => self.vel = val,
+            "w" => self.w = val,
+            "x" => self.x = val,
+            "y" => self.y = val,
+            "z" => self.z = val,
+            "lat" => self.gps.get_or_insert_with(Gps::default).lat = val,
+            "long" => self.gps.get_or_insert_with(Gps::default).long = val,
+            "fix" => self.gps.get_or_insert_with(Gps::default).fix = val,
+            "quality" => self.gps.get_or_insert_with(Gps::default).quality = val,
+            _ => {return false;},
+        };
+        true
+    }
+
+    /// value of a field by its api channel name
+    pub fn field(&self, name: &str) -> Option<f32> {
+        match name {
+            "alt" => Some(self.alt),
+            "balt" => Some(self.balt),
+            "vel" => Some(self.vel),
+            "w" => Some(self.w),
+            "x" => Some(self.x),
+            "y" => Some(self.y),
+            "z" => Some(self.z),
+            "lat" => self.gps.map(|n| n.lat),
+            "long" => self.gps.map(|n| n.long),
+            "fix" => self.gps.map(|n| n.fix),
+            "quality" => self.gps.map(|n| n.quality),
+            _ => None,
+        }
+    }
+}
+
+/// the bytes the vehicle would send for `frame`, for feeding simulated radios
+#[cfg(test)]
+pub fn encode_frame(frame: &Frame) -> [u8; FRAME_SIZE] {
+    let mut buf: [u8; FRAME_SIZE] = [0u8; FRAME_SIZE];
+
+    let vals: [f32; 8] = [frame.time, frame.alt, frame.balt, frame.vel, frame.w, frame.x, frame.y, frame.z];
+    for (i, val) in vals.iter().enumerate() {
+        buf[i*4..i*4 + 4].copy_from_slice(&val.to_le_bytes());
+    }
+
+    return buf;
+}
+
+/// fields of `Frame` in the order the vehicle sends them, as in `STM32/main.cpp::transmit()`
+pub const FRAME_FIELDS: [&str; 8] = ["time", "alt", "balt", "vel", "w", "x", "y", "z"];
+
+/// optional fields of `Gps`, a schema has either all or none of them
+pub const GPS_FIELDS: [&str; 4] = ["lat", "long", "fix", "quality"];
+
+/// layout of the telemetry frame, one little endian f32 per field
+/// 
+/// `_` marks 4 bytes the ground station ignores
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Schema {
+    pub fields: Vec<String>,
+}
+
+impl Default for Schema {
+    fn default() -> Schema {
+        Schema {fields: FRAME_FIELDS.iter().map(|n| n.to_string()).collect()}
+    }
+}
+
+impl Schema {
+    pub fn frame_size(&self) -> usize {
+        self.fields.len() * 4
+    }
+
+    /// every frame field has to appear exactly once, the gps fields all once or not at all
+    pub fn validate(&self) -> Result<(), String> {
+        for name in self.fields.iter() {
+            if name != "_" && !FRAME_FIELDS.contains(&name.as_str()) && !GPS_FIELDS.contains(&name.as_str()) {
+                return Err(format!("unknown field \"{}\", expected one of {}, {} or _", name, FRAME_FIELDS.join(", "), GPS_FIELDS.join(", ")));
+            }
+        }
+
+        for name in FRAME_FIELDS.iter().chain(GPS_FIELDS.iter()) {
+            match self.fields.iter().filter(|n| n == name).count() {
+                0 | 1 => {},
+                _ => {return Err(format!("field \"{}\" appears more than once", name))},
+            };
+        }
+
+        for name in FRAME_FIELDS.iter() {
+            if !self.fields.iter().any(|n| n == name) {
+                return Err(format!("missing field \"{}\"", name));
+            }
+        }
+
+        if self.has_gps() {
+            for name in GPS_FIELDS.iter() {
+                if !self.fields.iter().any(|n| n == name) {
+                    return Err(format!("missing gps field \"{}\", the gps fields go together", name));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// frames carry a gps fix
+    pub fn has_gps(&self) -> bool {
+        self.fields.iter().any(|n| GPS_FIELDS.contains(&n.as_str()))
+    }
+
+    pub fn decode(&self, buf: &[u8]) -> Result<Frame, String> {
+        if buf.len() != self.frame_size() {
+            return Err(format!("expected length {} got {}", self.frame_size(), buf.len()));
+        }
+
+        let mut frame = Frame {time: 0f32, alt: 0f32, balt: 0f32, vel: 0f32, w: 0f32, x: 0f32, y: 0f32, z: 0f32, gps: None};
+        for (i, name) in self.fields.iter().enumerate() {
+            let val = f32::from_le_bytes(match buf[i*4..i*4 + 4].try_into() {
+                Ok(n) => n,
+                Err(_) => {return Err(format!("error converting bytes {}..{} to f32", i*4, i*4 + 4))},
+            });
+            frame.set(name, val);
+        }
+
+        Ok(frame)
+    }
+}
+
+/// size of an uplink command, an opcode followed by a little endian f32 argument
+pub const COMMAND_SIZE: usize = 5;
+
+pub fn encode_command(opcode: u8, arg: f32) -> [u8; COMMAND_SIZE] {
+    let mut buf: [u8; COMMAND_SIZE] = [0u8; COMMAND_SIZE];
+    buf[0] = opcode;
+    buf[1..].copy_from_slice(&arg.to_le_bytes());
+
+    return buf;
+}
+
 #[cfg(test)]
 mod tests {
     use rand::Rng;
-    use crate::protocol::{RocketData, encode_stream, decode_stream};
+    use crate::protocol::{RocketData, encode_stream, decode_stream, Frame, Gps, encode_frame, encode_command, Schema, FRAME_FIELDS};
+    use rocket::serde::json;
 
     fn generate_random_data() -> RocketData {
         let mut rng = rand::thread_rng();
@@ -183,4 +421,62 @@ mod tests {
         assert_eq!(data, new_data);
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn frame_enc_dec() {
+        let frame = Frame {time: 1.5f32, alt: 2f32, balt: 3f32, vel: 4f32, w: 5f32, x: 6f32, y: 7f32, z: 8f32, gps: None};
+
+        let buf = encode_frame(&frame);
+        assert_eq!(&buf[0..4], &1.5f32.to_le_bytes());
+        assert_eq!(&buf[28..32], &8f32.to_le_bytes());
+
+        assert_eq!(Schema::default().decode(&buf).expect("error decoding frame"), frame);
+    }
+
+    #[test]
+    fn frame_bad_len() {
+        assert!(Schema::default().decode(&[0u8; 31]).is_err());
+        assert!(Schema::default().decode(&[]).is_err());
+    }
+
+    #[test]
+    fn command_enc() {
+        assert_eq!(encode_command(2, 1.5), [2, 0, 0, 0xc0, 0x3f]);
+    }
+
+    #[test]
+    fn schema_order() {
+        let schema = Schema {fields: ["_", "z", "y", "x", "w", "vel", "balt", "alt", "time"].iter().map(|n| n.to_string()).collect()};
+        assert!(schema.validate().is_ok());
+        assert_eq!(schema.frame_size(), 36);
+
+        let vals: [f32; 9] = [99.0, 8.0, 7.0, 6.0, 5.0, 4.0, 3.0, 2.0, 1.0];
+        let buf: Vec<u8> = vals.iter().flat_map(|n| n.to_le_bytes()).collect();
+        assert_eq!(schema.decode(&buf).unwrap(), Frame {time: 1.0, alt: 2.0, balt: 3.0, vel: 4.0, w: 5.0, x: 6.0, y: 7.0, z: 8.0, gps: None});
+
+        assert!(Schema {fields: vec!["time".to_string()]}.validate().unwrap_err().contains("missing field \"alt\""));
+        assert!(Schema {fields: vec!["speed".to_string()]}.validate().unwrap_err().contains("speed"));
+    }
+
+    #[test]
+    fn schema_gps() {
+        let fields = |names: &[&str]| Schema {fields: FRAME_FIELDS.iter().chain(names.iter()).map(|n| n.to_string()).collect()};
+
+        let schema = fields(&["lat", "long", "fix", "quality"]);
+        assert!(schema.validate().is_ok());
+        assert!(schema.has_gps());
+        assert!(!Schema::default().has_gps());
+        assert!(fields(&["lat", "long"]).validate().unwrap_err().contains("\"fix\""));
+
+        let vals: [f32; 12] = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 47.65, -122.3, 3.0, 1.0];
+        let buf: Vec<u8> = vals.iter().flat_map(|n| n.to_le_bytes()).collect();
+        let frame = schema.decode(&buf).unwrap();
+        assert_eq!(frame.gps, Some(Gps {lat: 47.65, long: -122.3, fix: 3.0, quality: 1.0}));
+        assert_eq!(frame.field("long"), Some(-122.3));
+
+        let text = json::to_string(&frame).unwrap();
+        assert!(text.contains("\"lat\":47.65"));
+        assert_eq!(json::from_str::<Frame>(&text).unwrap(), frame);
+        assert_eq!(json::from_str::<Frame>(&json::to_string(&Schema::default().decode(&buf[..32]).unwrap()).unwrap()).unwrap().gps, None);
+    }
+}
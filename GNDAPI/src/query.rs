@@ -0,0 +1,463 @@
+/// which clock a time bound is given in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Clock {
+    /// seconds since vehicle boot, as sent in each frame
+    Vehicle,
+    /// ground receive time, UTC seconds since the unix epoch
+    Utc,
+}
+
+impl Clock {
+    pub fn parse(name: &str) -> Option<Clock> {
+        match name {
+            "vehicle" => Some(Clock::Vehicle),
+            "utc" => Some(Clock::Utc),
+            _ => None,
+        }
+    }
+}
+
+/// bounds of a time range query, all optional
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Range {
+    /// inclusive lower bound
+    pub from: Option<f64>,
+    /// inclusive upper bound
+    pub to: Option<f64>,
+    /// exclusive lower bound, for "everything after the last sample I have"
+    pub since: Option<f64>,
+    /// index of the first sample the client has not seen yet
+    pub cursor: Option<usize>,
+}
+
+/// first index whose key is past `bound`
+///
+/// `key` must be sorted ascending over `0..len`
+///
+/// with `inclusive` the bound itself counts as past
+pub fn first_past<F: Fn(usize) -> f64>(len: usize, key: F, bound: f64, inclusive: bool) -> usize {
+    let mut lo: usize = 0;
+    let mut hi: usize = len;
+
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        let k = key(mid);
+        let past = if inclusive {k >= bound} else {k > bound};
+
+        if past {
+            hi = mid;
+        }
+        else {
+            lo = mid + 1;
+        }
+    }
+
+    return lo;
+}
+
+/// index of the first sample after the last time `key` went backwards, 0 if it never did
+///
+/// the vehicle clock restarts from zero when it reboots
+pub fn last_boot<F: Fn(usize) -> f64>(len: usize, key: F) -> usize {
+    let mut i: usize = len;
+
+    while i > 1 {
+        if key(i - 1) < key(i - 2) {
+            return i - 1;
+        }
+        i -= 1;
+    }
+
+    return 0;
+}
+
+/// resolves a range query to sample indices
+///
+/// time bounds are searched in the samples since the last reboot, everything received
+/// before it counts as earlier than any bound
+///
+/// ## Returns
+/// `(start, end, cursor)` where `start..end` are the matching samples and `cursor`
+/// is the index the next incremental poll should resume from
+pub fn resolve<F: Fn(usize) -> f64>(len: usize, key: F, range: &Range) -> (usize, usize, usize) {
+    let mut start: usize = range.cursor.unwrap_or(0).min(len);
+    let mut end: usize = len;
+
+    let boot = if range.from.is_some() || range.since.is_some() || range.to.is_some() {last_boot(len, &key)} else {0};
+    let past = |bound: f64, inclusive: bool| boot + first_past(len - boot, |i| key(boot + i), bound, inclusive);
+
+    if let Some(from) = range.from {
+        start = start.max(past(from, true));
+    }
+    if let Some(since) = range.since {
+        start = start.max(past(since, false));
+    }
+    if let Some(to) = range.to {
+        end = end.min(past(to, false));
+    }
+
+    if start > end {
+        // everything up to start has already been seen, nothing new in range
+        return (start, start, start);
+    }
+
+    return (start, end, end);
+}
+
+/// builds a cursor token tied to the current session
+///
+/// tokens from a previous run of the ground station are rejected, since their indices mean nothing now
+pub fn encode_cursor(session: u64, index: usize) -> String {
+    format!("{:x}-{:x}", session, index)
+}
+
+pub fn decode_cursor(session: u64, token: &str) -> Result<usize, String> {
+    let (s, i) = match token.split_once('-') {
+        Some(n) => n,
+        None => {return Err(format!("malformed cursor \"{}\"", token))},
+    };
+
+    let s = match u64::from_str_radix(s, 16) {
+        Ok(n) => n,
+        Err(_) => {return Err(format!("malformed cursor \"{}\"", token))},
+    };
+    let i = match usize::from_str_radix(i, 16) {
+        Ok(n) => n,
+        Err(_) => {return Err(format!("malformed cursor \"{}\"", token))},
+    };
+
+    if s != session {
+        return Err("cursor is from a different session".to_string());
+    }
+
+    Ok(i)
+}
+
+/// shape preserving downsampling method
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Downsample {
+    /// Largest-Triangle-Three-Buckets, keeps the points that shape the line
+    Lttb,
+    /// min and max of each bucket, keeps every peak
+    MinMax,
+}
+
+impl Downsample {
+    pub fn parse(name: &str) -> Option<Downsample> {
+        match name {
+            "lttb" => Some(Downsample::Lttb),
+            "minmax" => Some(Downsample::MinMax),
+            _ => None,
+        }
+    }
+
+    /// reduces `samples` to at most `max_points`
+    pub fn apply(&self, samples: &[(f32, f32)], max_points: usize) -> Vec<(f32, f32)> {
+        match self {
+            Downsample::Lttb => lttb(samples, max_points),
+            Downsample::MinMax => min_max(samples, max_points),
+        }
+    }
+}
+
+/// Largest-Triangle-Three-Buckets downsampling
+///
+/// keeps the first and last sample, then from each bucket picks the sample forming
+/// the largest triangle with the previous pick and the average of the next bucket
+pub fn lttb(samples: &[(f32, f32)], max_points: usize) -> Vec<(f32, f32)> {
+    if max_points >= samples.len() {
+        return samples.to_vec();
+    }
+
+    // too few points for buckets, endpoints are the best we can do
+    match max_points {
+        0 => {return vec![]},
+        1 => {return vec![samples[0]]},
+        2 => {return vec![samples[0], samples[samples.len() - 1]]},
+        _ => {},
+    };
+
+    let mut out: Vec<(f32, f32)> = Vec::with_capacity(max_points);
+    let every = (samples.len() - 2) as f64 / (max_points - 2) as f64;
+
+    let mut a: usize = 0;
+    out.push(samples[0]);
+
+    for i in 0..max_points - 2 {
+        // average of the next bucket, the last bucket looks at the final sample
+        let next_start = ((i + 1) as f64 * every) as usize + 1;
+        let next_end = (((i + 2) as f64 * every) as usize + 1).min(samples.len());
+        let next = if next_start < next_end {&samples[next_start..next_end]} else {&samples[samples.len() - 1..]};
+
+        let avg_t = next.iter().map(|s| s.0 as f64).sum::<f64>() / next.len() as f64;
+        let avg_v = next.iter().map(|s| s.1 as f64).sum::<f64>() / next.len() as f64;
+
+        let start = (i as f64 * every) as usize + 1;
+        let end = (((i + 1) as f64 * every) as usize + 1).min(samples.len() - 1);
+
+        let (at, av) = (samples[a].0 as f64, samples[a].1 as f64);
+        let mut best: usize = start;
+        let mut best_area: f64 = -1f64;
+
+        for j in start..end {
+            let (t, v) = (samples[j].0 as f64, samples[j].1 as f64);
+            let area = ((at - avg_t) * (v - av) - (at - t) * (avg_v - av)).abs();
+            if area > best_area {
+                best_area = area;
+                best = j;
+            }
+        }
+
+        out.push(samples[best]);
+        a = best;
+    }
+
+    out.push(samples[samples.len() - 1]);
+
+    return out;
+}
+
+/// min / max per bucket downsampling
+///
+/// splits the samples into `max_points / 2` buckets and keeps the lowest and highest
+/// sample of each, in time order
+pub fn min_max(samples: &[(f32, f32)], max_points: usize) -> Vec<(f32, f32)> {
+    if max_points >= samples.len() {
+        return samples.to_vec();
+    }
+
+    let buckets = max_points / 2;
+    if buckets == 0 {
+        return samples.iter().take(max_points).copied().collect();
+    }
+
+    let mut out: Vec<(f32, f32)> = Vec::with_capacity(buckets * 2);
+    let every = samples.len() as f64 / buckets as f64;
+
+    for i in 0..buckets {
+        let start = (i as f64 * every) as usize;
+        let end = (((i + 1) as f64 * every) as usize).min(samples.len());
+        if start >= end {
+            continue;
+        }
+
+        let bucket = &samples[start..end];
+        let mut lo: usize = 0;
+        let mut hi: usize = 0;
+        for (j, s) in bucket.iter().enumerate() {
+            if s.1 < bucket[lo].1 {
+                lo = j;
+            }
+            if s.1 > bucket[hi].1 {
+                hi = j;
+            }
+        }
+
+        if lo == hi {
+            out.push(bucket[lo]);
+        }
+        else {
+            out.push(bucket[lo.min(hi)]);
+            out.push(bucket[lo.max(hi)]);
+        }
+    }
+
+    return out;
+}
+
+/// how to fill a channel's value at a time it has no sample for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interp {
+    /// only samples with exactly that timestamp, i.e. from the same frame
+    Exact,
+    /// the most recent sample at or before that time
+    Hold,
+    /// linear between the samples either side
+    Linear,
+}
+
+impl Interp {
+    pub fn parse(name: &str) -> Option<Interp> {
+        match name {
+            "none" => Some(Interp::Exact),
+            "hold" => Some(Interp::Hold),
+            "linear" => Some(Interp::Linear),
+            _ => None,
+        }
+    }
+}
+
+/// value of a channel at time `t`
+/// 
+/// `None` if the channel has no value there under the given `interp`
+pub fn value_at(samples: &[(f32, f32)], t: f64, interp: Interp) -> Option<f32> {
+    let i = first_past(samples.len(), |i| samples[i].0 as f64, t, true);
+
+    if i < samples.len() && samples[i].0 as f64 == t {
+        return Some(samples[i].1);
+    }
+
+    match interp {
+        Interp::Exact => None,
+        Interp::Hold => {
+            if i == 0 {None} else {Some(samples[i - 1].1)}
+        },
+        Interp::Linear => {
+            if i == 0 || i == samples.len() {
+                return None;
+            }
+
+            let (t0, v0) = (samples[i - 1].0 as f64, samples[i - 1].1 as f64);
+            let (t1, v1) = (samples[i].0 as f64, samples[i].1 as f64);
+            Some((v0 + (v1 - v0) * (t - t0) / (t1 - t0)) as f32)
+        },
+    }
+}
+
+/// most rows a single resampled request returns
+pub const MAX_ROWS: usize = 200_000;
+
+/// evenly spaced timestamps from `start` to `end` inclusive at `rate` hz
+pub fn grid(start: f64, end: f64, rate: f64) -> Vec<f64> {
+    let mut out: Vec<f64> = vec![];
+    if end < start {
+        return out;
+    }
+
+    let count = ((end - start) * rate).floor() as usize + 1;
+    for i in 0..count {
+        out.push(start + i as f64 / rate);
+    }
+
+    return out;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query::{first_past, last_boot, resolve, Range, encode_cursor, decode_cursor, lttb, min_max, value_at, grid, Interp};
+
+    const TIMES: [f64; 6] = [0.0, 0.5, 1.0, 1.0, 2.0, 3.5];
+
+    #[test]
+    fn binary_search() {
+        let key = |i: usize| TIMES[i];
+
+        assert_eq!(first_past(TIMES.len(), key, 1.0, true), 2);
+        assert_eq!(first_past(TIMES.len(), key, 1.0, false), 4);
+        assert_eq!(first_past(TIMES.len(), key, -1.0, true), 0);
+        assert_eq!(first_past(TIMES.len(), key, 9.0, true), 6);
+        assert_eq!(first_past(0, key, 1.0, true), 0);
+    }
+
+    #[test]
+    fn from_to() {
+        let range = Range {from: Some(0.5), to: Some(2.0), ..Default::default()};
+        assert_eq!(resolve(TIMES.len(), |i| TIMES[i], &range), (1, 5, 5));
+
+        let range = Range {since: Some(1.0), ..Default::default()};
+        assert_eq!(resolve(TIMES.len(), |i| TIMES[i], &range), (4, 6, 6));
+
+        let range = Range {from: Some(3.0), to: Some(1.0), ..Default::default()};
+        let (start, end, _) = resolve(TIMES.len(), |i| TIMES[i], &range);
+        assert_eq!(start, end);
+    }
+
+    #[test]
+    fn reboot() {
+        // the vehicle clock restarts at index 4
+        let times = [0.0, 1.0, 2.0, 3.0, 0.0, 1.0, 2.0];
+        let key = |i: usize| times[i];
+
+        assert_eq!(last_boot(times.len(), key), 4);
+        assert_eq!(last_boot(TIMES.len(), |i| TIMES[i]), 0);
+        assert_eq!(last_boot(0, key), 0);
+
+        let range = Range {from: Some(0.5), to: Some(1.5), ..Default::default()};
+        assert_eq!(resolve(times.len(), key, &range), (5, 6, 6));
+
+        let range = Range {since: Some(2.5), ..Default::default()};
+        assert_eq!(resolve(times.len(), key, &range), (7, 7, 7));
+
+        let range = Range {to: Some(0.5), ..Default::default()};
+        assert_eq!(resolve(times.len(), key, &range), (0, 5, 5));
+    }
+
+    #[test]
+    fn cursor_polling() {
+        // poll while samples are appended, every sample is returned exactly once
+        let mut seen: Vec<usize> = vec![];
+        let mut cursor: Option<usize> = None;
+
+        for len in [0, 2, 2, 3, 6] {
+            let range = Range {cursor, ..Default::default()};
+            let (start, end, next) = resolve(len, |i| TIMES[i], &range);
+            seen.extend(start..end);
+            cursor = Some(next);
+        }
+
+        assert_eq!(seen, vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn cursor_tokens() {
+        let token = encode_cursor(1234, 42);
+        assert_eq!(decode_cursor(1234, &token), Ok(42));
+        assert!(decode_cursor(1235, &token).is_err());
+        assert!(decode_cursor(1234, "garbage").is_err());
+    }
+
+    /// a flat line with a single spike half way through
+    fn spike(len: usize) -> Vec<(f32, f32)> {
+        (0..len).map(|i| (i as f32, if i == len / 2 {100f32} else {0f32})).collect()
+    }
+
+    #[test]
+    fn lttb_keeps_shape() {
+        let samples = spike(1000);
+        let out = lttb(&samples, 50);
+
+        assert_eq!(out.len(), 50);
+        assert_eq!(out[0], samples[0]);
+        assert_eq!(out[49], samples[999]);
+        assert!(out.contains(&(500f32, 100f32)));
+        assert!(out.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn min_max_keeps_peaks() {
+        let samples = spike(1000);
+        let out = min_max(&samples, 50);
+
+        assert!(out.len() <= 50);
+        assert!(out.contains(&(500f32, 100f32)));
+        assert!(out.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn downsample_small_inputs() {
+        let samples = spike(10);
+        assert_eq!(lttb(&samples, 20), samples);
+        assert_eq!(min_max(&samples, 10), samples);
+        assert_eq!(lttb(&samples, 2), vec![samples[0], samples[9]]);
+        assert_eq!(lttb(&samples, 0), vec![]);
+    }
+
+    #[test]
+    fn alignment() {
+        let samples = vec![(0f32, 0f32), (1f32, 10f32), (2f32, 20f32)];
+
+        assert_eq!(value_at(&samples, 1.0, Interp::Exact), Some(10f32));
+        assert_eq!(value_at(&samples, 1.5, Interp::Exact), None);
+        assert_eq!(value_at(&samples, 1.5, Interp::Hold), Some(10f32));
+        assert_eq!(value_at(&samples, 1.5, Interp::Linear), Some(15f32));
+        assert_eq!(value_at(&samples, -1.0, Interp::Hold), None);
+        assert_eq!(value_at(&samples, 3.0, Interp::Linear), None);
+        assert_eq!(value_at(&samples, 3.0, Interp::Hold), Some(20f32));
+    }
+
+    #[test]
+    fn resample_grid() {
+        assert_eq!(grid(1.0, 2.0, 4.0), vec![1.0, 1.25, 1.5, 1.75, 2.0]);
+        assert_eq!(grid(2.0, 1.0, 4.0), Vec::<f64>::new());
+    }
+}
@@ -0,0 +1,427 @@
+use crate::api::{Data, CHANNELS};
+use crate::command::CommandStatus;
+use crate::event::{Confidence, EventKind};
+use crate::store;
+
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// playback speeds a cursor accepts, times real time
+pub const MIN_SPEED: f64 = 0.25;
+pub const MAX_SPEED: f64 = 10.0;
+
+/// cursors held at once, the least recently used one is closed to make room
+pub const MAX_REPLAYS: usize = 8;
+
+/// seconds a cursor is kept without being used
+pub const REPLAY_IDLE: f64 = 600.0;
+
+/// a session log on disk
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionFile {
+    pub id: u64,
+    pub path: PathBuf,
+    pub bytes: u64,
+    /// the session this station is logging right now
+    pub current: bool,
+}
+
+/// session logs in `dir`, newest first
+pub fn sessions(dir: &Path, current: u64) -> Result<Vec<SessionFile>, String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(n) => n,
+        Err(n) => {return Err(format!("could not list {}: {}", dir.display(), n))},
+    };
+
+    let mut out: Vec<SessionFile> = vec![];
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|n| n.to_str()) != Some("jsonl") {
+            continue;
+        }
+        let id: u64 = match path.file_stem().and_then(|n| n.to_str()).and_then(|n| n.parse().ok()) {
+            Some(n) => n,
+            None => continue,
+        };
+
+        out.push(SessionFile {
+            id,
+            bytes: entry.metadata().map(|n| n.len()).unwrap_or(0),
+            path,
+            current: id == current,
+        });
+    }
+
+    out.sort_by(|a, b| b.id.cmp(&a.id));
+    Ok(out)
+}
+
+/// something worth a mark on the replay timeline
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Marker {
+    /// vehicle time, seconds
+    pub time: f32,
+    /// a flight event kind, e.g. `liftoff` or `apogee`, or `command`
+    pub kind: &'static str,
+    pub label: String,
+}
+
+/// vehicle time of the frame received at or after `rx_time` (UTC seconds)
+fn vehicle_time(data: &Data, rx_time: f64) -> Option<f32> {
+    let index = data.rx_time.partition_point(|n| *n < rx_time);
+    data.altitude.get(index.min(data.altitude.len().saturating_sub(1))).map(|n| n.0)
+}
+
+/// flight events and commands sent during the session
+pub fn markers(data: &Data) -> Vec<Marker> {
+    let mut out: Vec<Marker> = vec![];
+
+    for event in data.detector.events.iter() {
+        let mut label = match event.kind {
+            EventKind::Apogee => format!("apogee {:.0} m", event.alt),
+            n => n.name().to_string(),
+        };
+        if event.confidence == Confidence::Low {
+            label.push_str(" (uncertain)");
+        }
+        out.push(Marker {time: event.time, kind: event.kind.name(), label});
+    }
+
+    for command in data.commands.history.iter() {
+        if matches!(command.status, CommandStatus::Rejected) {
+            continue;
+        }
+        if let Some(time) = vehicle_time(data, command.requested) {
+            out.push(Marker {time, kind: "command", label: format!("{} {}", command.name, command.value)});
+        }
+    }
+
+    out.sort_by(|a, b| a.time.total_cmp(&b.time));
+    return out;
+}
+
+/// a change to a cursor, keys that are left out are kept
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Seek {
+    /// vehicle time, seconds
+    pub position: Option<f32>,
+    pub speed: Option<f64>,
+    pub playing: Option<bool>,
+}
+
+/// where a cursor is and the frame under it, served by `/api/replay/<id>`
+#[derive(Debug, Clone, Serialize)]
+pub struct Cursor {
+    pub id: u64,
+    pub session: u64,
+    /// vehicle time, seconds
+    pub position: f32,
+    pub speed: f64,
+    pub playing: bool,
+    /// vehicle time of the first and last frame
+    pub start: f32,
+    pub end: f32,
+    pub frames: usize,
+    /// index of the frame at or before `position`
+    pub index: Option<usize>,
+    /// ground receive time of that frame, UTC seconds
+    pub rx_time: Option<f64>,
+    /// every channel of that frame
+    pub values: BTreeMap<&'static str, f32>,
+}
+
+/// a new cursor plus the timeline markers of its session
+#[derive(Debug, Clone, Serialize)]
+pub struct Opened {
+    #[serde(flatten)]
+    pub cursor: Cursor,
+    pub markers: Vec<Marker>,
+}
+
+/// a playback cursor over a logged session
+///
+/// the position moves with the wall clock while playing, so every client sees the same instant
+pub struct Playback {
+    pub id: u64,
+    pub session: u64,
+    pub data: Arc<Data>,
+    /// position when playback last started or was moved
+    position: f32,
+    /// UTC seconds `position` was set
+    anchor: f64,
+    speed: f64,
+    playing: bool,
+    /// UTC seconds the cursor was last used
+    used: f64,
+}
+
+impl Playback {
+    pub fn new(id: u64, session: u64, data: Arc<Data>, now: f64) -> Playback {
+        let position = data.altitude.first().map(|n| n.0).unwrap_or(0.0);
+        Playback {
+            id,
+            session,
+            data,
+            position,
+            anchor: now,
+            speed: 1.0,
+            playing: false,
+            used: now,
+        }
+    }
+
+    pub fn start(&self) -> f32 {
+        self.data.altitude.first().map(|n| n.0).unwrap_or(0.0)
+    }
+
+    pub fn end(&self) -> f32 {
+        self.data.altitude.last().map(|n| n.0).unwrap_or(0.0)
+    }
+
+    /// position at `now`, stopping at the end of the session
+    pub fn position(&mut self, now: f64) -> f32 {
+        if !self.playing {
+            return self.position;
+        }
+
+        let position = self.position + ((now - self.anchor) * self.speed) as f32;
+        if position < self.end() {
+            return position;
+        }
+
+        self.position = self.end();
+        self.anchor = now;
+        self.playing = false;
+        self.position
+    }
+
+    /// applies a seek, speed or play / pause, all checked before any is applied
+    pub fn seek(&mut self, seek: &Seek, now: f64) -> Result<(), String> {
+        if let Some(speed) = seek.speed {
+            if !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+                return Err(format!("speed {} is outside {} to {}", speed, MIN_SPEED, MAX_SPEED));
+            }
+        }
+        if let Some(position) = seek.position {
+            if !position.is_finite() {
+                return Err(String::from("position must be a number"));
+            }
+        }
+
+        // re-anchor so a speed change does not jump the position
+        self.position = self.position(now);
+        self.anchor = now;
+        self.used = now;
+
+        if let Some(position) = seek.position {
+            self.position = position.clamp(self.start(), self.end());
+        }
+        if let Some(speed) = seek.speed {
+            self.speed = speed;
+        }
+        if let Some(playing) = seek.playing {
+            // playing from the end starts over
+            if playing && !self.playing && self.position >= self.end() {
+                self.position = self.start();
+            }
+            self.playing = playing;
+        }
+
+        Ok(())
+    }
+
+    pub fn cursor(&mut self, now: f64) -> Cursor {
+        self.used = now;
+        let position = self.position(now);
+
+        let data = &self.data;
+        let index = data.altitude.partition_point(|n| n.0 <= position).checked_sub(1);
+
+        let mut values: BTreeMap<&'static str, f32> = BTreeMap::new();
+        if let Some(i) = index {
+            for def in CHANNELS.iter() {
+                if let Some(val) = data.channel(def.name).and_then(|n| n.get(i)) {
+                    values.insert(def.name, val.1);
+                }
+            }
+        }
+
+        Cursor {
+            id: self.id,
+            session: self.session,
+            position,
+            speed: self.speed,
+            playing: self.playing,
+            start: self.start(),
+            end: self.end(),
+            frames: data.rx_time.len(),
+            index,
+            rx_time: index.and_then(|i| data.rx_time.get(i).copied()),
+            values,
+        }
+    }
+}
+
+/// every open playback cursor
+#[derive(Default)]
+pub struct Replays {
+    next: u64,
+    open: BTreeMap<u64, Playback>,
+}
+
+impl Replays {
+    /// opens a cursor over the log of `session` in `dir`, sharing the loaded session with other cursors on it
+    pub fn open(&mut self, dir: &Path, session: u64, now: f64) -> Result<&mut Playback, String> {
+        self.open.retain(|_, n| now - n.used < REPLAY_IDLE);
+        if self.open.len() >= MAX_REPLAYS {
+            let oldest = self.open.values().min_by(|a, b| a.used.total_cmp(&b.used)).map(|n| n.id);
+            if let Some(id) = oldest {
+                self.open.remove(&id);
+            }
+        }
+
+        let data = match self.open.values().find(|n| n.session == session) {
+            Some(n) => Arc::clone(&n.data),
+            None => {
+                let path = dir.join(format!("{}.jsonl", session));
+                if !path.is_file() {
+                    return Err(format!("no session {}", session));
+                }
+                Arc::new(store::load(&path)?)
+            },
+        };
+
+        self.next += 1;
+        let id = self.next;
+        self.open.insert(id, Playback::new(id, session, data, now));
+        Ok(self.open.get_mut(&id).expect("just inserted"))
+    }
+
+    pub fn get(&mut self, id: u64) -> Option<&mut Playback> {
+        self.open.get_mut(&id)
+    }
+
+    pub fn close(&mut self, id: u64) -> bool {
+        self.open.remove(&id).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::Data;
+    use crate::protocol::Frame;
+    use crate::replay::{markers, sessions, Playback, Replays, Seek, MAX_REPLAYS};
+    use crate::store::{Record, Store};
+
+    use std::env;
+    use std::fs;
+    use std::sync::Arc;
+
+    /// a frame every 0.5 s of 1 s on the pad, 4 s up at 20 m/s and 5 s back down
+    fn frames() -> Vec<Frame> {
+        (0..21).map(|i| {
+            let time = i as f32 * 0.5;
+            let (alt, vel) = match time {
+                t if t < 1.0 => (0.0, 0.0),
+                t if t < 5.0 => ((t - 1.0) * 20.0, 20.0),
+                t => (80.0 - (t - 5.0) * 20.0, -20.0),
+            };
+            Frame {time, alt, balt: alt, vel, w: 1f32, x: 0f32, y: 0f32, z: 0f32, gps: None}
+        }).collect()
+    }
+
+    fn flight() -> Data {
+        let mut data = Data::new();
+        for frame in frames() {
+            data.record_at(&frame, 1000.0 + frame.time as f64);
+        }
+        data
+    }
+
+    #[test]
+    fn playback() {
+        let mut cursor = Playback::new(1, 7, Arc::new(flight()), 100.0);
+        assert_eq!(cursor.cursor(100.0).position, 0.0);
+        assert_eq!(cursor.end(), 10.0);
+
+        cursor.seek(&Seek {playing: Some(true), speed: Some(2.0), ..Seek::default()}, 100.0).unwrap();
+        let now = cursor.cursor(101.0);
+        assert_eq!(now.position, 2.0);
+        assert_eq!(now.index, Some(4));
+        assert_eq!(now.values["alt"], 20.0);
+        assert_eq!(now.rx_time, Some(1002.0));
+
+        // a speed change keeps the position
+        cursor.seek(&Seek {speed: Some(0.5), ..Seek::default()}, 101.0).unwrap();
+        assert_eq!(cursor.cursor(103.0).position, 3.0);
+
+        // stops at the end
+        let end = cursor.cursor(200.0);
+        assert_eq!(end.position, 10.0);
+        assert!(!end.playing);
+
+        cursor.seek(&Seek {position: Some(4.2), ..Seek::default()}, 200.0).unwrap();
+        let now = cursor.cursor(300.0);
+        assert_eq!(now.position, 4.2);
+        assert_eq!(now.values["vel"], 20.0);
+
+        assert!(cursor.seek(&Seek {speed: Some(20.0), ..Seek::default()}, 300.0).unwrap_err().contains("speed"));
+        assert!(cursor.seek(&Seek {position: Some(-5.0), ..Seek::default()}, 300.0).is_ok());
+        assert_eq!(cursor.cursor(300.0).position, 0.0);
+    }
+
+    #[test]
+    fn flight_markers() {
+        let found = markers(&flight());
+        assert_eq!(found.iter().map(|n| n.kind).collect::<Vec<&str>>(), vec!["liftoff", "burnout", "apogee", "drogue"]);
+        assert_eq!(found[0].time, 1.0);
+        assert_eq!((found[2].time, found[2].label.as_str()), (5.0, "apogee 80 m"));
+
+        assert!(markers(&Data::new()).is_empty());
+    }
+
+    #[test]
+    fn open_sessions() {
+        let dir = env::temp_dir().join(format!("gndapi-replay-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut store = Store::create(&dir, 5, 1000.0).unwrap();
+        for frame in frames() {
+            store.append(&Record::Frame {rx_time: 1000.0 + frame.time as f64, frame});
+        }
+        store.close().unwrap();
+        Store::create(&dir, 9, 2000.0).unwrap().close().unwrap();
+
+        let list = sessions(&dir, 9).unwrap();
+        assert_eq!(list.iter().map(|n| n.id).collect::<Vec<u64>>(), vec![9, 5]);
+        assert!(list[0].current);
+
+        let mut replays = Replays::default();
+        let first = replays.open(&dir, 5, 0.0).unwrap();
+        assert_eq!(first.end(), 10.0);
+        let id = first.id;
+
+        // a second cursor shares the loaded session
+        let second = replays.open(&dir, 5, 1.0).unwrap();
+        assert_ne!(second.id, id);
+        let shared = Arc::clone(&second.data);
+        assert!(Arc::ptr_eq(&shared, &replays.get(id).unwrap().data));
+
+        assert!(matches!(replays.open(&dir, 6, 0.0), Err(n) if n.contains("no session")));
+
+        for i in 0..MAX_REPLAYS {
+            replays.open(&dir, 9, 2.0 + i as f64).unwrap();
+        }
+        assert!(replays.get(id).is_none());
+
+        let id = replays.open(&dir, 5, 20.0).unwrap().id;
+        assert!(replays.close(id));
+        assert!(!replays.close(id));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
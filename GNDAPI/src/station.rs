@@ -0,0 +1,539 @@
+use crate::alarm::AlarmConfig;
+use crate::auth::Auth;
+use crate::command;
+use crate::event::EventConfig;
+use crate::fusion::FusionConfig;
+use crate::interlock::InterlockConfig;
+use crate::link::{LOCKED_AGE, LOST_AGE, POLL_BACKOFF, RECV_TIMEOUT};
+use crate::map::MapConfig;
+use crate::protocol::Schema;
+use crate::store::SESSION_DIR;
+use crate::tuning::RadioSettings;
+use crate::vehicle::VehicleConfig;
+
+use rocket::figment::{
+    Figment,
+    providers::{Format, Toml},
+    value::Value,
+};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// station config read when `--config` and `GNDAPI_CONFIG` are not given
+pub const CONFIG_FILE: &str = "Station.toml";
+
+/// prefix of env overrides, `__` separates tables, e.g. `GNDAPI_RADIO__SETTINGS__POWER=10`
+pub const ENV_PREFIX: &str = "GNDAPI_";
+
+pub const USAGE: &str = "usage: RadioAPI [--config <file>] [--port <serial port>] [--power <dBm>] [--set <key>=<value>]... [--check]
+       RadioAPI export ...
+
+  --config  station config, defaults to $GNDAPI_CONFIG or Station.toml
+  --set     overrides a single key, e.g. --set link.lost_age=5 or --set server.port=8081
+  --check   validate the config, print it and exit";
+
+/// the `[radio]` table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RadioConfig {
+    /// serial port of the ground radio, asked for on startup if unset
+    pub port: Option<String>,
+    /// applied at startup and after every reset, can be changed through `/api/radio`
+    pub settings: RadioSettings,
+    /// time without a frame before the radio is reset, ms
+    pub reset_after_ms: u64,
+    /// how long one poll waits for a new frame, ms
+    pub recv_timeout_ms: u64,
+    /// pause between polls that returned no new frame, ms
+    pub poll_backoff_ms: u64,
+    /// pause between attempts to reopen the port after a reset, ms
+    pub reopen_delay_ms: u64,
+}
+
+impl Default for RadioConfig {
+    fn default() -> RadioConfig {
+        RadioConfig {
+            port: None,
+            settings: RadioSettings::default(),
+            reset_after_ms: 1000,
+            recv_timeout_ms: RECV_TIMEOUT.as_millis() as u64,
+            poll_backoff_ms: POLL_BACKOFF.as_millis() as u64,
+            reopen_delay_ms: 50,
+        }
+    }
+}
+
+impl RadioConfig {
+    pub fn reset_after(&self) -> Duration {
+        Duration::from_millis(self.reset_after_ms)
+    }
+
+    pub fn recv_timeout(&self) -> Duration {
+        Duration::from_millis(self.recv_timeout_ms)
+    }
+
+    pub fn poll_backoff(&self) -> Duration {
+        Duration::from_millis(self.poll_backoff_ms)
+    }
+
+    pub fn reopen_delay(&self) -> Duration {
+        Duration::from_millis(self.reopen_delay_ms)
+    }
+}
+
+/// the `[link]` table, see `LinkState`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinkConfig {
+    /// last frame age under which the link counts as locked, seconds
+    pub locked_age: f64,
+    /// last frame age after which the link counts as lost, seconds
+    pub lost_age: f64,
+}
+
+impl Default for LinkConfig {
+    fn default() -> LinkConfig {
+        LinkConfig {
+            locked_age: LOCKED_AGE,
+            lost_age: LOST_AGE,
+        }
+    }
+}
+
+/// the `[packet]` table
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PacketConfig {
+    /// order of the f32 fields in a telemetry frame, has to match the vehicle firmware
+    pub fields: Schema,
+}
+
+/// the `[storage]` table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// directory session logs are written to
+    pub session_dir: PathBuf,
+}
+
+impl Default for StorageConfig {
+    fn default() -> StorageConfig {
+        StorageConfig {
+            session_dir: PathBuf::from(SESSION_DIR),
+        }
+    }
+}
+
+/// the `[server]` table, the rest of the http settings stay in `Rocket.toml`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub address: String,
+    pub port: u16,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            address: String::from("0.0.0.0"),
+            port: 8080,
+        }
+    }
+}
+
+/// the `[supervisor]` table, restart backoff of the radio thread
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SupervisorConfig {
+    pub backoff_initial_ms: u64,
+    pub backoff_max_ms: u64,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> SupervisorConfig {
+        SupervisorConfig {
+            backoff_initial_ms: 500,
+            backoff_max_ms: 30_000,
+        }
+    }
+}
+
+/// the `[metrics]` table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// serve `/metrics` for prometheus
+    pub enabled: bool,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> MetricsConfig {
+        MetricsConfig {
+            enabled: true,
+        }
+    }
+}
+
+/// everything the ground station can be configured with, see `Station.toml`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StationConfig {
+    pub radio: RadioConfig,
+    pub link: LinkConfig,
+    pub packet: PacketConfig,
+    pub storage: StorageConfig,
+    pub server: ServerConfig,
+    pub supervisor: SupervisorConfig,
+    pub auth: Auth,
+    pub interlock: InterlockConfig,
+    pub metrics: MetricsConfig,
+    pub map: MapConfig,
+    pub vehicle: VehicleConfig,
+    pub alarms: AlarmConfig,
+    pub events: EventConfig,
+    pub fusion: FusionConfig,
+}
+
+/// command line options of the station itself
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Args {
+    pub config: Option<PathBuf>,
+    /// `--set` overrides in the order given, `--port` and `--power` included
+    pub overrides: Vec<(String, String)>,
+    pub check: bool,
+}
+
+impl Args {
+    pub fn parse(args: &[String]) -> Result<Args, String> {
+        let mut out = Args::default();
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let mut value = |name: &str| match iter.next() {
+                Some(n) => Ok(n.clone()),
+                None => Err(format!("{} needs a value\n\n{}", name, USAGE)),
+            };
+
+            match arg.as_str() {
+                "--config" => {out.config = Some(PathBuf::from(value("--config")?));},
+                "--port" => {out.overrides.push((String::from("radio.port"), value("--port")?));},
+                "--power" => {out.overrides.push((String::from("radio.settings.power"), value("--power")?));},
+                "--set" => {
+                    let set = value("--set")?;
+                    match set.split_once('=') {
+                        Some((key, val)) if !key.trim().is_empty() => {out.overrides.push((key.trim().to_string(), val.trim().to_string()));},
+                        _ => {return Err(format!("expected --set <key>=<value>, got \"{}\"", set))},
+                    };
+                },
+                "--check" => {out.check = true;},
+                "-h" | "--help" => {return Err(USAGE.to_string())},
+                _ => {return Err(format!("unknown argument \"{}\"\n\n{}", arg, USAGE))},
+            };
+        }
+
+        return Ok(out);
+    }
+}
+
+impl StationConfig {
+    /// reads the config file, then `GNDAPI_*` env vars, then command line overrides
+    pub fn load(args: &Args) -> Result<StationConfig, String> {
+        let vars = std::env::vars_os().filter_map(|(key, val)| Some((key.into_string().ok()?, val.into_string().ok()?)));
+        return StationConfig::load_with(args, vars);
+    }
+
+    /// as `load`, with the environment given as `vars` instead of read from the process
+    pub fn load_with<I: IntoIterator<Item = (String, String)>>(args: &Args, vars: I) -> Result<StationConfig, String> {
+        let mut figment = Figment::new();
+
+        // `GNDAPI_RADIO__SETTINGS__POWER` sets `radio.settings.power`, `GNDAPI_CONFIG` names the file
+        let mut file: Option<PathBuf> = None;
+        let mut env: Vec<(String, String)> = vec![];
+        for (key, val) in vars {
+            let key = match key.strip_prefix(ENV_PREFIX) {
+                Some(n) if !n.is_empty() => n.to_lowercase(),
+                _ => {continue;},
+            };
+
+            if key == "config" {
+                file = Some(PathBuf::from(val));
+            }
+            else {
+                env.push((key.split("__").collect::<Vec<&str>>().join("."), val.trim().to_string()));
+            }
+        }
+
+        let path = args.config.clone().or(file);
+        match path {
+            Some(n) => {
+                if !n.exists() {
+                    return Err(format!("config file {} does not exist", n.display()));
+                }
+                figment = figment.merge(Toml::file(n));
+            },
+            None => {
+                if Path::new(CONFIG_FILE).exists() {
+                    figment = figment.merge(Toml::file(CONFIG_FILE));
+                }
+            },
+        };
+
+        for (key, val) in env.iter().chain(args.overrides.iter()) {
+            // `5` is a number and `[1, 2]` an array
+            let val: Value = val.parse().expect("parsing a value never fails");
+            figment = figment.merge((key.as_str(), val));
+        }
+
+        StationConfig::from_figment(&figment)
+    }
+
+    pub fn from_figment(figment: &Figment) -> Result<StationConfig, String> {
+        let config = match figment.extract::<StationConfig>() {
+            Ok(n) => n,
+            Err(err) => {
+                let errors: Vec<String> = err.into_iter().map(|n| n.to_string()).collect();
+                return Err(format!("invalid station config:\n  {}", errors.join("\n  ")));
+            },
+        };
+
+        match config.validate() {
+            Ok(_) => Ok(config),
+            Err(n) => Err(format!("invalid station config:\n  {}", n.join("\n  "))),
+        }
+    }
+
+    /// checks values that parse but make no sense, returns every problem found
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors: Vec<String> = vec![];
+
+        if self.radio.port.as_ref().map(|n| n.trim().is_empty()).unwrap_or(false) {
+            errors.push(String::from("radio.port is empty, leave it unset to be asked on startup"));
+        }
+        if let Err(n) = self.radio.settings.validate() {
+            errors.extend(n.iter().map(|n| format!("radio.settings.{}", n)));
+        }
+        if self.radio.reset_after_ms == 0 {
+            errors.push(String::from("radio.reset_after_ms must be positive"));
+        }
+        if self.radio.recv_timeout_ms == 0 {
+            errors.push(String::from("radio.recv_timeout_ms must be positive"));
+        }
+
+        if !(self.link.locked_age > 0.0) {
+            errors.push(String::from("link.locked_age must be positive"));
+        }
+        if !(self.link.lost_age > self.link.locked_age) {
+            errors.push(format!("link.lost_age ({}) must be greater than link.locked_age ({})", self.link.lost_age, self.link.locked_age));
+        }
+
+        if let Err(n) = self.packet.fields.validate() {
+            errors.push(format!("packet.fields: {}", n));
+        }
+
+        if self.storage.session_dir.as_os_str().is_empty() {
+            errors.push(String::from("storage.session_dir is empty"));
+        }
+
+        if self.server.port == 0 {
+            errors.push(String::from("server.port must not be 0"));
+        }
+        if self.server.address.parse::<std::net::IpAddr>().is_err() {
+            errors.push(format!("server.address \"{}\" is not an ip address", self.server.address));
+        }
+
+        if self.supervisor.backoff_initial_ms == 0 || self.supervisor.backoff_max_ms < self.supervisor.backoff_initial_ms {
+            errors.push(String::from("supervisor.backoff_initial_ms must be positive and at most backoff_max_ms"));
+        }
+
+        let mut tokens: BTreeSet<&str> = BTreeSet::new();
+        for user in self.auth.users.iter() {
+            if user.token.is_empty() {
+                errors.push(format!("auth.users: {} has an empty token", user.name));
+            }
+            else if !tokens.insert(&user.token) {
+                errors.push(format!("auth.users: {} shares a token with another user", user.name));
+            }
+        }
+
+        for name in self.interlock.hazardous.iter() {
+            if command::lookup(name).is_none() {
+                errors.push(format!("interlock.hazardous: unknown command \"{}\"", name));
+            }
+        }
+        if !(self.interlock.arm_timeout > 0.0) {
+            errors.push(String::from("interlock.arm_timeout must be positive"));
+        }
+        if let Err(n) = self.map.validate() {
+            errors.extend(n.iter().map(|n| format!("map.{}", n)));
+        }
+        if let Err(n) = self.vehicle.validate() {
+            errors.extend(n.iter().map(|n| format!("vehicle.{}", n)));
+        }
+        if let Err(n) = self.alarms.validate() {
+            errors.extend(n.iter().map(|n| format!("alarms.{}", n)));
+        }
+        if let Err(n) = self.events.validate() {
+            errors.extend(n.iter().map(|n| format!("events.{}", n)));
+        }
+        if let Err(n) = self.fusion.validate() {
+            errors.extend(n.iter().map(|n| format!("fusion.{}", n)));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::Role;
+    use crate::station::{Args, StationConfig};
+
+    use rocket::figment::{Figment, providers::{Format, Toml}};
+
+    fn args(args: &[&str]) -> Result<Args, String> {
+        Args::parse(&args.iter().map(|n| n.to_string()).collect::<Vec<String>>())
+    }
+
+    #[test]
+    fn defaults() {
+        let config = StationConfig::from_figment(&Figment::new()).unwrap();
+        assert_eq!(config.radio.settings.power, 14.0);
+        assert_eq!(config.radio.reset_after_ms, 1000);
+        assert_eq!(config.packet.fields.frame_size(), 32);
+        assert!(config.auth.public_read);
+        assert!(config.interlock.hazardous.is_empty());
+        assert_eq!(config.alarms.limits.len(), 4);
+    }
+
+    #[test]
+    fn tables() {
+        let figment = Figment::from(Toml::string(r#"
+            [radio]
+            port = "/dev/ttyUSB0"
+
+            [radio.settings]
+            power = 10
+
+            [auth]
+            public_read = false
+            users = [
+                { name = "rso", token = "abc", role = "operator" },
+                { name = "pad", token = "def", role = "viewer" },
+            ]
+
+            [interlock]
+            hazardous = ["test"]
+            require_locked = true
+        "#));
+
+        let config = StationConfig::from_figment(&figment).unwrap();
+        assert_eq!(config.radio.port.as_deref(), Some("/dev/ttyUSB0"));
+        assert_eq!(config.radio.settings.power, 10.0);
+        assert_eq!(config.radio.settings.frequency, 435e6);
+        assert_eq!(config.radio.recv_timeout_ms, 100);
+
+        assert!(!config.auth.public_read);
+        assert_eq!(config.auth.user("abc").unwrap().name, "rso");
+        assert_eq!(config.auth.user("def").unwrap().role, Role::Viewer);
+
+        assert_eq!(config.interlock.hazardous, vec!["test".to_string()]);
+        assert_eq!(config.interlock.arm_timeout, 10.0);
+        assert!(config.interlock.require_locked);
+    }
+
+    #[test]
+    fn every_error_reported() {
+        let figment = Figment::from(Toml::string(r#"
+            [radio.settings]
+            power = 20
+
+            [link]
+            locked_age = 4
+
+            [packet]
+            fields = ["time", "alt"]
+
+            [auth]
+            users = [
+                { name = "rso", token = "abc", role = "operator" },
+                { name = "pad", token = "abc", role = "viewer" },
+            ]
+
+            [interlock]
+            hazardous = ["fire"]
+
+            [map]
+            tiles = "nope.mbtiles"
+            station = { lat = 47.6, long = 237.7 }
+
+            [alarms]
+            limits = [{ name = "speed", channel = "speed", red = { high = 300 } }]
+
+            [fusion]
+            accel_noise = 0
+        "#));
+
+        let err = StationConfig::from_figment(&figment).unwrap_err();
+        assert!(err.contains("radio.settings.power is 20"));
+        assert!(err.contains("link.lost_age"));
+        assert!(err.contains("packet.fields: missing field \"balt\""));
+        assert!(err.contains("pad shares a token"));
+        assert!(err.contains("unknown command \"fire\""));
+        assert!(err.contains("map.station: long 237.7"));
+        assert!(err.contains("map.tiles: nope.mbtiles does not exist"));
+        assert!(err.contains("alarms.limits: speed watches unknown channel"));
+        assert!(err.contains("fusion.accel_noise must be positive"));
+
+        // typos are caught rather than silently ignored
+        assert!(StationConfig::from_figment(&Figment::from(Toml::string("[radio]\npowr = 10"))).unwrap_err().contains("powr"));
+        assert!(StationConfig::from_figment(&Figment::from(Toml::string("[auth]\nusers = 3"))).is_err());
+    }
+
+    #[test]
+    fn command_line() {
+        let parsed = args(&["--port", "COM3", "--set", "link.lost_age=5", "--check"]).unwrap();
+        assert!(parsed.check);
+        assert_eq!(parsed.overrides, vec![
+            ("radio.port".to_string(), "COM3".to_string()),
+            ("link.lost_age".to_string(), "5".to_string()),
+        ]);
+
+        let config = StationConfig::load(&parsed).unwrap();
+        assert_eq!(config.radio.port.as_deref(), Some("COM3"));
+        assert_eq!(config.link.lost_age, 5.0);
+
+        assert!(args(&["--set", "power"]).is_err());
+        assert!(args(&["--port"]).is_err());
+        assert!(args(&["--bogus"]).unwrap_err().contains("bogus"));
+        assert!(StationConfig::load(&args(&["--config", "does-not-exist.toml"]).unwrap()).is_err());
+    }
+
+    #[test]
+    fn env_overrides() {
+        let vars = |n: &[(&str, &str)]| n.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<Vec<(String, String)>>();
+
+        // the examples in the README and Station.toml
+        let config = StationConfig::load_with(&Args::default(), vars(&[
+            ("GNDAPI_RADIO__SETTINGS__POWER", "10"),
+            ("GNDAPI_LINK__LOST_AGE", "5"),
+            ("PATH", "/usr/bin"),
+        ])).unwrap();
+        assert_eq!(config.radio.settings.power, 10.0);
+        assert_eq!(config.link.lost_age, 5.0);
+
+        // the command line wins over the environment
+        let args = args(&["--set", "link.lost_age=7"]).unwrap();
+        let config = StationConfig::load_with(&args, vars(&[("GNDAPI_LINK__LOST_AGE", "5")])).unwrap();
+        assert_eq!(config.link.lost_age, 7.0);
+
+        assert!(StationConfig::load_with(&Args::default(), vars(&[("GNDAPI_CONFIG", "/nonexistent/Station.toml")])).is_err());
+        assert!(StationConfig::load_with(&Args::default(), vars(&[("GNDAPI_LINK__NOPE", "1")])).is_err());
+    }
+}
@@ -0,0 +1,273 @@
+use crate::alarm::Alarm;
+use crate::api::Data;
+use crate::protocol::Frame;
+use crate::command::Command;
+use crate::event::FlightEvent;
+use crate::tuning::RadioChange;
+
+use rocket::serde::json;
+use serde::{Serialize, Deserialize};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// default directory session logs are written to, see `storage.session_dir`
+pub const SESSION_DIR: &str = "sessions";
+
+/// one line of a session log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Record {
+    /// first line of every log
+    Session {
+        id: u64,
+        /// UTC seconds
+        started: f64,
+    },
+    /// an accepted frame and its ground receive time (UTC seconds)
+    Frame {
+        rx_time: f64,
+        #[serde(flatten)]
+        frame: Frame,
+    },
+    /// a command request, logged again on every change to it
+    Command {
+        #[serde(flatten)]
+        command: Command,
+    },
+    /// a radio settings change, logged again once the radio thread applies it
+    Radio {
+        #[serde(flatten)]
+        change: RadioChange,
+    },
+    /// an alarm, logged again when it escalates, clears or is acknowledged
+    Alarm {
+        #[serde(flatten)]
+        alarm: Alarm,
+    },
+    /// a detected flight event
+    Event {
+        #[serde(flatten)]
+        event: FlightEvent,
+    },
+}
+
+/// append only JSON Lines log of a session
+///
+/// every record is flushed as it is written, so a crash loses at most the line in flight
+pub struct Store {
+    pub path: PathBuf,
+    writer: BufWriter<File>,
+    /// records written this session
+    pub records: u64,
+    /// last write error, if any
+    pub error: Option<String>,
+}
+
+impl Store {
+    /// creates `<dir>/<session>.jsonl` and writes the session header
+    pub fn create(dir: &Path, session: u64, started: f64) -> Result<Store, String> {
+        match fs::create_dir_all(dir) {
+            Ok(_) => {},
+            Err(n) => {return Err(format!("could not create {}: {}", dir.display(), n))},
+        };
+
+        let path = dir.join(format!("{}.jsonl", session));
+        let file = match File::create(&path) {
+            Ok(n) => n,
+            Err(n) => {return Err(format!("could not create {}: {}", path.display(), n))},
+        };
+
+        let mut store = Store {
+            path,
+            writer: BufWriter::new(file),
+            records: 0,
+            error: None,
+        };
+
+        store.append(&Record::Session {id: session, started});
+        return match store.error.clone() {
+            Some(n) => Err(n),
+            None => Ok(store),
+        };
+    }
+
+    /// writes a record, failures are kept in `error` rather than interrupting ingest
+    pub fn append(&mut self, record: &Record) {
+        let line = match json::to_string(record) {
+            Ok(n) => n,
+            Err(n) => {
+                self.error = Some(format!("could not encode record: {}", n));
+                return;
+            }
+        };
+
+        let res = writeln!(self.writer, "{}", line).and_then(|_| self.writer.flush());
+        match res {
+            Ok(_) => {
+                self.records += 1;
+            },
+            Err(n) => {
+                println!("Error writing session log | {}", n);
+                self.error = Some(format!("could not write {}: {}", self.path.display(), n));
+            }
+        };
+    }
+}
+
+impl Store {
+    /// flushes and syncs the log to disk, returns its path
+    pub fn close(mut self) -> Result<PathBuf, String> {
+        let res = self.writer.flush().and_then(|_| self.writer.get_ref().sync_all());
+        match res {
+            Ok(_) => Ok(self.path),
+            Err(n) => Err(format!("could not sync {}: {}", self.path.display(), n)),
+        }
+    }
+}
+
+/// replays a session log into a fresh `Data`
+pub fn load(path: &Path) -> Result<Data, String> {
+    let file = match File::open(path) {
+        Ok(n) => n,
+        Err(n) => {return Err(format!("could not open {}: {}", path.display(), n))},
+    };
+
+    let mut data = Data::new();
+    // the frames are run through the detector again, what was detected at the time wins
+    let mut events: Vec<FlightEvent> = vec![];
+
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = match line {
+            Ok(n) => n,
+            Err(n) => {return Err(format!("could not read {}: {}", path.display(), n))},
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record: Record = match json::from_str(&line) {
+            Ok(n) => n,
+            Err(n) => {
+                // a crash can leave the last line half written
+                println!("skipping line {} of {} | {}", i + 1, path.display(), n);
+                continue;
+            }
+        };
+
+        match record {
+            Record::Session {id, started} => {
+                data.session = id;
+                data.started = started;
+            },
+            Record::Frame {rx_time, frame} => {
+                data.record_at(&frame, rx_time);
+            },
+            Record::Command {command} => {
+                data.commands.update(command);
+            },
+            Record::Radio {change} => {
+                data.tuning.update(change);
+            },
+            Record::Alarm {alarm} => {
+                data.alarms.update(alarm);
+            },
+            Record::Event {event} => {
+                events.push(event);
+            },
+        };
+    }
+
+    if !events.is_empty() {
+        data.detector.events = events;
+    }
+
+    return Ok(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::Data;
+    use crate::command::CommandStatus;
+    use crate::event::{Confidence, EventKind, FlightEvent};
+    use crate::protocol::{Frame, Gps};
+    use crate::store::{Record, Store, load};
+    use crate::tuning::RadioSettings;
+
+    use rocket::serde::json;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn log_and_load() {
+        let dir = env::temp_dir().join(format!("gndapi-store-{}", std::process::id()));
+
+        let mut data = Data::new();
+        data.store = Some(Store::create(&dir, data.session, data.started).expect("error creating store"));
+
+        for i in 0..5 {
+            data.record(&Frame {time: i as f32, alt: i as f32 * 2f32, balt: 0f32, vel: 1f32, w: 1f32, x: 0f32, y: 0f32, z: 0f32, gps: None});
+        }
+        // non-finite values are expected, e.g. no gps fix
+        let gps = Gps {lat: f32::NAN, long: f32::NAN, fix: 0f32, quality: 0f32};
+        data.record(&Frame {time: 5.0, alt: f32::NAN, balt: f32::INFINITY, vel: f32::NEG_INFINITY, w: 1f32, x: 0f32, y: 0f32, z: 0f32, gps: Some(gps)});
+
+        let mut cmd = data.command("test", 1.0, "rso");
+        cmd.status = CommandStatus::Sent;
+        data.update_command(cmd);
+
+        let mut change = data.request_tuning(RadioSettings {power: 5.0, ..RadioSettings::default()}, "rso");
+        change.applied = Some(change.requested);
+        data.update_tuning(change);
+
+        let path = data.store.as_ref().unwrap().path.clone();
+        assert_eq!(data.store.as_ref().unwrap().records, 11);
+
+        // what was detected during the session is kept over detecting it again
+        let event = FlightEvent {kind: EventKind::Apogee, time: 3.0, detected: 0.0, alt: 6.0, vel: 0.0, confidence: Confidence::Low, detail: String::new()};
+        data.store.as_mut().unwrap().append(&Record::Event {event: event.clone()});
+
+        // older logs wrote non-finite values as null
+        let old = "{\"type\":\"frame\",\"rx_time\":1.0,\"time\":6.0,\"alt\":null,\"balt\":1.0,\"vel\":1.0,\"w\":1.0,\"x\":0.0,\"y\":0.0,\"z\":0.0}\n";
+        // a torn final line is skipped
+        fs::write(&path, fs::read_to_string(&path).unwrap() + old + "{\"type\":\"fra").unwrap();
+
+        let loaded = load(&path).expect("error loading session");
+        assert_eq!(loaded.session, data.session);
+        let bits = |n: &[(f32, f32)]| n.iter().map(|n| (n.0.to_bits(), n.1.to_bits())).collect::<Vec<(u32, u32)>>();
+        assert_eq!(loaded.altitude.len(), 7);
+        assert_eq!(bits(&loaded.altitude[..6]), bits(&data.altitude));
+        assert!(loaded.altitude[6].1.is_nan());
+        assert_eq!(loaded.balt[5].1, f32::INFINITY);
+        assert_eq!(loaded.vel[5].1, f32::NEG_INFINITY);
+        assert!(loaded.lat[5].1.is_nan());
+        assert_eq!(loaded.fix[5].1, 0f32);
+        assert_eq!(loaded.rx_time[..data.rx_time.len()], data.rx_time[..]);
+        assert_eq!(loaded.rx_time.len(), data.rx_time.len() + 1);
+
+        assert_eq!(loaded.commands.history.len(), 1);
+        assert_eq!(loaded.commands.history[0].name, "test");
+        assert_eq!(loaded.commands.history[0].status, CommandStatus::Sent);
+        assert_eq!(loaded.tuning.settings.power, 5.0);
+        assert!(loaded.tuning.pending.is_none());
+        assert!(loaded.store.is_none());
+        assert_eq!(loaded.detector.events, vec![event]);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn rx_time_round_trip() {
+        // receive times are unix seconds with sub microsecond digits, every bit has to survive the log
+        for i in 0..10000 {
+            let rx_time = 1760000000.0 + i as f64 * 0.0123456789;
+            let record = Record::Frame {rx_time, frame: Frame {time: 0f32, alt: 0f32, balt: 0f32, vel: 0f32, w: 0f32, x: 0f32, y: 0f32, z: 0f32, gps: None}};
+
+            let line = json::to_string(&record).unwrap();
+            match json::from_str::<Record>(&line).unwrap() {
+                Record::Frame {rx_time: n, ..} => assert_eq!(n.to_bits(), rx_time.to_bits(), "{}", line),
+                _ => panic!("not a frame"),
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use crate::event::FlightEvent;
+use crate::protocol::Frame;
+
+use rocket::tokio::sync::broadcast;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// how many messages a slow subscriber may fall behind before it starts missing them
+pub const FEED_CAPACITY: usize = 256;
+
+/// everything pushed to live subscribers
+#[derive(Debug, Clone)]
+pub enum Message {
+    /// an accepted frame and its ground receive time (UTC seconds)
+    Frame(Frame, f64),
+    /// a detected flight event, sent to every subscriber
+    Event(FlightEvent),
+}
+
+pub type Feed = broadcast::Sender<Message>;
+
+pub fn feed() -> Feed {
+    let (tx, _) = broadcast::channel(FEED_CAPACITY);
+    return tx;
+}
+
+/// one frame as sent to a subscriber, only the channels it asked for
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Sample {
+    /// vehicle time, seconds since boot
+    pub time: f32,
+    /// ground receive time, UTC seconds
+    pub rx_time: f64,
+    #[serde(flatten)]
+    pub values: BTreeMap<String, f32>,
+}
+
+/// what a single subscriber asked to receive
+pub struct Subscription {
+    pub channels: Vec<String>,
+    /// forward every nth frame
+    pub decimate: usize,
+    seen: usize,
+}
+
+impl Subscription {
+    pub fn new(channels: Vec<String>, decimate: usize) -> Subscription {
+        Subscription {
+            channels,
+            decimate: decimate.max(1),
+            seen: 0,
+        }
+    }
+
+    /// filters a frame down to this subscription
+    /// 
+    /// ## Returns
+    /// `None` if the frame is dropped by decimation
+    pub fn sample(&mut self, frame: &Frame, rx_time: f64) -> Option<Sample> {
+        self.seen += 1;
+        if (self.seen - 1) % self.decimate != 0 {
+            return None;
+        }
+
+        let mut values: BTreeMap<String, f32> = BTreeMap::new();
+        for name in self.channels.iter() {
+            if let Some(n) = frame.field(name) {
+                values.insert(name.clone(), n);
+            }
+        }
+
+        Some(Sample {time: frame.time, rx_time, values})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stream::Subscription;
+    use crate::protocol::Frame;
+
+    fn frame(time: f32) -> Frame {
+        Frame {time, alt: time * 10f32, balt: 0f32, vel: 1f32, w: 1f32, x: 0f32, y: 0f32, z: 0f32, gps: None}
+    }
+
+    #[test]
+    fn decimation() {
+        let mut sub = Subscription::new(vec!["alt".to_string()], 3);
+
+        let sent: Vec<f32> = (0..7).filter_map(|i| sub.sample(&frame(i as f32), 0f64)).map(|s| s.time).collect();
+        assert_eq!(sent, vec![0f32, 3f32, 6f32]);
+    }
+
+    #[test]
+    fn channel_filter() {
+        let mut sub = Subscription::new(vec!["alt".to_string(), "vel".to_string()], 1);
+
+        let sample = sub.sample(&frame(2f32), 5f64).unwrap();
+        assert_eq!(sample.rx_time, 5f64);
+        assert_eq!(sample.values.len(), 2);
+        assert_eq!(sample.values["alt"], 20f32);
+        assert_eq!(sample.values["vel"], 1f32);
+    }
+}
@@ -0,0 +1,297 @@
+use crate::api::TData;
+use crate::link::RadioState;
+
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// how long tasks get to wind down on shutdown before we stop waiting on them
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// a task that stays up this long has its backoff reset
+pub const HEALTHY_RUN: Duration = Duration::from_secs(30);
+
+/// exponential restart delay
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff {initial, max, current: initial}
+    }
+
+    /// the delay before the next restart, doubling each call up to `max`
+    pub fn next(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        return delay;
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        Backoff::new(Duration::from_millis(500), Duration::from_secs(30))
+    }
+}
+
+struct Task {
+    run: fn(TData),
+    /// restart the task when it fails, otherwise its exit shuts the station down
+    restart: bool,
+    backoff: Backoff,
+    handle: Option<JoinHandle<()>>,
+    started: Instant,
+    /// when a failed task is due to be started again
+    restart_at: Option<Instant>,
+}
+
+/// runs the station's threads, restarting failed ones and shutting everything down in order
+///
+/// a task fails when it panics or returns while the station is still alive.
+/// the api task is not restarted, it returns once rocket shuts down, either from
+/// `/cmd/quit` or SIGINT / SIGTERM, and that is what starts the shutdown
+pub struct Supervisor {
+    data: TData,
+    tasks: BTreeMap<&'static str, Task>,
+    tx: Sender<(&'static str, bool)>,
+    rx: Receiver<(&'static str, bool)>,
+    backoff: Backoff,
+}
+
+impl Supervisor {
+    pub fn new(data: TData, backoff: Backoff) -> Supervisor {
+        let (tx, rx) = mpsc::channel();
+        Supervisor {
+            data,
+            tasks: BTreeMap::new(),
+            tx,
+            rx,
+            backoff,
+        }
+    }
+
+    /// adds a task and starts it
+    pub fn spawn(&mut self, name: &'static str, run: fn(TData), restart: bool) {
+        let mut task = Task {
+            run,
+            restart,
+            backoff: self.backoff.clone(),
+            handle: None,
+            started: Instant::now(),
+            restart_at: None,
+        };
+        task.handle = Some(self.start(name, run));
+        self.tasks.insert(name, task);
+    }
+
+    fn start(&self, name: &'static str, run: fn(TData)) -> JoinHandle<()> {
+        let data = self.data.clone();
+        let tx = self.tx.clone();
+
+        thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let res = panic::catch_unwind(AssertUnwindSafe(|| run(data)));
+                let _ = tx.send((name, res.is_err()));
+            })
+            .expect("could not spawn thread")
+    }
+
+    fn alive(&self) -> bool {
+        let data = match self.data.lock() {
+            Ok(n) => n,
+            Err(n) => n.into_inner(),
+        };
+        data.is_alive
+    }
+
+    /// blocks until the station shuts down
+    pub fn run(&mut self) {
+        loop {
+            // wake up for the next restart that is due
+            let now = Instant::now();
+            let timeout = self.tasks.values()
+                .filter_map(|n| n.restart_at)
+                .map(|n| n.saturating_duration_since(now))
+                .min()
+                .unwrap_or(Duration::from_secs(1));
+
+            match self.rx.recv_timeout(timeout) {
+                Ok((name, panicked)) => {
+                    if self.exited(name, panicked) {
+                        break;
+                    }
+                },
+                Err(RecvTimeoutError::Timeout) => {},
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+
+            let now = Instant::now();
+            let due: Vec<&'static str> = self.tasks.iter()
+                .filter(|(_, n)| n.restart_at.map(|n| n <= now).unwrap_or(false))
+                .map(|(name, _)| *name)
+                .collect();
+
+            for name in due {
+                println!("restarting {}", name);
+                let run = self.tasks[name].run;
+                let handle = self.start(name, run);
+
+                let task = self.tasks.get_mut(name).unwrap();
+                task.handle = Some(handle);
+                task.started = Instant::now();
+                task.restart_at = None;
+            }
+        }
+
+        self.shutdown();
+    }
+
+    /// handles a task exit, returns true if the station should shut down
+    fn exited(&mut self, name: &'static str, panicked: bool) -> bool {
+        if let Some(handle) = self.tasks.get_mut(name).and_then(|n| n.handle.take()) {
+            let _ = handle.join();
+        }
+
+        // a panic while holding the lock must not take the api down with it
+        if self.data.is_poisoned() {
+            self.data.clear_poison();
+        }
+
+        let alive = self.alive();
+        let task = match self.tasks.get_mut(name) {
+            Some(n) => n,
+            None => {return false;},
+        };
+
+        if !alive || !task.restart {
+            println!("{} exited, shutting down", name);
+            return true;
+        }
+
+        if task.started.elapsed() >= HEALTHY_RUN {
+            task.backoff.reset();
+        }
+        let delay = task.backoff.next();
+        task.restart_at = Some(Instant::now() + delay);
+
+        println!("{} {}, restarting in {:?}", name, if panicked {"panicked"} else {"stopped"}, delay);
+
+        let mut data = self.data.lock().expect("could not lock mutex");
+        data.radio = RadioState::Stopped;
+        data.link.crashes += 1;
+
+        return false;
+    }
+
+    /// stops every task, waits for them to exit, then closes the session log
+    fn shutdown(&mut self) {
+        match self.data.lock() {
+            Ok(mut n) => {n.is_alive = false;},
+            Err(n) => {n.into_inner().is_alive = false;},
+        };
+
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        let mut running: usize = self.tasks.values().filter(|n| n.handle.is_some()).count();
+        while running > 0 {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.rx.recv_timeout(timeout) {
+                Ok((name, _)) => {
+                    if let Some(handle) = self.tasks.get_mut(name).and_then(|n| n.handle.take()) {
+                        let _ = handle.join();
+                        running -= 1;
+                    }
+                },
+                Err(_) => {
+                    // e.g. the radio thread is still waiting on a port to be picked
+                    println!("{} task(s) did not stop in time", running);
+                    break;
+                }
+            };
+        }
+
+        let mut data = match self.data.lock() {
+            Ok(n) => n,
+            Err(n) => n.into_inner(),
+        };
+        data.radio = RadioState::Stopped;
+        if let Some(store) = data.store.take() {
+            match store.close() {
+                Ok(path) => println!("closed session log {}", path.display()),
+                Err(n) => println!("Error closing session log | {}", n),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::{Data, TData};
+    use crate::supervisor::{Backoff, Supervisor};
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn backoff_doubles() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(350));
+        assert_eq!(backoff.next(), Duration::from_millis(100));
+        assert_eq!(backoff.next(), Duration::from_millis(200));
+        assert_eq!(backoff.next(), Duration::from_millis(350));
+        assert_eq!(backoff.next(), Duration::from_millis(350));
+
+        backoff.reset();
+        assert_eq!(backoff.next(), Duration::from_millis(100));
+    }
+
+    static STARTS: AtomicUsize = AtomicUsize::new(0);
+
+    /// panics twice, holding the lock the second time, then runs until shutdown
+    fn flaky(data: TData) {
+        match STARTS.fetch_add(1, Ordering::SeqCst) {
+            0 => panic!("first start"),
+            1 => {
+                let _data = data.lock().unwrap();
+                panic!("second start");
+            },
+            _ => {},
+        };
+
+        while data.lock().unwrap().is_alive {
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// stands in for the api, shuts the station down after a while
+    fn api(_: TData) {
+        thread::sleep(Duration::from_millis(300));
+    }
+
+    #[test]
+    fn restarts_then_shuts_down() {
+        let data: TData = Arc::new(Mutex::new(Data::new()));
+        let mut sup = Supervisor::new(Arc::clone(&data), Backoff::new(Duration::from_millis(10), Duration::from_millis(50)));
+
+        sup.spawn("flaky", flaky, true);
+        sup.spawn("api", api, false);
+        sup.run();
+
+        assert_eq!(STARTS.load(Ordering::SeqCst), 3);
+
+        let data = data.lock().expect("lock is usable after a panic");
+        assert!(!data.is_alive);
+        assert_eq!(data.link.crashes, 2);
+    }
+}
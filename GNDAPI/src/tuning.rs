@@ -0,0 +1,291 @@
+use ArmlabRadio::radio_serial::{ModulationFormat, Radio, RadioError};
+
+use rocket::serde::json::{self, Value};
+use serde::{Serialize, Deserialize};
+
+/// frequency bands the CC1200 can tune to, Hz
+pub const BANDS: [(f32, f32); 6] = [
+    (136e6, 160e6),
+    (164e6, 192e6),
+    (205e6, 240e6),
+    (273.3e6, 320e6),
+    (410e6, 480e6),
+    (820e6, 960e6),
+];
+
+/// modulation of the CC1200, see `ArmlabRadio::radio_serial::ModulationFormat`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Modulation {
+    Fsk2,
+    Gfsk2,
+    Ask,
+    Fsk4,
+    Gfsk4,
+}
+
+impl Modulation {
+    fn format(self) -> ModulationFormat {
+        match self {
+            Modulation::Fsk2 => ModulationFormat::FSK2,
+            Modulation::Gfsk2 => ModulationFormat::GFSK2,
+            Modulation::Ask => ModulationFormat::ASK,
+            Modulation::Fsk4 => ModulationFormat::FSK4,
+            Modulation::Gfsk4 => ModulationFormat::GFSK4,
+        }
+    }
+}
+
+/// everything ArmlabRadio can set on the ground radio, the `[radio.settings]` table
+///
+/// defaults match the vehicle's `Radio::setup_443()` profile, except for power
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RadioSettings {
+    /// transmit power, dBm
+    pub power: f32,
+    /// carrier, Hz
+    pub frequency: f32,
+    /// FSK deviation, Hz
+    pub deviation: f32,
+    /// symbols per second
+    pub symbol_rate: f32,
+    /// receive filter bandwidth, Hz
+    pub rx_filter: f32,
+    pub modulation: Modulation,
+}
+
+impl Default for RadioSettings {
+    fn default() -> RadioSettings {
+        RadioSettings {
+            power: 14.0,
+            frequency: 435e6,
+            deviation: 49896.0,
+            symbol_rate: 100000.0,
+            rx_filter: 208300.0,
+            modulation: Modulation::Gfsk2,
+        }
+    }
+}
+
+impl RadioSettings {
+    /// checks every setting against what the CC1200 supports, returns every problem found
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors: Vec<String> = vec![];
+
+        if !(-16.0..=14.0).contains(&self.power) {
+            errors.push(format!("power is {} dBm, the radio supports -16 to 14", self.power));
+        }
+        if !BANDS.iter().any(|(lo, hi)| (*lo..=*hi).contains(&self.frequency)) {
+            errors.push(format!("frequency {} Hz is outside every band the radio supports", self.frequency));
+        }
+        for (name, val) in [("deviation", self.deviation), ("symbol_rate", self.symbol_rate), ("rx_filter", self.rx_filter)] {
+            if !(val.is_finite() && val > 0.0) {
+                errors.push(format!("{} must be positive", name));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(errors)
+        }
+    }
+
+    /// these settings with the keys of a JSON object replaced, e.g. `{"power": 10}`
+    pub fn patch(&self, patch: &Value) -> Result<RadioSettings, String> {
+        let changes = match patch.as_object() {
+            Some(n) => n,
+            None => {return Err(String::from("expected an object of settings"))},
+        };
+
+        let mut settings = json::to_value(self).expect("settings serialize");
+        for (key, val) in changes.iter() {
+            settings[key] = val.clone();
+        }
+
+        let settings: RadioSettings = match json::from_value(settings) {
+            Ok(n) => n,
+            Err(n) => {return Err(n.to_string())},
+        };
+
+        match settings.validate() {
+            Ok(_) => Ok(settings),
+            Err(n) => Err(n.join(", ")),
+        }
+    }
+}
+
+/// a request to change the radio settings, logged when requested and again when applied or failed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RadioChange {
+    pub settings: RadioSettings,
+    /// user name and address of whoever asked for it
+    pub client: String,
+    /// UTC seconds
+    pub requested: f64,
+    /// UTC seconds the radio thread applied it, or gave up on it
+    pub applied: Option<f64>,
+    pub error: Option<String>,
+}
+
+/// radio settings as shown by `/api/radio`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Tuning {
+    /// what the radio is set to, re-applied after every reset
+    pub settings: RadioSettings,
+    /// UTC seconds the settings were last applied, none until the radio is up
+    pub applied: Option<f64>,
+    /// a change waiting for the radio thread
+    pub pending: Option<RadioChange>,
+    /// why the last change failed
+    pub error: Option<String>,
+}
+
+impl Tuning {
+    /// queues a change for the radio thread, replacing one it has not picked up yet
+    pub fn request(&mut self, settings: RadioSettings, client: &str, now: f64) -> RadioChange {
+        let change = RadioChange {
+            settings,
+            client: client.to_string(),
+            requested: now,
+            applied: None,
+            error: None,
+        };
+        self.pending = Some(change.clone());
+
+        return change;
+    }
+
+    /// records a change, or what happened to it, also used when replaying a session log
+    pub fn update(&mut self, change: RadioChange) {
+        let applied = match change.applied {
+            Some(n) => n,
+            None => {
+                self.pending = Some(change);
+                return;
+            }
+        };
+
+        match change.error.as_ref() {
+            Some(n) => {self.error = Some(n.clone());},
+            None => {
+                self.settings = change.settings.clone();
+                self.applied = Some(applied);
+                self.error = None;
+            },
+        };
+
+        if self.pending.as_ref().map(|n| n.requested == change.requested).unwrap_or(false) {
+            self.pending = None;
+        }
+    }
+}
+
+/// anything that can be tuned, implemented for the serial `Radio` and by mock radios in tests
+pub trait Tuner {
+    fn tune(&mut self, settings: &RadioSettings) -> Result<(), RadioError>;
+}
+
+impl Tuner for Radio {
+    fn tune(&mut self, settings: &RadioSettings) -> Result<(), RadioError> {
+        self.set_modulation(settings.modulation.format())?;
+        self.set_frequency(settings.frequency)?;
+        self.set_deviation(settings.deviation)?;
+        self.set_symbol_rate(settings.symbol_rate)?;
+        self.set_rx_filter(settings.rx_filter)?;
+        self.set_power(settings.power)?;
+        Ok(())
+    }
+}
+
+/// applies `next`, putting `current` back if the radio rejects part of it
+pub fn retune<T: Tuner>(radio: &mut T, current: &RadioSettings, next: &RadioSettings) -> Result<(), String> {
+    match radio.tune(next) {
+        Ok(_) => Ok(()),
+        Err(n) => {
+            let restored = match radio.tune(current) {
+                Ok(_) => "previous settings restored",
+                Err(_) => "could not restore the previous settings",
+            };
+            Err(format!("{:?}, {}", n, restored))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tuning::{retune, RadioSettings, Tuner, Tuning};
+
+    use ArmlabRadio::radio_serial::RadioError;
+    use rocket::serde::json::json;
+
+    /// rejects any power above `max_power`
+    struct MockTuner {
+        max_power: f32,
+        tuned: Vec<RadioSettings>,
+    }
+
+    impl Tuner for MockTuner {
+        fn tune(&mut self, settings: &RadioSettings) -> Result<(), RadioError> {
+            if settings.power > self.max_power {
+                return Err(RadioError::WriteError);
+            }
+            self.tuned.push(settings.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn patch() {
+        let settings = RadioSettings::default();
+        assert!(settings.validate().is_ok());
+
+        let patched = settings.patch(&json!({"power": 10, "modulation": "fsk4"})).unwrap();
+        assert_eq!(patched.power, 10.0);
+        assert_eq!(patched.frequency, settings.frequency);
+
+        assert!(settings.patch(&json!({"power": 20})).unwrap_err().contains("power"));
+        assert!(settings.patch(&json!({"frequency": 600e6})).unwrap_err().contains("band"));
+        assert!(settings.patch(&json!({"gain": 3})).unwrap_err().contains("gain"));
+        assert!(settings.patch(&json!({"modulation": "ook"})).is_err());
+        assert!(settings.patch(&json!(10)).is_err());
+    }
+
+    #[test]
+    fn pending_then_applied() {
+        let mut tuning = Tuning::default();
+
+        let mut change = tuning.request(RadioSettings {power: 5.0, ..RadioSettings::default()}, "rso", 10.0);
+        assert_eq!(tuning.pending, Some(change.clone()));
+        assert_eq!(tuning.settings.power, 14.0);
+
+        change.applied = Some(11.0);
+        tuning.update(change);
+        assert!(tuning.pending.is_none());
+        assert_eq!(tuning.settings.power, 5.0);
+        assert_eq!(tuning.applied, Some(11.0));
+
+        let mut change = tuning.request(RadioSettings {power: 1.0, ..RadioSettings::default()}, "rso", 12.0);
+        change.applied = Some(13.0);
+        change.error = Some(String::from("WriteError"));
+        tuning.update(change);
+        assert_eq!(tuning.settings.power, 5.0);
+        assert_eq!(tuning.error.as_deref(), Some("WriteError"));
+    }
+
+    #[test]
+    fn restores_on_failure() {
+        let current = RadioSettings::default();
+        let mut radio = MockTuner {max_power: 10.0, tuned: vec![]};
+
+        let next = RadioSettings {power: 5.0, ..current.clone()};
+        assert!(retune(&mut radio, &current, &next).is_ok());
+
+        let current = next;
+        let next = RadioSettings {power: 12.0, ..current.clone()};
+        assert!(retune(&mut radio, &current, &next).unwrap_err().contains("restored"));
+        assert_eq!(radio.tuned.last(), Some(&current));
+    }
+}
@@ -0,0 +1,224 @@
+use serde::{Serialize, Deserialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// default of `vehicle.model`, where an uploaded model is kept
+pub const MODEL_FILE: &str = "vehicle/model.glb";
+
+/// largest model `/api/vehicle/model` accepts, bytes
+pub const MAX_MODEL_SIZE: u64 = 32 * 1024 * 1024;
+
+/// rotation from the IMU frame to the model, degrees, applied yaw (z) then pitch (y) then roll (x)
+///
+/// the default lines the BNO055 up with the stock `model.glb`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Mounting {
+    pub roll: f64,
+    pub pitch: f64,
+    pub yaw: f64,
+}
+
+impl Default for Mounting {
+    fn default() -> Mounting {
+        Mounting {
+            roll: 0.0,
+            pitch: 0.0,
+            yaw: -90.0,
+        }
+    }
+}
+
+impl Mounting {
+    /// the rotation as a `[w, x, y, z]` quaternion
+    pub fn quaternion(&self) -> [f64; 4] {
+        let (sr, cr) = (self.roll.to_radians() / 2.0).sin_cos();
+        let (sp, cp) = (self.pitch.to_radians() / 2.0).sin_cos();
+        let (sy, cy) = (self.yaw.to_radians() / 2.0).sin_cos();
+
+        [
+            cr * cp * cy + sr * sp * sy,
+            sr * cp * cy - cr * sp * sy,
+            cr * sp * cy + sr * cp * sy,
+            cr * cp * sy - sr * sp * cy,
+        ]
+    }
+}
+
+/// the `[vehicle]` table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VehicleConfig {
+    pub name: Option<String>,
+    /// glTF binary shown by the attitude view, the stock model is used while it does not exist
+    pub model: PathBuf,
+    pub mounting: Mounting,
+    /// the IMU reports the world in the sensor frame, so its quaternion is conjugated first
+    pub invert: bool,
+}
+
+impl Default for VehicleConfig {
+    fn default() -> VehicleConfig {
+        VehicleConfig {
+            name: None,
+            model: PathBuf::from(MODEL_FILE),
+            mounting: Mounting::default(),
+            invert: true,
+        }
+    }
+}
+
+impl VehicleConfig {
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors: Vec<String> = vec![];
+
+        if self.model.as_os_str().is_empty() {
+            errors.push(String::from("model is empty"));
+        }
+        let Mounting {roll, pitch, yaw} = self.mounting;
+        if ![roll, pitch, yaw].iter().all(|n| n.is_finite()) {
+            errors.push(String::from("mounting angles must be numbers"));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(errors)
+        }
+    }
+
+    /// the configured model, none until one exists
+    pub fn load_model(&self) -> Option<Vec<u8>> {
+        fs::read(&self.model).ok()
+    }
+}
+
+/// how the attitude view draws the vehicle, served by `/api/vehicle`
+#[derive(Debug, Clone, Serialize)]
+pub struct VehicleView {
+    pub name: Option<String>,
+    /// url of the model to load
+    pub model: &'static str,
+    /// false while the stock model is served
+    pub custom_model: bool,
+    pub mounting: Mounting,
+    /// `mounting` as `[w, x, y, z]`, the view shows `mounting * attitude`
+    pub quaternion: [f64; 4],
+    pub invert: bool,
+}
+
+impl VehicleView {
+    pub fn new(config: &VehicleConfig) -> VehicleView {
+        VehicleView {
+            name: config.name.clone(),
+            model: "/api/vehicle/model",
+            custom_model: config.model.is_file(),
+            mounting: config.mounting,
+            quaternion: config.mounting.quaternion(),
+            invert: config.invert,
+        }
+    }
+}
+
+/// checks the header of a glTF binary, see https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html#binary-gltf-layout
+pub fn check_model(bytes: &[u8]) -> Result<(), String> {
+    if bytes.len() < 12 || &bytes[0..4] != b"glTF" {
+        return Err(String::from("not a glTF binary (.glb) file"));
+    }
+
+    let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    if version != 2 {
+        return Err(format!("glTF version {} is not supported, expected 2", version));
+    }
+
+    let length = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize;
+    if length != bytes.len() {
+        return Err(format!("file is {} bytes but its header says {}, it may be truncated", bytes.len(), length));
+    }
+
+    Ok(())
+}
+
+/// replaces the model at `path`, written beside it first so a failed upload never leaves half a file
+pub fn save_model(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    check_model(bytes)?;
+
+    if let Some(dir) = path.parent().filter(|n| !n.as_os_str().is_empty()) {
+        if let Err(n) = fs::create_dir_all(dir) {
+            return Err(format!("could not create {}: {}", dir.display(), n));
+        }
+    }
+
+    let partial = path.with_extension("glb.part");
+    let res = fs::write(&partial, bytes).and_then(|_| fs::rename(&partial, path));
+    match res {
+        Ok(_) => Ok(()),
+        Err(n) => {
+            let _ = fs::remove_file(&partial);
+            Err(format!("could not write {}: {}", path.display(), n))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vehicle::{check_model, save_model, Mounting, VehicleConfig, VehicleView};
+
+    use std::env;
+    use std::fs;
+
+    /// an empty glTF binary, header only
+    fn glb() -> Vec<u8> {
+        let mut bytes = b"glTF".to_vec();
+        bytes.extend(2u32.to_le_bytes());
+        bytes.extend(12u32.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn mounting() {
+        // -90 degrees about z, what the view used to hard code
+        let q = Mounting::default().quaternion();
+        let half = std::f64::consts::FRAC_1_SQRT_2;
+        for (a, b) in q.iter().zip([half, 0.0, 0.0, -half].iter()) {
+            assert!((a - b).abs() < 1e-12);
+        }
+
+        let q = Mounting {roll: 0.0, pitch: 0.0, yaw: 0.0}.quaternion();
+        assert_eq!(q, [1.0, 0.0, 0.0, 0.0]);
+
+        // a quarter turn of roll is about x
+        let q = Mounting {roll: 90.0, pitch: 0.0, yaw: 0.0}.quaternion();
+        assert!((q[1] - half).abs() < 1e-12 && q[2].abs() < 1e-12);
+
+        let bad = VehicleConfig {mounting: Mounting {roll: f64::NAN, ..Mounting::default()}, ..VehicleConfig::default()};
+        assert!(bad.validate().is_err());
+    }
+
+    #[test]
+    fn models() {
+        assert!(check_model(&glb()).is_ok());
+        assert!(check_model(b"{\"asset\": {}}").unwrap_err().contains(".glb"));
+        assert!(check_model(&glb()[..11]).is_err());
+
+        let mut truncated = glb();
+        truncated[8] = 40;
+        assert!(check_model(&truncated).unwrap_err().contains("truncated"));
+
+        let dir = env::temp_dir().join(format!("gndapi-vehicle-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let config = VehicleConfig {model: dir.join("rocket.glb"), ..VehicleConfig::default()};
+        assert!(!VehicleView::new(&config).custom_model);
+        assert!(config.load_model().is_none());
+
+        save_model(&config.model, &glb()).unwrap();
+        assert_eq!(config.load_model(), Some(glb()));
+        assert!(VehicleView::new(&config).custom_model);
+
+        assert!(save_model(&config.model, b"nope").is_err());
+        assert_eq!(config.load_model(), Some(glb()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
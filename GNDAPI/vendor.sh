@@ -0,0 +1,17 @@
+#!/bin/sh
+# downloads the third party ui assets into public/vendor, they are embedded in the
+# binary at build time so the station ui never needs a network connection.
+# not part of the build, run it with internet access to update THREE and commit the files
+set -e
+
+THREE=0.130.0
+CDN=https://cdn.jsdelivr.net/npm/three@$THREE
+DIR=$(dirname "$0")/public/vendor/three
+
+mkdir -p "$DIR"
+curl -fsSL "$CDN/build/three.min.js" -o "$DIR/three.min.js"
+curl -fsSL "$CDN/examples/js/controls/OrbitControls.js" -o "$DIR/OrbitControls.js"
+curl -fsSL "$CDN/examples/js/loaders/GLTFLoader.js" -o "$DIR/GLTFLoader.js"
+curl -fsSL "$CDN/LICENSE" -o "$DIR/LICENSE"
+
+echo "three.js $THREE vendored into $DIR"